@@ -0,0 +1,44 @@
+//! Steps a 512x512 field with and without the halo and prints how long each
+//! takes. Run with `cargo bench --bench halo`.
+
+use std::time::{Duration, Instant};
+
+use kontawa::simulation::{EdgeMode, WaveField, WaveParams};
+
+const SIZE: usize = 512;
+const STEPS: u32 = 300;
+
+fn time_steps(halo: bool) -> Duration {
+    let params = WaveParams {
+        edge_mode: EdgeMode::Reflect,
+        ..WaveParams::default()
+    };
+    let mut field = WaveField::new(SIZE, SIZE, params);
+    field.halo = halo;
+    field
+        .pressures
+        .stamp_gaussian(SIZE as f32 / 2.0, SIZE as f32 / 2.0, 8.0, 1.0);
+    // Warm up the thread pool before timing anything.
+    for _ in 0..10 {
+        field.step();
+    }
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        field.step();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let edge_reads = time_steps(false);
+    let halo = time_steps(true);
+    let per_step = |total: Duration| total.as_secs_f64() * 1e3 / STEPS as f64;
+    println!("{SIZE}x{SIZE}, {STEPS} steps");
+    println!("edge mode reads: {:.3} ms/step", per_step(edge_reads));
+    println!("halo:            {:.3} ms/step", per_step(halo));
+    println!(
+        "speedup:         {:.2}x",
+        edge_reads.as_secs_f64() / halo.as_secs_f64()
+    );
+}
@@ -1,120 +1,1424 @@
-use std::sync::{atomic::AtomicUsize, atomic::Ordering, Mutex, MutexGuard, TryLockError};
+use std::sync::{atomic::Ordering, Mutex};
 
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use audio_processor_analysis::fft_processor::FftProcessorOptions;
-use audio_processor_traits::simple_processor::MonoAudioProcessor;
+use audio_processor_analysis::fft_processor::{FftProcessor, FftProcessorOptions};
+use audio_processor_analysis::window_functions::{make_window_vec, WindowFunctionType};
+use triple_buffer::{Input, Output};
 
-pub struct DoubleBuffer<T> {
-    idx: AtomicUsize,
-    bufs: [Mutex<T>; 2],
+use crate::wav::Wav;
+use crate::SimParams;
+
+/// How samples are cut into FFT frames. The audio thread picks changes up
+/// from the params as it goes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FftConfig {
+    /// Samples per FFT frame, and so bins per published spectrum.
+    pub size: usize,
+    /// Fraction of each frame shared with the next, in `[0, 1)`.
+    pub overlap: f32,
+}
+impl FftConfig {
+    /// Frame sizes offered in the GUI.
+    pub const SIZES: [usize; 4] = [256, 512, 1024, 2048];
+}
+impl Default for FftConfig {
+    fn default() -> Self {
+        FftConfig {
+            size: 512,
+            overlap: 0.75,
+        }
+    }
 }
-impl<T> DoubleBuffer<T> {
-    pub fn new([front, back]: [T; 2]) -> DoubleBuffer<T> {
-        let idx = AtomicUsize::new(0);
-        DoubleBuffer {
-            idx,
-            bufs: [Mutex::new(front), Mutex::new(back)],
+
+/// How FFT bin magnitudes are turned into the published spectrum.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MagnitudeTransform {
+    Linear,
+    /// Natural log, with magnitudes below `floor` raised to it first.
+    Log {
+        floor: f32,
+    },
+    /// Decibels above `floor_db` (relative to a magnitude of 1), so silence
+    /// is zero and everything louder is positive.
+    Db {
+        floor_db: f32,
+    },
+    Sqrt,
+}
+impl MagnitudeTransform {
+    /// `Db`'s floor unless configured otherwise.
+    pub const DEFAULT_DB_FLOOR: f32 = -100.0;
+
+    pub fn apply(self, magnitude: f32) -> f32 {
+        match self {
+            MagnitudeTransform::Linear => magnitude,
+            MagnitudeTransform::Log { floor } => magnitude.max(floor).ln(),
+            MagnitudeTransform::Db { floor_db } => {
+                // Written so NaN (and silence's -inf) land on the floor.
+                let db = 20.0 * magnitude.log10();
+                if db > floor_db {
+                    db - floor_db
+                } else {
+                    0.0
+                }
+            }
+            MagnitudeTransform::Sqrt => magnitude.sqrt(),
         }
     }
-    pub fn front(&self) -> MutexGuard<'_, T> {
-        self.get(self.idx.load(Ordering::SeqCst))
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MagnitudeTransform::Linear => "Linear",
+            MagnitudeTransform::Log { .. } => "Log",
+            MagnitudeTransform::Db { .. } => "Db",
+            MagnitudeTransform::Sqrt => "Sqrt",
+        }
     }
-    pub fn back(&self) -> MutexGuard<'_, T> {
-        self.get(1 ^ self.idx.load(Ordering::SeqCst))
+}
+impl Default for MagnitudeTransform {
+    fn default() -> Self {
+        MagnitudeTransform::Log { floor: 1e-9 }
     }
-    pub fn flip(&self) {
-        self.idx.fetch_xor(1, Ordering::SeqCst);
+}
+
+/// The producer's end of a triple buffer: three slots, one for each side
+/// and one in between. The producer fills in its own slot and swaps it into
+/// the middle with a single atomic exchange, so publishing never waits on
+/// the consumer, or on anything else.
+pub struct Publisher<T: Send>(Input<T>);
+impl<T: Send> Publisher<T> {
+    /// Fill in the next value and make it the latest. `write` gets whatever
+    /// the slot held from a couple of publishes ago, not a blank one.
+    pub fn publish(&mut self, write: impl FnOnce(&mut T)) {
+        write(self.0.input_buffer_mut());
+        self.0.publish();
     }
-    fn get(&self, init_idx: usize) -> MutexGuard<'_, T> {
-        let mut idx = init_idx;
+}
 
-        loop {
-            let mew = &self.bufs[idx];
-            match mew.try_lock() {
-                Ok(x) => {
-                    return x;
+/// The consumer's end of a triple buffer.
+pub struct Subscriber<T: Send>(Output<T>);
+impl<T: Send> Subscriber<T> {
+    /// Pick up the most recently published value, if there's been one since
+    /// last time, and say whether there was. Otherwise the front stays as it
+    /// is.
+    pub fn flip(&mut self) -> bool {
+        self.0.update()
+    }
+
+    /// The value picked up by the last `flip`.
+    pub fn front(&self) -> &T {
+        self.0.peek_output_buffer()
+    }
+}
+
+/// A new triple buffer whose front starts out as `initial`.
+pub fn triple_buffer<T: Clone + Send>(initial: &T) -> (Publisher<T>, Subscriber<T>) {
+    let (input, output) = triple_buffer::triple_buffer(initial);
+    (Publisher(input), Subscriber(output))
+}
+
+/// Named channels from the audio thread to the main thread, so a new kind
+/// of data can cross over without threading another end through every
+/// constructor. Only the subscribers live here, and only the main thread
+/// takes the lock; each publisher belongs to whichever stream feeds it.
+#[derive(Default)]
+pub struct Channels {
+    subscribers: Mutex<HashMap<&'static str, Subscriber<Vec<f32>>>>,
+}
+impl Channels {
+    /// The audio spectrum, one value per FFT bin.
+    pub const SPECTRUM: &'static str = "spectrum";
+    /// The RMS level of the latest block of input, as a single value.
+    pub const LEVEL: &'static str = "level";
+
+    pub fn new() -> Channels {
+        Channels::default()
+    }
+
+    /// A publisher for `name`, cutting off whichever one fed it before.
+    /// Readers keep seeing the last value until the new one publishes.
+    pub fn publisher(&self, name: &'static str) -> Publisher<Vec<f32>> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let last = subscribers
+            .get(name)
+            .map(|subscriber| subscriber.front().clone())
+            .unwrap_or_default();
+        let (publisher, subscriber) = triple_buffer(&last);
+        subscribers.insert(name, subscriber);
+        publisher
+    }
+
+    /// Run `read` on the subscriber for `name`, which reads as empty until
+    /// something publishes there.
+    pub fn subscriber<R>(
+        &self,
+        name: &'static str,
+        read: impl FnOnce(&mut Subscriber<Vec<f32>>) -> R,
+    ) -> R {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        read(
+            subscribers
+                .entry(name)
+                .or_insert_with(|| triple_buffer(&Vec::new()).1),
+        )
+    }
+}
+
+/// Exponential backoff between attempts to bring a dead stream back.
+pub struct Backoff {
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+impl Backoff {
+    const BASE: Duration = Duration::from_millis(500);
+    const MAX: Duration = Duration::from_secs(30);
+
+    pub fn new() -> Backoff {
+        Backoff {
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+    /// Whether enough time has passed to try again.
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|t| now >= t)
+    }
+    /// Record a failure, pushing the next attempt further out.
+    pub fn failed(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        self.next_attempt = Some(now + self.delay());
+    }
+    /// Record a success, resetting the delay.
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+        self.next_attempt = None;
+    }
+    /// How long to wait after the current run of failures.
+    pub fn delay(&self) -> Duration {
+        let shift = self.failures.saturating_sub(1).min(16);
+        (Self::BASE * (1 << shift)).min(Self::MAX)
+    }
+}
+
+/// Which input device to capture from.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum DevicePref {
+    /// Whatever the host says the default input is.
+    #[default]
+    Default,
+    /// The device with this name, if there is one.
+    ByName(String),
+}
+
+/// The device from `devices` (name and device pairs) that `pref` asks for,
+/// or `None` for the default.
+pub fn pick_device<D>(
+    devices: impl IntoIterator<Item = (String, D)>,
+    pref: &DevicePref,
+) -> Option<D> {
+    let DevicePref::ByName(wanted) = pref else {
+        return None;
+    };
+    let device = devices
+        .into_iter()
+        .find(|(name, _)| name == wanted)
+        .map(|(_, device)| device);
+    if device.is_none() {
+        log::warn!("no input device named '{wanted}', using the default");
+    }
+    device
+}
+
+/// Names of every input device the host knows about.
+pub fn list_input_devices() -> Vec<String> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+    match cpal::default_host().input_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            log::warn!("couldn't list input devices: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Settings fixed for the life of an input stream; changing them means
+/// restarting it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioSettings {
+    /// Sample rate to ask the device for, or the closest it can do.
+    pub sample_rate: u32,
+}
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            sample_rate: 48_000,
+        }
+    }
+}
+
+/// Where the audio comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioSource {
+    /// Live capture from an input device.
+    Microphone,
+    /// A recording, played through once in real time.
+    WavFile(PathBuf),
+}
+
+/// A running source; dropping it stops the audio.
+enum Capture {
+    Live { _stream: cpal::Stream },
+    File { _playback: WavPlayback },
+}
+
+/// Streams a decoded file into the spectrum pipeline from its own thread,
+/// paced to the file's sample rate.
+struct WavPlayback {
+    stop: Arc<AtomicBool>,
+}
+impl WavPlayback {
+    /// How much audio is handed over at a time.
+    const CHUNK: Duration = Duration::from_millis(10);
+
+    fn start(wav: Wav, mut process: impl FnMut(&[f32]) + Send + 'static) -> WavPlayback {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        std::thread::spawn(move || {
+            let chunk = ((wav.sample_rate as f32 * Self::CHUNK.as_secs_f32()) as usize).max(1);
+            let start = Instant::now();
+            for (n, samples) in wav.samples.chunks(chunk).enumerate() {
+                if stopped.load(Ordering::SeqCst) {
+                    return;
                 }
-                Err(TryLockError::WouldBlock) => {
-                    // try the other buffer then
-                    idx ^= 1;
+                process(samples);
+
+                let due = start + Self::CHUNK * (n as u32 + 1);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    std::thread::sleep(wait);
                 }
-                Err(TryLockError::Poisoned(e)) => {
-                    panic!("{e:?}")
+            }
+            log::info!("finished playing the WAV file");
+        });
+        WavPlayback { stop }
+    }
+}
+impl Drop for WavPlayback {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// An audio source that restarts itself if the device goes away.
+pub struct AudioInput {
+    channels: Arc<Channels>,
+    failed: Arc<AtomicBool>,
+    source: AudioSource,
+    stream: Option<Capture>,
+    backoff: Backoff,
+    settings: AudioSettings,
+    params: Arc<Mutex<SimParams>>,
+    /// The device asked for when the stream was last started.
+    device: DevicePref,
+}
+impl AudioInput {
+    /// Start publishing to `channels`' spectrum and level.
+    pub fn start(
+        channels: Arc<Channels>,
+        source: AudioSource,
+        settings: AudioSettings,
+        params: Arc<Mutex<SimParams>>,
+    ) -> AudioInput {
+        let mut input = AudioInput {
+            channels,
+            failed: Arc::new(AtomicBool::new(false)),
+            source,
+            stream: None,
+            backoff: Backoff::new(),
+            settings,
+            params,
+            device: DevicePref::Default,
+        };
+        input.restart(Instant::now());
+        input
+    }
+
+    /// Check on the stream, rebuilding it if it has errored and the backoff
+    /// allows. Call this regularly from the main loop.
+    pub fn poll(&mut self) {
+        if self.failed.swap(false, Ordering::SeqCst) {
+            log::warn!("audio stream errored, dropping it");
+            self.stream = None;
+            self.backoff.failed(Instant::now());
+        }
+
+        let device = self.params.lock().unwrap().input_device.clone();
+        if self.source == AudioSource::Microphone && device != self.device {
+            log::info!("switching input device to {device:?}");
+            self.stream = None;
+            self.backoff.succeeded();
+        }
+
+        let now = Instant::now();
+        if self.stream.is_none() && self.backoff.ready(now) {
+            self.restart(now);
+        }
+    }
+
+    fn restart(&mut self, now: Instant) {
+        log::info!("starting audio stream");
+        self.device = self.params.lock().unwrap().input_device.clone();
+        let capture = match &self.source {
+            AudioSource::Microphone => do_audio(
+                self.channels.publisher(Channels::SPECTRUM),
+                self.channels.publisher(Channels::LEVEL),
+                self.failed.clone(),
+                self.settings,
+                &self.device,
+                self.params.clone(),
+            )
+            .map(|stream| Capture::Live { _stream: stream }),
+            AudioSource::WavFile(path) => Wav::load(path).map(|wav| {
+                let process = spectrum_processor(
+                    self.channels.publisher(Channels::SPECTRUM),
+                    self.channels.publisher(Channels::LEVEL),
+                    self.params.clone(),
+                );
+                Capture::File {
+                    _playback: WavPlayback::start(wav, process),
                 }
+            }),
+        };
+        match capture {
+            Ok(capture) => {
+                self.stream = Some(capture);
+                self.backoff.succeeded();
+            }
+            Err(e) => {
+                self.backoff.failed(now);
+                log::warn!(
+                    "couldn't start audio stream: {e} (retrying in {:?})",
+                    self.backoff.delay()
+                );
             }
         }
     }
 }
 
-pub fn do_audio(dbuf: Arc<DoubleBuffer<Vec<f32>>>) -> cpal::Stream {
+/// A fixed-capacity FIFO of samples, so a burst of input can never grow
+/// memory without bound.
+pub struct AudioRing {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+impl AudioRing {
+    pub fn with_capacity(capacity: usize) -> AudioRing {
+        AudioRing {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append as many of `samples` as there's room for, returning how many.
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let n = samples.len().min(self.capacity - self.samples.len());
+        self.samples.extend(&samples[..n]);
+        n
+    }
+
+    /// Copy the oldest `out.len()` samples into `out`, leaving them queued.
+    /// False, touching nothing, if there aren't that many.
+    pub fn peek(&self, out: &mut [f32]) -> bool {
+        if self.samples.len() < out.len() {
+            return false;
+        }
+        for (o, &s) in out.iter_mut().zip(&self.samples) {
+            *o = s;
+        }
+        true
+    }
+
+    /// Drop the oldest `n` samples.
+    pub fn discard(&mut self, n: usize) {
+        self.samples.drain(..n.min(self.samples.len()));
+    }
+
+    /// How many samples are queued.
+    pub fn queued(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Take the oldest sample, if there is one.
+    pub fn pop(&mut self) -> Option<f32> {
+        self.samples.pop_front()
+    }
+}
+
+/// Cuts a stream of samples into windows of `size`, starting a new one every
+/// `hop` samples so consecutive windows overlap.
+pub struct Framer {
+    hop: usize,
+    pending: AudioRing,
+    window: Vec<f32>,
+}
+impl Framer {
+    /// `overlap` is the fraction of each window shared with the next, in
+    /// `[0, 1)`.
+    pub fn new(size: usize, overlap: f32) -> Framer {
+        let hop = (size as f32 * (1.0 - overlap.clamp(0.0, 1.0))) as usize;
+        Framer {
+            hop: hop.clamp(1, size),
+            pending: AudioRing::with_capacity(size * 4),
+            window: vec![0.0; size],
+        }
+    }
+
+    /// Append `samples`, calling `emit` with each window completed by them.
+    /// Whatever the next window still needs is kept for the next call.
+    pub fn push(&mut self, mut samples: &[f32], mut emit: impl FnMut(&[f32])) {
+        // Feed the ring a bit at a time so it never has to drop anything:
+        // every full window is emitted, making room, before taking more.
+        loop {
+            let taken = self.pending.push(samples);
+            samples = &samples[taken..];
+            while self.pending.peek(&mut self.window) {
+                emit(&self.window);
+                self.pending.discard(self.hop);
+            }
+            if samples.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+/// Sample formats `do_audio` can read.
+const SUPPORTED_FORMATS: [cpal::SampleFormat; 3] = [
+    cpal::SampleFormat::F32,
+    cpal::SampleFormat::I16,
+    cpal::SampleFormat::U16,
+];
+
+/// Pick the config to open from what the device supports: one in a format
+/// from `formats`, preferring f32 since that's what we deal in directly,
+/// then the sample rate closest to `target_rate`. `None` if no config is in
+/// a usable format.
+pub fn best_config(
+    configs: impl IntoIterator<Item = cpal::SupportedStreamConfigRange>,
+    formats: &[cpal::SampleFormat],
+    target_rate: u32,
+) -> Option<cpal::SupportedStreamConfig> {
+    configs
+        .into_iter()
+        .filter(|range| formats.contains(&range.sample_format()))
+        .map(|range| {
+            let rate = target_rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            (range, rate)
+        })
+        .min_by_key(|(range, rate)| {
+            (
+                range.sample_format() != cpal::SampleFormat::F32,
+                rate.abs_diff(target_rate),
+            )
+        })
+        .map(|(range, rate)| range.with_sample_rate(cpal::SampleRate(rate)))
+}
+
+pub fn do_audio(
+    spectra: Publisher<Vec<f32>>,
+    levels: Publisher<Vec<f32>>,
+    failed: Arc<AtomicBool>,
+    settings: AudioSettings,
+    device_pref: &DevicePref,
+    params: Arc<Mutex<SimParams>>,
+) -> Result<cpal::Stream, String> {
     use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-    use cpal::{Data, FromSample, Sample, SampleFormat};
+    use cpal::SampleFormat;
     let host = cpal::default_host();
 
-    let device = host
-        .default_input_device()
-        .expect("no output device available");
+    let named = host
+        .input_devices()
+        .map_err(|e| format!("error while listing devices: {e}"))?
+        .filter_map(|d| Some((d.name().ok()?, d)));
+    let device = match pick_device(named, device_pref) {
+        Some(device) => device,
+        None => host
+            .default_input_device()
+            .ok_or("no input device available")?,
+    };
 
-    let mut supported_configs_range = device
+    let supported_configs = device
         .supported_input_configs()
-        .expect("error while querying configs");
-    let supported_config = supported_configs_range
-        .next()
-        .expect("no supported config?!")
-        .with_max_sample_rate();
+        .map_err(|e| format!("error while querying configs: {e}"))?;
+    let supported_config = best_config(supported_configs, &SUPPORTED_FORMATS, settings.sample_rate)
+        .ok_or("device reports no usable input configs")?;
+    log::info!("using input config {supported_config:?}");
 
-    let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
+    let err_fn = move |err| {
+        eprintln!("an error occurred on the input audio stream: {}", err);
+        failed.store(true, Ordering::SeqCst);
+    };
     let sample_format = supported_config.sample_format();
     let config = supported_config.into();
-   
-    use audio_processor_analysis::fft_processor::FftProcessor;
-    use audio_processor_traits::*;
 
-    let settings = AudioProcessorSettings::default();
-    let mut context = AudioContext::from(settings);
+    let process = spectrum_processor(spectra, levels, params);
+
+    let stream = match sample_format {
+        SampleFormat::F32 => build_input_stream::<f32>(&device, &config, process, err_fn),
+        SampleFormat::I16 => build_input_stream::<i16>(&device, &config, process, err_fn),
+        SampleFormat::U16 => build_input_stream::<u16>(&device, &config, process, err_fn),
+        sample_format => return Err(format!("Unsupported sample format '{sample_format}'")),
+    }
+    .map_err(|e| format!("couldn't build input stream: {e}"))?;
+
+    stream
+        .play()
+        .map_err(|e| format!("couldn't start input stream: {e}"))?;
+
+    Ok(stream)
+}
 
-    let mut fft_processor = FftProcessor::new(FftProcessorOptions {
-        size: 512,
-        overlap_ratio: 0.75,
-        ..Default::default()
-    });
-    fft_processor.m_prepare(&mut context);
+/// An audio sample for a pressure: scaled by `gain` and clipped to the
+/// output range, with anything non-finite as silence.
+pub fn pressure_to_sample(pressure: f32, gain: f32) -> f32 {
+    let sample = pressure * gain;
+    if sample.is_finite() {
+        sample.clamp(-1.0, 1.0)
+    } else {
+        0.0
+    }
+}
 
-    let mut buffer: AudioBuffer<f32> = AudioBuffer::empty();
-    buffer.resize(1, fft_processor.size());
+/// Converts a stream from one sample rate to another by linear
+/// interpolation, carrying its position over from one block to the next.
+pub struct Resampler {
+    /// Input samples per output sample.
+    step: f64,
+    /// Where the next output sample falls, in input samples after `prev`.
+    pos: f64,
+    /// The last input sample of the previous block; before the first block,
+    /// the stream is taken to have been at its first sample all along.
+    prev: Option<f32>,
+}
+impl Resampler {
+    pub fn new(from_rate: f32, to_rate: f32) -> Resampler {
+        let mut resampler = Resampler {
+            step: 1.0,
+            pos: 0.0,
+            prev: None,
+        };
+        resampler.set_rates(from_rate, to_rate);
+        resampler
+    }
 
-    let chunk_size = fft_processor.size();
+    /// Change rates without restarting the stream.
+    pub fn set_rates(&mut self, from_rate: f32, to_rate: f32) {
+        self.step = from_rate as f64 / to_rate as f64;
+    }
 
-    // fixme: use a ring buffer here (lol)
-    let mut accum_buffer = Vec::<f32>::with_capacity(fft_processor.size());
-    let write_silence = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-        accum_buffer.extend(data.iter().cloned());
-        
-        let mut chunks = accum_buffer.chunks_exact(chunk_size);
-        for chunk in chunks {
-            buffer.copy_from_interleaved(chunk);
-            simple_processor::process_buffer(&mut context, &mut fft_processor, &mut buffer);
+    /// Resample `input`, which carries straight on from the last block,
+    /// appending the result to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if !(self.step.is_finite() && self.step > 0.0) {
+            return;
+        }
+        let Some(&first) = input.first() else {
+            return;
+        };
+        let prev = self.prev.unwrap_or(first);
+        let at = |i: usize| if i == 0 { prev } else { input[i - 1] };
+        while self.pos < input.len() as f64 {
+            let i = self.pos as usize;
+            let t = (self.pos - i as f64) as f32;
+            out.push(at(i) + (at(i + 1) - at(i)) * t);
+            self.pos += self.step;
         }
+        self.pos -= input.len() as f64;
+        self.prev = input.last().copied();
+    }
+}
 
-        let fft_buf = fft_processor.buffer();
+/// Seconds of sound queued for the output stream at most, which bounds how
+/// far behind the simulation it can fall.
+const OUTPUT_BUFFER_SECONDS: f32 = 0.25;
 
-        let mut out_buf = dbuf.back();
-        out_buf.clear();
-        out_buf.extend(fft_buf
-            .iter()
-            .map(|complex| complex.norm().ln()));
+/// Sample formats `do_audio_output` can write.
+const OUTPUT_FORMATS: [cpal::SampleFormat; 3] = [
+    cpal::SampleFormat::F32,
+    cpal::SampleFormat::I16,
+    cpal::SampleFormat::U16,
+];
+
+/// Play whatever's queued in `ring` on the default output device, the same
+/// sample on every channel, and silence whenever it runs dry. Returns the
+/// stream and the sample rate it settled on.
+pub fn do_audio_output(
+    ring: Arc<Mutex<AudioRing>>,
+    failed: Arc<AtomicBool>,
+    settings: AudioSettings,
+) -> Result<(cpal::Stream, u32), String> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::SampleFormat;
+    let host = cpal::default_host();
+    let device = host
+        .default_output_device()
+        .ok_or("no output device available")?;
+
+    let supported_configs = device
+        .supported_output_configs()
+        .map_err(|e| format!("error while querying configs: {e}"))?;
+    let supported_config = best_config(supported_configs, &OUTPUT_FORMATS, settings.sample_rate)
+        .ok_or("device reports no usable output configs")?;
+    log::info!("using output config {supported_config:?}");
+
+    let err_fn = move |err| {
+        eprintln!("an error occurred on the output audio stream: {}", err);
+        failed.store(true, Ordering::SeqCst);
     };
+    let sample_format = supported_config.sample_format();
+    let sample_rate = supported_config.sample_rate().0;
+    let config = supported_config.into();
 
     let stream = match sample_format {
-        SampleFormat::F32 => device.build_input_stream(&config, write_silence, err_fn, None),
-        //ampleFormat::I16 => device.build_input_stream(&config, write_silence::<i16>, err_fn, None),
-        //SampleFormat::U16 => device.build_input_stream(&config, write_silence::<u16>, err_fn, None),
-        sample_format => panic!("Unsupported sample format '{sample_format}'"),
+        SampleFormat::F32 => build_output_stream::<f32>(&device, &config, ring, err_fn),
+        SampleFormat::I16 => build_output_stream::<i16>(&device, &config, ring, err_fn),
+        SampleFormat::U16 => build_output_stream::<u16>(&device, &config, ring, err_fn),
+        sample_format => return Err(format!("Unsupported sample format '{sample_format}'")),
     }
-    .unwrap();
-
-    stream.play().unwrap();
+    .map_err(|e| format!("couldn't build output stream: {e}"))?;
 
     stream
+        .play()
+        .map_err(|e| format!("couldn't start output stream: {e}"))?;
+
+    Ok((stream, sample_rate))
+}
+
+/// An output stream of `T` samples drawn from `ring`.
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    ring: Arc<Mutex<AudioRing>>,
+    err_fn: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    use cpal::traits::DeviceTrait;
+
+    let channels = config.channels.max(1) as usize;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            // Never wait on the main thread: if it's busy queueing, play
+            // silence this time around.
+            let mut ring = ring.try_lock().ok();
+            for frame in data.chunks_mut(channels) {
+                let sample = ring.as_mut().and_then(|ring| ring.pop()).unwrap_or(0.0);
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        err_fn,
+        None,
+    )
+}
+
+/// Plays what the simulation hears through the default output device.
+pub struct AudioOutput {
+    _stream: cpal::Stream,
+    ring: Arc<Mutex<AudioRing>>,
+    failed: Arc<AtomicBool>,
+    sample_rate: u32,
+    sonifier: Sonifier,
+}
+impl AudioOutput {
+    pub fn start(settings: AudioSettings) -> Result<AudioOutput, String> {
+        // Sized for the highest rate a device is likely to pick.
+        let capacity = (OUTPUT_BUFFER_SECONDS * 192_000.0) as usize;
+        let ring = Arc::new(Mutex::new(AudioRing::with_capacity(capacity)));
+        let failed = Arc::new(AtomicBool::new(false));
+        let (stream, sample_rate) = do_audio_output(ring.clone(), failed.clone(), settings)?;
+        Ok(AudioOutput {
+            _stream: stream,
+            ring,
+            failed,
+            sample_rate,
+            sonifier: Sonifier::new(sample_rate),
+        })
+    }
+
+    /// Whether the stream has errored and should be dropped.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Queue `pressures`, sampled `tick_rate` times per real second, to be
+    /// played. Whatever doesn't fit in the buffer is dropped.
+    pub fn play(&mut self, pressures: &[f32], tick_rate: f32, gain: f32) {
+        let samples = self.sonifier.convert(pressures, tick_rate, gain);
+
+        let limit = (OUTPUT_BUFFER_SECONDS * self.sample_rate as f32) as usize;
+        let mut ring = self.ring.lock().unwrap();
+        let room = limit.saturating_sub(ring.queued());
+        ring.push(&samples[..samples.len().min(room)]);
+    }
+}
+
+/// Turns pressures sampled once a tick into samples at an output's rate.
+pub struct Sonifier {
+    sample_rate: u32,
+    resampler: Resampler,
+    resampled: Vec<f32>,
+}
+impl Sonifier {
+    pub fn new(sample_rate: u32) -> Sonifier {
+        Sonifier {
+            sample_rate,
+            resampler: Resampler::new(1.0, sample_rate as f32),
+            resampled: Vec::new(),
+        }
+    }
+
+    /// `pressures`, sampled `tick_rate` times per real second and carrying
+    /// straight on from the last call, as samples ready to play.
+    pub fn convert(&mut self, pressures: &[f32], tick_rate: f32, gain: f32) -> &[f32] {
+        self.resampler.set_rates(tick_rate, self.sample_rate as f32);
+        let samples = pressures.iter().map(|&p| pressure_to_sample(p, gain));
+        let samples: Vec<f32> = samples.collect();
+        self.resampled.clear();
+        self.resampler.process(&samples, &mut self.resampled);
+        &self.resampled
+    }
+}
+
+/// An FFT and everything that has to be rebuilt along with it when its
+/// config changes.
+struct Analyzer {
+    config: FftConfig,
+    fft_processor: FftProcessor,
+    window: Vec<f32>,
+    framer: Framer,
+}
+impl Analyzer {
+    fn new(config: FftConfig) -> Analyzer {
+        // The processor is only used for its FFT plan; framing and windowing
+        // happen here so the overlap is under our control.
+        let fft_processor = FftProcessor::new(FftProcessorOptions {
+            size: config.size,
+            ..Default::default()
+        });
+        Analyzer {
+            config,
+            fft_processor,
+            window: make_window_vec(config.size, WindowFunctionType::Hann),
+            framer: Framer::new(config.size, config.overlap),
+        }
+    }
+
+    /// Feed in `samples`, returning whether a new spectrum is ready in
+    /// [`bins`](Self::bins).
+    fn push(&mut self, samples: &[f32]) -> bool {
+        let Analyzer {
+            fft_processor,
+            window,
+            framer,
+            ..
+        } = self;
+        let mut published = false;
+        framer.push(samples, |frame| {
+            let mut bins = std::mem::take(fft_processor.buffer_mut());
+            for ((bin, &sample), &w) in bins.iter_mut().zip(frame).zip(window.iter()) {
+                bin.re = sample * w;
+                bin.im = 0.0;
+            }
+            fft_processor.process_fft_buffer(&mut bins);
+            *fft_processor.buffer_mut() = bins;
+            published = true;
+        });
+        published
+    }
+
+    fn bins(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
+        self.fft_processor
+            .buffer()
+            .iter()
+            .map(|complex| complex.norm())
+    }
+}
+
+/// Fold the newest spectrum into a running exponential average: each bin
+/// keeps `smoothing` of its old value and takes the rest from `latest`. Starts
+/// over from `latest` whenever the number of bins changes.
+pub fn smooth_spectrum(
+    smoothed: &mut Vec<f32>,
+    latest: impl ExactSizeIterator<Item = f32>,
+    smoothing: f32,
+) {
+    let a = smoothing.clamp(0.0, 1.0);
+    if smoothed.len() != latest.len() {
+        smoothed.clear();
+        smoothed.extend(latest);
+        return;
+    }
+    for (s, new) in smoothed.iter_mut().zip(latest) {
+        *s = a * *s + (1.0 - a) * new;
+        // A -inf from a log of silence would otherwise stick forever.
+        if !s.is_finite() {
+            *s = new;
+        }
+    }
+}
+
+/// Silences spectrum bins that are only hiss. A bin opens once it reaches
+/// the threshold and stays open until it drops `hysteresis` below it, so
+/// bins hovering around the threshold don't flicker.
+#[derive(Default)]
+pub struct NoiseGate {
+    /// Whether each bin was passing through last time.
+    open: Vec<bool>,
+}
+impl NoiseGate {
+    /// Zero every closed bin of `levels`, updating which are open.
+    pub fn apply(&mut self, levels: &mut [f32], threshold: f32, hysteresis: f32) {
+        // A new FFT size starts every bin closed.
+        if self.open.len() != levels.len() {
+            self.open = vec![false; levels.len()];
+        }
+        for (level, open) in levels.iter_mut().zip(&mut self.open) {
+            *open = if *open {
+                *level >= threshold - hysteresis
+            } else {
+                *level >= threshold
+            };
+            if !*open {
+                *level = 0.0;
+            }
+        }
+    }
+}
+
+/// Root-mean-square level of `samples`; zero if there are none.
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// Turns incoming samples into spectra, publishing each through
+/// `spectra`, and the level of each block through `levels`. Shared by every
+/// audio source.
+pub fn spectrum_processor(
+    mut spectra: Publisher<Vec<f32>>,
+    mut levels: Publisher<Vec<f32>>,
+    params: Arc<Mutex<SimParams>>,
+) -> impl FnMut(&[f32]) + Send + 'static {
+    // Never wait on the params lock in the audio callback; keep using the
+    // last settings seen until it's free.
+    let mut transform = MagnitudeTransform::default();
+    let mut smoothing = 0.0;
+    let mut analyzer = Analyzer::new(FftConfig::default());
+    let mut smoothed = Vec::new();
+    // Threshold and hysteresis, if gating.
+    let mut gating = None;
+    let mut gate = NoiseGate::default();
+    move |data: &[f32]| {
+        levels.publish(|level| {
+            level.clear();
+            level.push(rms(data));
+        });
+
+        if let Ok(params) = params.try_lock() {
+            transform = params.magnitude_transform;
+            smoothing = params.spectrum_smoothing;
+            gating = params
+                .noise_gate
+                .then_some((params.gate_threshold, params.gate_hysteresis));
+            let config = params.fft_config();
+            if config != analyzer.config {
+                // Drops whatever was buffered, but a resize is rare and a
+                // gap of one frame isn't noticeable.
+                analyzer = Analyzer::new(config);
+            }
+        }
+
+        if !analyzer.push(data) {
+            return;
+        }
+
+        let levels = analyzer.bins().map(|magnitude| transform.apply(magnitude));
+        smooth_spectrum(&mut smoothed, levels, smoothing);
+
+        spectra.publish(|out_buf| {
+            out_buf.clear();
+            out_buf.extend_from_slice(&smoothed);
+            if let Some((threshold, hysteresis)) = gating {
+                gate.apply(out_buf, threshold, hysteresis);
+            }
+        });
+    }
+}
+
+/// A sample of any format as an `f32` in `[-1, 1]`, silence at zero.
+pub fn sample_to_f32<T: cpal::Sample>(sample: T) -> f32
+where
+    f32: cpal::FromSample<T>,
+{
+    sample.to_sample()
+}
+
+/// An input stream of `T` samples, converted to `f32` for `process`.
+fn build_input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut process: impl FnMut(&[f32]) + Send + 'static,
+    err_fn: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    use cpal::traits::DeviceTrait;
+
+    let mut converted = Vec::new();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            converted.clear();
+            converted.extend(data.iter().map(|&s| sample_to_f32(s)));
+            process(&converted);
+        },
+        err_fn,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_on_failure_and_resets_on_success() {
+        let start = Instant::now();
+        let mut backoff = Backoff::new();
+        assert!(backoff.ready(start));
+
+        backoff.failed(start);
+        assert!(!backoff.ready(start));
+        assert!(backoff.ready(start + Duration::from_millis(500)));
+        backoff.failed(start);
+        assert_eq!(backoff.delay(), Duration::from_secs(1));
+        for _ in 0..20 {
+            backoff.failed(start);
+        }
+        assert_eq!(backoff.delay(), Duration::from_secs(30));
+        assert!(!backoff.ready(start + Duration::from_secs(29)));
+
+        backoff.succeeded();
+        assert!(backoff.ready(start));
+        backoff.failed(start);
+        assert_eq!(backoff.delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn half_overlap_about_doubles_the_frames() {
+        let frames = |overlap| {
+            let mut framer = Framer::new(256, overlap);
+            let mut count = 0;
+            // In uneven pieces, as a stream would deliver them.
+            for chunk in vec![0.0; 256 * 40].chunks(300) {
+                framer.push(chunk, |window| {
+                    assert_eq!(window.len(), 256);
+                    count += 1;
+                });
+            }
+            count
+        };
+        assert_eq!(frames(0.0), 40);
+        assert_eq!(frames(0.5), 79);
+    }
+
+    #[test]
+    fn overlapping_windows_share_samples() {
+        let mut framer = Framer::new(4, 0.5);
+        let mut windows = Vec::new();
+        framer.push(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0], |w| {
+            windows.push(w.to_vec())
+        });
+        assert_eq!(windows, [[0.0, 1.0, 2.0, 3.0], [2.0, 3.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn magnitude_transforms_stay_finite() {
+        let log = MagnitudeTransform::Log { floor: 1e-6 };
+        let db = MagnitudeTransform::Db { floor_db: -60.0 };
+        assert_eq!(MagnitudeTransform::Linear.apply(4.0), 4.0);
+        assert_eq!(MagnitudeTransform::Sqrt.apply(4.0), 2.0);
+        assert!((log.apply(std::f32::consts::E) - 1.0).abs() < 1e-6);
+        assert!((db.apply(10.0) - 80.0).abs() < 1e-4);
+
+        assert!((log.apply(0.0) - 1e-6f32.ln()).abs() < 1e-4);
+        assert_eq!(db.apply(0.0), 0.0);
+        assert_eq!(db.apply(f32::NAN), 0.0);
+        assert!(MagnitudeTransform::default().apply(0.0).is_finite());
+    }
+
+    fn config_range(
+        format: cpal::SampleFormat,
+        min_rate: u32,
+        max_rate: u32,
+    ) -> cpal::SupportedStreamConfigRange {
+        cpal::SupportedStreamConfigRange::new(
+            2,
+            cpal::SampleRate(min_rate),
+            cpal::SampleRate(max_rate),
+            cpal::SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn best_config_prefers_f32_then_the_nearest_rate() {
+        use cpal::SampleFormat::{F32, F64, I16, I8};
+        let best = |configs: Vec<_>| {
+            best_config(configs, &SUPPORTED_FORMATS, 48_000)
+                .map(|config| (config.sample_format(), config.sample_rate().0))
+        };
+        let configs = vec![
+            config_range(I16, 48_000, 48_000),
+            config_range(F32, 8_000, 22_050),
+            config_range(F32, 32_000, 44_100),
+            config_range(F64, 48_000, 48_000),
+        ];
+        assert_eq!(best(configs), Some((F32, 44_100)));
+        // Formats the stream can't be built for are never picked, however
+        // well their rate fits.
+        let configs = vec![
+            config_range(F64, 48_000, 48_000),
+            config_range(I16, 8_000, 96_000),
+        ];
+        assert_eq!(best(configs), Some((I16, 48_000)));
+        assert_eq!(best(vec![config_range(I8, 48_000, 48_000)]), None);
+        assert_eq!(best(Vec::new()), None);
+    }
+
+    #[test]
+    fn channels_keep_their_own_latest_values() {
+        let channels = Channels::new();
+        let mut spectrum = channels.publisher(Channels::SPECTRUM);
+        let mut level = channels.publisher(Channels::LEVEL);
+        let latest = |name| {
+            channels.subscriber(name, |subscriber| {
+                subscriber.flip();
+                subscriber.front().clone()
+            })
+        };
+
+        spectrum.publish(|v| *v = vec![1.0, 2.0, 3.0]);
+        level.publish(|v| *v = vec![0.5]);
+        spectrum.publish(|v| *v = vec![4.0, 5.0]);
+        assert_eq!(latest(Channels::SPECTRUM), [4.0, 5.0]);
+        assert_eq!(latest(Channels::LEVEL), [0.5]);
+
+        level.publish(|v| *v = vec![0.25]);
+        assert_eq!(latest(Channels::SPECTRUM), [4.0, 5.0]);
+        assert_eq!(latest(Channels::LEVEL), [0.25]);
+
+        // A restarted stream takes over from the old one, and until it
+        // publishes, readers keep the last value.
+        let mut restarted = channels.publisher(Channels::LEVEL);
+        level.publish(|v| *v = vec![9.0]);
+        assert_eq!(latest(Channels::LEVEL), [0.25]);
+        restarted.publish(|v| *v = vec![0.75]);
+        assert_eq!(latest(Channels::LEVEL), [0.75]);
+    }
+
+    #[test]
+    fn ring_carries_remainders_across_pushes() {
+        let mut ring = AudioRing::with_capacity(32);
+        let input: Vec<f32> = (0..500).map(|i| i as f32).collect();
+        let mut output = Vec::new();
+        let mut chunk = [0.0; 7];
+        let mut rest = &input[..];
+        for size in [1, 13, 5, 0, 20, 3, 8, 17].into_iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (block, after) = rest.split_at(size.min(rest.len()));
+            assert_eq!(ring.push(block), block.len());
+            rest = after;
+            while ring.peek(&mut chunk) {
+                ring.discard(chunk.len());
+                output.extend_from_slice(&chunk);
+            }
+        }
+        // Everything but the last partial chunk, each sample once, in order.
+        assert_eq!(output, input[..input.len() / 7 * 7]);
+        assert_eq!(ring.queued(), input.len() % 7);
+
+        // A full ring takes only what fits.
+        assert_eq!(ring.push(&[0.0; 40]), 32 - input.len() % 7);
+    }
+
+    #[test]
+    fn integer_samples_convert_to_unit_range() {
+        assert!((sample_to_f32(i16::MAX) - 1.0).abs() < 1e-4);
+        assert_eq!(sample_to_f32(0i16), 0.0);
+        assert_eq!(sample_to_f32(i16::MIN), -1.0);
+        // Unsigned samples sit around the middle of their range.
+        assert_eq!(sample_to_f32(32768u16), 0.0);
+        assert!((sample_to_f32(u16::MAX) - 1.0).abs() < 1e-4);
+        assert_eq!(sample_to_f32(0u16), -1.0);
+    }
+
+    #[test]
+    fn devices_are_picked_by_exact_name() {
+        let devices = || {
+            ["pipewire", "USB Mic", "USB Mic (2)"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| (name.to_string(), i))
+        };
+        let by_name = |name: &str| DevicePref::ByName(name.to_string());
+        assert_eq!(pick_device(devices(), &by_name("USB Mic")), Some(1));
+        assert_eq!(pick_device(devices(), &by_name("USB Mic (2)")), Some(2));
+        assert_eq!(pick_device(devices(), &by_name("usb mic")), None);
+        assert_eq!(pick_device(devices(), &by_name("Webcam")), None);
+        assert_eq!(pick_device(devices(), &DevicePref::Default), None);
+    }
+
+    /// A 16-bit stereo WAV of a 1 kHz sine at 8 kHz, the same on both sides.
+    fn sine_wav(frames: usize) -> Vec<u8> {
+        let data: Vec<u8> = (0..frames)
+            .flat_map(|i| {
+                let s = (std::f32::consts::TAU * 1000.0 * i as f32 / 8000.0).sin() * 0.5;
+                let s = ((s * 32767.0) as i16).to_le_bytes();
+                [s, s].concat()
+            })
+            .collect();
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&(8000u32 * 4).to_le_bytes());
+        fmt.extend_from_slice(&4u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&((4 + 8 + fmt.len() + 8 + data.len()) as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        for (id, body) in [(b"fmt ", &fmt), (b"data", &data)] {
+            wav.extend_from_slice(id);
+            wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            wav.extend_from_slice(body);
+        }
+        wav
+    }
+
+    #[test]
+    fn decoded_wav_makes_a_spectrum() {
+        let wav = Wav::parse(&sine_wav(8000)).unwrap();
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.samples.len(), 8000);
+        assert!((wav.samples[2] - 0.5).abs() < 1e-3);
+
+        let channels = Channels::new();
+        let params = Arc::new(Mutex::new(SimParams::default()));
+        let mut process = spectrum_processor(
+            channels.publisher(Channels::SPECTRUM),
+            channels.publisher(Channels::LEVEL),
+            params,
+        );
+        for chunk in wav.samples.chunks(80) {
+            process(chunk);
+        }
+        let spectrum = channels.subscriber(Channels::SPECTRUM, |spectra| {
+            spectra.flip();
+            spectra.front().clone()
+        });
+        assert!(!spectrum.is_empty());
+        assert!(spectrum.iter().all(|level| level.is_finite()));
+        // The bins run over the whole FFT, so the top half mirrors the bottom.
+        let peak = (0..spectrum.len() / 2)
+            .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+            .unwrap();
+        let expected = spectrum.len() / 8;
+        assert!(
+            peak.abs_diff(expected) <= 1,
+            "peak at bin {peak} of {}",
+            spectrum.len()
+        );
+    }
+
+    #[test]
+    fn spectrum_length_follows_the_fft_size() {
+        let channels = Channels::new();
+        let params = Arc::new(Mutex::new(SimParams::default()));
+        let mut process = spectrum_processor(
+            channels.publisher(Channels::SPECTRUM),
+            channels.publisher(Channels::LEVEL),
+            params.clone(),
+        );
+        let noise: Vec<f32> = (0..4096)
+            .map(|i| ((i * 7919) % 101) as f32 / 50.0 - 1.0)
+            .collect();
+
+        for size in [1024, 256, 2048] {
+            params.lock().unwrap().fft_size = size;
+            process(&noise);
+            let len = channels.subscriber(Channels::SPECTRUM, |spectra| {
+                spectra.flip();
+                spectra.front().len()
+            });
+            assert_eq!(len, size);
+        }
+    }
+
+    #[test]
+    fn smoothing_approaches_a_step_geometrically() {
+        let mut smoothed = Vec::new();
+        smooth_spectrum(&mut smoothed, [0.0, 2.0].into_iter(), 0.75);
+        assert_eq!(smoothed, [0.0, 2.0]);
+
+        // Each frame closes a quarter of what's left of the gap.
+        for frame in 1..=8 {
+            smooth_spectrum(&mut smoothed, [1.0, 2.0].into_iter(), 0.75);
+            let expected = 1.0 - 0.75f32.powi(frame);
+            assert!((smoothed[0] - expected).abs() < 1e-6, "frame {frame}");
+            assert_eq!(smoothed[1], 2.0);
+        }
+
+        // A new length starts over from the latest spectrum.
+        smooth_spectrum(&mut smoothed, [5.0, 6.0, 7.0].into_iter(), 0.75);
+        assert_eq!(smoothed, [5.0, 6.0, 7.0]);
+    }
+
+    #[test]
+    fn flip_moves_the_front_only_to_something_newer() {
+        let (mut publisher, mut subscriber) = triple_buffer(&1);
+        assert!(!subscriber.flip());
+        assert_eq!(*subscriber.front(), 1);
+
+        publisher.publish(|v| *v = 2);
+        publisher.publish(|v| *v = 3);
+        assert!(subscriber.flip());
+        assert_eq!(*subscriber.front(), 3);
+        assert!(!subscriber.flip());
+        assert_eq!(*subscriber.front(), 3);
+    }
+
+    #[test]
+    fn consumer_only_ever_sees_whole_published_values() {
+        const LEN: usize = 512;
+        const UPDATES: u32 = 2000;
+        let (mut publisher, mut subscriber) = triple_buffer(&vec![0; LEN]);
+        let producer = std::thread::spawn(move || {
+            for n in 1..=UPDATES {
+                publisher.publish(|values| {
+                    // Written a piece at a time, so a read part way through
+                    // would see a mix.
+                    for value in values.iter_mut() {
+                        *value = n;
+                    }
+                });
+            }
+        });
+
+        let mut last = 0;
+        while last < UPDATES {
+            subscriber.flip();
+            let front = subscriber.front();
+            let n = front[0];
+            assert!(front.iter().all(|&value| value == n), "torn read of {n}");
+            assert!(n >= last, "went back from {last} to {n}");
+            last = n;
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn constant_pressure_plays_as_a_constant_sample() {
+        let mut sonifier = Sonifier::new(48_000);
+        let mut played = Vec::new();
+        // Uneven blocks, as frames come, at 60 ticks a second.
+        for ticks in [1, 3, 2, 5, 1] {
+            played.extend_from_slice(sonifier.convert(&vec![0.25; ticks], 60.0, 2.0));
+        }
+        assert_eq!(played.len(), 12 * 800);
+        assert!(
+            played.iter().all(|&sample| sample == 0.5),
+            "{:?}",
+            &played[..4]
+        );
+
+        // Past full scale it clips, and keeps on clipping.
+        let loud = sonifier.convert(&[3.0; 4], 60.0, 1.0);
+        assert!(!loud.is_empty());
+        assert!(loud[loud.len() / 2..].iter().all(|&sample| sample == 1.0));
+    }
+
+    #[test]
+    fn rms_of_a_sine_is_its_amplitude_over_root_two() {
+        // Whole cycles of a 0.8 amplitude sine, 64 samples each.
+        let sine: Vec<f32> = (0..64 * 10)
+            .map(|i| 0.8 * (std::f32::consts::TAU * i as f32 / 64.0).sin())
+            .collect();
+        assert!(
+            (rms(&sine) - 0.8 / 2f32.sqrt()).abs() < 1e-4,
+            "{}",
+            rms(&sine)
+        );
+        assert_eq!(rms(&[0.5; 32]), 0.5);
+        assert_eq!(rms(&[-0.5, 0.5]), 0.5);
+        assert_eq!(rms(&[]), 0.0);
+    }
+
+    #[test]
+    fn gate_zeroes_quiet_bins_and_holds_loud_ones_open() {
+        let mut gate = NoiseGate::default();
+        let mut quiet = vec![0.1, 0.3, 0.49, 0.0];
+        gate.apply(&mut quiet, 0.5, 0.1);
+        assert_eq!(quiet, [0.0; 4]);
+
+        let mut loud = vec![0.5, 0.9, 0.2, 2.0];
+        gate.apply(&mut loud, 0.5, 0.1);
+        assert_eq!(loud, [0.5, 0.9, 0.0, 2.0]);
+
+        // Dipping a little under the threshold keeps an open bin open, but
+        // not a closed one; dipping past the hysteresis closes it.
+        let mut dipped = vec![0.45, 0.35, 0.45, 0.6];
+        gate.apply(&mut dipped, 0.5, 0.1);
+        assert_eq!(dipped, [0.45, 0.0, 0.0, 0.6]);
+    }
+
+    #[test]
+    fn each_scale_maps_known_magnitudes() {
+        let db = MagnitudeTransform::Db {
+            floor_db: MagnitudeTransform::DEFAULT_DB_FLOOR,
+        };
+        // Full scale sits a whole floor's worth above silence.
+        assert!((db.apply(1.0) - 100.0).abs() < 1e-4);
+        assert!((db.apply(0.1) - 80.0).abs() < 1e-4);
+        assert!((db.apply(1e-3) - 40.0).abs() < 1e-3);
+        // Anything at or under the floor is silence.
+        for silent in [1e-5, 1e-6, 1e-12, 0.0, -1.0] {
+            assert_eq!(db.apply(silent), 0.0, "{silent}");
+        }
+
+        let log = MagnitudeTransform::Log { floor: 1e-3 };
+        assert_eq!(log.apply(1.0), 0.0);
+        assert!((log.apply(100.0) - 100f32.ln()).abs() < 1e-5);
+        assert_eq!(log.apply(1e-9), log.apply(1e-3));
+        assert_eq!(MagnitudeTransform::Linear.apply(0.125), 0.125);
+        assert_eq!(MagnitudeTransform::Sqrt.apply(0.25), 0.5);
+        assert_eq!(
+            MagnitudeTransform::default(),
+            MagnitudeTransform::Log { floor: 1e-9 }
+        );
+    }
 }
@@ -0,0 +1,117 @@
+//! Saving a burst of frames around loud moments.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::export;
+
+/// One rendered frame.
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Watches the field energy and, when it rises through a threshold, hands
+/// back the frames leading up to it (the pre-roll) plus those that follow
+/// (the post-roll).
+pub(crate) struct Trigger<F> {
+    pre_roll: VecDeque<F>,
+    /// Frames of the burst in progress, and how many more it needs.
+    burst: Option<(Vec<F>, usize)>,
+    was_above: bool,
+}
+
+impl<F> Trigger<F> {
+    pub fn new() -> Trigger<F> {
+        Trigger {
+            pre_roll: VecDeque::new(),
+            burst: None,
+            was_above: false,
+        }
+    }
+
+    /// Feed in the next frame and the energy it showed. Returns a finished
+    /// burst, oldest first, once the post-roll is complete.
+    pub fn push(
+        &mut self,
+        frame: F,
+        energy: f32,
+        threshold: f32,
+        pre: usize,
+        post: usize,
+    ) -> Option<Vec<F>> {
+        let above = energy >= threshold;
+        let crossed = above && !self.was_above;
+        self.was_above = above;
+
+        if let Some((frames, remaining)) = &mut self.burst {
+            frames.push(frame);
+            *remaining -= 1;
+            if *remaining == 0 {
+                return self.burst.take().map(|(frames, _)| frames);
+            }
+            return None;
+        }
+
+        if crossed {
+            let mut frames: Vec<F> = self.pre_roll.drain(..).collect();
+            frames.push(frame);
+            if post == 0 {
+                return Some(frames);
+            }
+            self.burst = Some((frames, post));
+            return None;
+        }
+
+        self.pre_roll.push_back(frame);
+        while self.pre_roll.len() > pre {
+            self.pre_roll.pop_front();
+        }
+        None
+    }
+}
+
+impl<F> Default for Trigger<F> {
+    fn default() -> Self {
+        Trigger::new()
+    }
+}
+
+/// Write a burst out as numbered PNGs in a fresh directory under `dir`.
+pub(crate) fn save_burst(dir: &Path, frames: &[Frame]) -> Result<PathBuf, String> {
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let burst_dir = dir.join(format!("capture-{stamp}"));
+    for (i, frame) in frames.iter().enumerate() {
+        let path = burst_dir.join(format!("{i:04}.png"));
+        export::save_png(&path, frame.width, frame.height, &frame.rgba)?;
+    }
+    Ok(burst_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_spans_the_pre_and_post_roll_around_a_spike() {
+        let energies = [
+            0.0, 1.0, 0.0, 2.0, 9.0, 12.0, 3.0, 0.0, 0.0, 1.0, 10.0, 0.0, 0.0, 0.0,
+        ];
+        let mut trigger = Trigger::new();
+        let bursts: Vec<(usize, Vec<usize>)> = energies
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &energy)| Some((i, trigger.push(i, energy, 5.0, 2, 3)?)))
+            .collect();
+        assert_eq!(
+            bursts,
+            [
+                (7, vec![2, 3, 4, 5, 6, 7]),
+                (13, vec![8, 9, 10, 11, 12, 13])
+            ]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::PathBuf;
+
+use kontawa::simulation::{CellRect, DampingRamp};
+
+use crate::config::Config;
+use crate::paint;
+use crate::sweep::{Sweep, SweepRange};
+
+/// Command-line options.
+#[derive(Default)]
+pub(crate) struct Args {
+    /// Defaults, overlaid with any config files and flags, in order.
+    pub config: Config,
+    /// Print the effective config to stdout.
+    pub dump_config: bool,
+    /// Keep going after `--dump-config` instead of exiting.
+    pub keep_running: bool,
+    /// Never run more than this many ticks: a sweep runs at most this many
+    /// per combination, and the window pauses once it gets there.
+    pub max_ticks: Option<u32>,
+    /// Open the window at its default size and place, ignoring the saved one.
+    pub reset_window: bool,
+    /// Play this file instead of listening to the microphone.
+    pub wav: Option<PathBuf>,
+    /// Start with the obstacles drawn in this image.
+    pub materials: Option<PathBuf>,
+    /// Extra damping to lay over the field, in order.
+    pub damping: Vec<DampingRamp>,
+
+    /// Where to write a parameter sweep, if running one.
+    pub sweep_out: Option<PathBuf>,
+    pub sweep_alpha: Option<SweepRange>,
+    pub sweep_damping: Option<SweepRange>,
+    pub sweep_ticks: Option<u32>,
+    pub contact_sheet: bool,
+}
+
+const USAGE: &str = "\
+usage: kontawa [options]
+
+    --config FILE   load settings from FILE (later flags override it)
+    --dump-config   print the effective config and exit
+    --continue      with --dump-config, start up normally afterwards
+    --no-warmup     don't warm up the thread pool at startup
+    --reset-window  ignore the remembered window size and position
+    --no-bar        don't run the moving bar
+    --max-ticks N   stop simulating after N ticks
+    --grid WxH      simulate a W by H grid of cells
+    --wav FILE      drive the simulation from a WAV file instead of the mic
+    --materials IMG start with obstacles from a PNG or BMP: black is solid,
+                    white fluid, and mask_accent an emitter
+    --damp-rect X,Y,W,H,FROM:TO[,v]
+                    ramp extra damping from FROM to TO across a rectangle,
+                    left to right (top to bottom with v); 1 is none
+    --damp-radial X,Y,R,CENTER
+                    extra damping of CENTER at (X, Y), easing to none R
+                    cells out
+    -h, --help      print this message
+
+sweeps (run headless and exit):
+    --sweep DIR                 write a PNG per parameter combination to DIR
+    --sweep-alpha MIN:MAX:N     grad_alpha values (default: the configured one)
+    --sweep-damping MIN:MAX:N   grad_damping values (default: the configured one)
+    --sweep-ticks N             ticks to run each combination (default 300)
+    --contact-sheet             also tile every frame into contact_sheet.png";
+
+impl Args {
+    pub fn parse() -> Args {
+        match Args::parse_from(std::env::args().skip(1)) {
+            Ok(args) => args,
+            Err(msg) => {
+                if !msg.is_empty() {
+                    eprintln!("{msg}");
+                }
+                eprintln!("{USAGE}");
+                std::process::exit(if msg.is_empty() { 0 } else { 2 });
+            }
+        }
+    }
+
+    /// Parse arguments (not including the program name). An empty error means
+    /// help was requested.
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut out = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = PathBuf::from(value(&mut args, &arg)?);
+                    out.config.apply_file(&path)?;
+                }
+                "--dump-config" => out.dump_config = true,
+                "--continue" => out.keep_running = true,
+                "--no-warmup" => out.config.warmup = false,
+                "--reset-window" => out.reset_window = true,
+                "--wav" => out.wav = Some(value(&mut args, &arg)?.into()),
+                "--materials" => out.materials = Some(value(&mut args, &arg)?.into()),
+                "--damp-rect" => out.damping.push(parse_damp_rect(&value(&mut args, &arg)?)?),
+                "--damp-radial" => out
+                    .damping
+                    .push(parse_damp_radial(&value(&mut args, &arg)?)?),
+                "--no-bar" => out.config.params.animate_bar = false,
+                "--max-ticks" => out.max_ticks = Some(parse_ticks(&value(&mut args, &arg)?)?),
+                "--grid" => {
+                    let grid = value(&mut args, &arg)?;
+                    let bad = || format!("expected WxH, got '{grid}'");
+                    let (w, h) = grid.split_once('x').ok_or_else(bad)?;
+                    out.config.grid_width = w.parse().map_err(|_| bad())?;
+                    out.config.grid_height = h.parse().map_err(|_| bad())?;
+                }
+                "--sweep" => out.sweep_out = Some(value(&mut args, &arg)?.into()),
+                "--sweep-alpha" => {
+                    out.sweep_alpha = Some(SweepRange::parse(&value(&mut args, &arg)?)?)
+                }
+                "--sweep-damping" => {
+                    out.sweep_damping = Some(SweepRange::parse(&value(&mut args, &arg)?)?)
+                }
+                "--sweep-ticks" => out.sweep_ticks = Some(parse_ticks(&value(&mut args, &arg)?)?),
+                "--contact-sheet" => out.contact_sheet = true,
+                "-h" | "--help" => return Err(String::new()),
+                _ => return Err(format!("unknown argument '{arg}'")),
+            }
+        }
+
+        if out.sweep_out.is_none() && (out.sweep_alpha.is_some() || out.sweep_damping.is_some()) {
+            return Err("--sweep-alpha and --sweep-damping need --sweep".to_string());
+        }
+        if out.config.grid_width == 0 || out.config.grid_height == 0 {
+            return Err("the grid needs at least one cell each way".to_string());
+        }
+        if out.config.params.fft_size == 0 {
+            return Err("fft_size can't be zero".to_string());
+        }
+        if !(1..=crate::MAX_SUBSTEPS).contains(&out.config.params.substeps) {
+            return Err(format!(
+                "substeps must be from 1 to {}",
+                crate::MAX_SUBSTEPS
+            ));
+        }
+        if !paint::BRUSH_RADIUS_RANGE.contains(&out.config.params.brush_radius) {
+            let (min, max) = paint::BRUSH_RADIUS_RANGE.into_inner();
+            return Err(format!("brush_radius must be from {min} to {max}"));
+        }
+        Ok(out)
+    }
+
+    /// The sweep asked for, filling unswept parameters from the config.
+    pub fn sweep(&self) -> Option<Sweep> {
+        let params = &self.config.params;
+        Some(Sweep {
+            grad_alpha: self
+                .sweep_alpha
+                .unwrap_or(SweepRange::single(params.wave.grad_alpha)),
+            grad_damping: self
+                .sweep_damping
+                .unwrap_or(SweepRange::single(params.wave.grad_damping)),
+            ticks: self
+                .sweep_ticks
+                .unwrap_or(300)
+                .min(self.max_ticks.unwrap_or(u32::MAX)),
+            out_dir: self.sweep_out.clone()?,
+            contact_sheet: self.contact_sheet,
+            grid: (self.config.grid_width, self.config.grid_height),
+        })
+    }
+}
+
+/// The value following a flag.
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{flag} needs a value"))
+}
+
+fn parse_ticks(ticks: &str) -> Result<u32, String> {
+    ticks
+        .parse()
+        .map_err(|_| format!("bad tick count '{ticks}'"))
+}
+
+/// A damping value, which keeps that fraction each tick: 1 is none.
+fn parse_damping(damping: &str) -> Result<f32, String> {
+    match damping.parse() {
+        Ok(damping) if (0.0..=1.0).contains(&damping) => Ok(damping),
+        _ => Err(format!("damping must be from 0 to 1, got '{damping}'")),
+    }
+}
+
+/// `X,Y,W,H,FROM:TO`, optionally followed by `,v` to ramp top to bottom.
+fn parse_damp_rect(ramp: &str) -> Result<DampingRamp, String> {
+    let bad = || format!("expected X,Y,W,H,FROM:TO[,v], got '{ramp}'");
+    let parts: Vec<&str> = ramp.split(',').collect();
+    let horizontal = match parts.get(5) {
+        None => true,
+        Some(&"v") if parts.len() == 6 => false,
+        _ => return Err(bad()),
+    };
+    let [x, y, width, height] = match parts.get(..4) {
+        Some(&[x, y, width, height]) => [x, y, width, height].map(str::parse::<usize>),
+        _ => return Err(bad()),
+    };
+    let (start, end) = parts
+        .get(4)
+        .and_then(|ends| ends.split_once(':'))
+        .ok_or_else(bad)?;
+    Ok(DampingRamp::Rect {
+        rect: CellRect {
+            x: x.map_err(|_| bad())?,
+            y: y.map_err(|_| bad())?,
+            width: width.map_err(|_| bad())?,
+            height: height.map_err(|_| bad())?,
+        },
+        horizontal,
+        start: parse_damping(start)?,
+        end: parse_damping(end)?,
+    })
+}
+
+/// `X,Y,R,CENTER`.
+fn parse_damp_radial(ramp: &str) -> Result<DampingRamp, String> {
+    let bad = || format!("expected X,Y,R,CENTER, got '{ramp}'");
+    let &[x, y, radius, center_value] = ramp.split(',').collect::<Vec<_>>().as_slice() else {
+        return Err(bad());
+    };
+    let number = |v: &str| v.parse::<f32>().map_err(|_| bad());
+    let radius = number(radius)?;
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(format!(
+            "the damping radius has to be positive, got {radius}"
+        ));
+    }
+    Ok(DampingRamp::Radial {
+        center: (number(x)?, number(y)?),
+        radius,
+        center_value: parse_damping(center_value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn sweep_ranges_need_sweep() {
+        assert!(parse(&["--sweep-alpha", "0.1:0.5:3"]).is_err());
+        assert!(parse(&["--sweep-damping", "0.9:1:2"]).is_err());
+        let args = parse(&["--sweep", "out", "--sweep-alpha", "0.1:0.5:3"]).unwrap();
+        assert_eq!(args.sweep().unwrap().grad_alpha.steps, 3);
+    }
+
+    #[test]
+    fn damping_ramps_parse_in_order() {
+        let args = parse(&[
+            "--damp-rect",
+            "48,0,16,32,1:0.9",
+            "--damp-radial",
+            "10,12.5,6,0.5",
+            "--damp-rect",
+            "0,0,8,4,0.8:1,v",
+        ])
+        .unwrap();
+        let rect = |x, y, width, height| CellRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            args.damping,
+            [
+                DampingRamp::Rect {
+                    rect: rect(48, 0, 16, 32),
+                    horizontal: true,
+                    start: 1.0,
+                    end: 0.9
+                },
+                DampingRamp::Radial {
+                    center: (10.0, 12.5),
+                    radius: 6.0,
+                    center_value: 0.5
+                },
+                DampingRamp::Rect {
+                    rect: rect(0, 0, 8, 4),
+                    horizontal: false,
+                    start: 0.8,
+                    end: 1.0
+                },
+            ]
+        );
+        for bad in ["1,2,3,4", "1,2,3,4,0.5:2", "1,2,3,4,1:1,h", "a,2,3,4,1:1"] {
+            assert!(parse(&["--damp-rect", bad]).is_err(), "{bad}");
+        }
+        for bad in ["1,2,3", "1,2,0,0.5", "1,2,3,-1"] {
+            assert!(parse(&["--damp-radial", bad]).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! Plain `key = value` config files covering everything that can be set at
+//! startup or tweaked in the GUI.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use kontawa::render::{Palette, SolidStyle, TrailMode};
+use kontawa::simulation::{EdgeMode, Material};
+
+use crate::audio::{AudioSettings, DevicePref, MagnitudeTransform};
+use crate::emitter::{Modulation, Waveform};
+use crate::injection::{FreqScale, SpectrumMapping};
+use crate::mask::ImageFit;
+use crate::paint::{BrushTarget, PaintMode};
+use crate::SimParams;
+
+/// Everything that makes up a run's configuration.
+pub(crate) struct Config {
+    /// Spin up the rayon pool before the first frame.
+    pub warmup: bool,
+    /// Simulation grid size, in cells.
+    pub grid_width: u32,
+    pub grid_height: u32,
+    pub audio: AudioSettings,
+    pub params: SimParams,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            warmup: true,
+            grid_width: crate::DEFAULT_WIDTH,
+            grid_height: crate::DEFAULT_HEIGHT,
+            audio: AudioSettings::default(),
+            params: SimParams::default(),
+        }
+    }
+}
+
+/// A value that can be written to and read back from a config file.
+pub(crate) trait ConfigValue: Sized {
+    fn write(&self) -> String;
+    fn read(s: &str) -> Option<Self>;
+}
+
+/// Anything whose `Display` and `FromStr` round-trip.
+macro_rules! plain_config_values {
+    ($($ty:ty),*) => {
+        $(
+            impl ConfigValue for $ty {
+                fn write(&self) -> String {
+                    self.to_string()
+                }
+                fn read(s: &str) -> Option<Self> {
+                    s.parse().ok()
+                }
+            }
+        )*
+    };
+}
+plain_config_values!(f32, u32, u64, usize, bool);
+
+/// Colors are written as `rrggbb` hex.
+impl ConfigValue for [u8; 3] {
+    fn write(&self) -> String {
+        let [r, g, b] = self;
+        format!("{r:02x}{g:02x}{b:02x}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        if s.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+        Some([channel(0)?, channel(2)?, channel(4)?])
+    }
+}
+impl ConfigValue for SolidStyle {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Flat" => Some(SolidStyle::Flat),
+            "Checker" => Some(SolidStyle::Checker),
+            "Hatch" => Some(SolidStyle::Hatch),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for SpectrumMapping {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Wrap" => Some(SpectrumMapping::Wrap),
+            "Clamp" => Some(SpectrumMapping::Clamp),
+            "Resample" => Some(SpectrumMapping::Resample),
+            _ => None,
+        }
+    }
+}
+
+/// Written as the variant name, with `Log`'s or `Db`'s floor after a colon.
+impl ConfigValue for MagnitudeTransform {
+    fn write(&self) -> String {
+        match self {
+            MagnitudeTransform::Log { floor } => format!("Log:{floor}"),
+            MagnitudeTransform::Db { floor_db } => format!("Db:{floor_db}"),
+            other => other.name().to_string(),
+        }
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s.split_once(':') {
+            Some(("Log", floor)) => Some(MagnitudeTransform::Log {
+                floor: floor.trim().parse().ok()?,
+            }),
+            Some(("Db", floor_db)) => Some(MagnitudeTransform::Db {
+                floor_db: floor_db.trim().parse().ok()?,
+            }),
+            Some(_) => None,
+            None => match s {
+                "Linear" => Some(MagnitudeTransform::Linear),
+                "Log" => Some(MagnitudeTransform::default()),
+                "Db" => Some(MagnitudeTransform::Db {
+                    floor_db: MagnitudeTransform::DEFAULT_DB_FLOOR,
+                }),
+                "Sqrt" => Some(MagnitudeTransform::Sqrt),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// `Default`, or the device's name.
+impl ConfigValue for DevicePref {
+    fn write(&self) -> String {
+        match self {
+            DevicePref::Default => "Default".to_string(),
+            DevicePref::ByName(name) => name.clone(),
+        }
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Default" => Some(DevicePref::Default),
+            name => Some(DevicePref::ByName(name.to_string())),
+        }
+    }
+}
+
+impl ConfigValue for FreqScale {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Linear" => Some(FreqScale::Linear),
+            "Log" => Some(FreqScale::Log),
+            "Mel" => Some(FreqScale::Mel),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for PaintMode {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Set" => Some(PaintMode::Set),
+            "Erase" => Some(PaintMode::Erase),
+            "Toggle" => Some(PaintMode::Toggle),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for BrushTarget {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Material" => Some(BrushTarget::Material),
+            "Params" => Some(BrushTarget::Params),
+            "Damping" => Some(BrushTarget::Damping),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for TrailMode {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Off" => Some(TrailMode::Off),
+            "Max" => Some(TrailMode::Max),
+            "Average" => Some(TrailMode::Average),
+            _ => None,
+        }
+    }
+}
+
+/// A colormap's name, or `Bipolar:POSITIVE:NEGATIVE:MIDPOINT` with the colors
+/// in hex.
+impl ConfigValue for Palette {
+    fn write(&self) -> String {
+        match self {
+            Palette::Bipolar {
+                positive,
+                negative,
+                midpoint,
+            } => format!(
+                "Bipolar:{}:{}:{midpoint}",
+                positive.write(),
+                negative.write()
+            ),
+            other => other.name().to_string(),
+        }
+    }
+    fn read(s: &str) -> Option<Self> {
+        let mut parts = s.split(':');
+        match parts.next()? {
+            "RedBlue" => Some(Palette::RedBlue),
+            "Viridis" => Some(Palette::Viridis),
+            "Grayscale" => Some(Palette::Grayscale),
+            "Turbo" => Some(Palette::Turbo),
+            "Bipolar" => {
+                let palette = Palette::Bipolar {
+                    positive: ConfigValue::read(parts.next()?)?,
+                    negative: ConfigValue::read(parts.next()?)?,
+                    midpoint: parts.next()?.parse().ok()?,
+                };
+                parts.next().is_none().then_some(palette)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for Modulation {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Off" => Some(Modulation::Off),
+            "Am" => Some(Modulation::Am),
+            "Fm" => Some(Modulation::Fm),
+            _ => None,
+        }
+    }
+}
+
+/// Written as the variant name, with `Absorb`'s thickness after a colon.
+impl ConfigValue for EdgeMode {
+    fn write(&self) -> String {
+        match self {
+            EdgeMode::Absorb { thickness } => format!("Absorb:{thickness}"),
+            other => other.name().to_string(),
+        }
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s.split_once(':') {
+            Some(("Absorb", thickness)) => Some(EdgeMode::Absorb {
+                thickness: thickness.trim().parse().ok()?,
+            }),
+            Some(_) => None,
+            None => match s {
+                "Zero" => Some(EdgeMode::Zero),
+                "Radiating" => Some(EdgeMode::Radiating),
+                "Reflect" => Some(EdgeMode::Reflect),
+                "Periodic" => Some(EdgeMode::Periodic),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Written as the variant name, with a membrane's tension after a colon.
+impl ConfigValue for Material {
+    fn write(&self) -> String {
+        match self {
+            Material::Membrane(tension) => format!("Membrane:{tension}"),
+            other => other.name().to_string(),
+        }
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s.split_once(':') {
+            Some(("Membrane", tension)) => Some(Material::Membrane(tension.trim().parse().ok()?)),
+            Some(_) => None,
+            None => match s {
+                "Fluid" => Some(Material::Fluid),
+                "Solid" => Some(Material::Solid),
+                "Emitter" => Some(Material::Emitter),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl ConfigValue for Waveform {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        Waveform::ALL.into_iter().find(|w| format!("{w:?}") == s)
+    }
+}
+
+impl ConfigValue for ImageFit {
+    fn write(&self) -> String {
+        format!("{self:?}")
+    }
+    fn read(s: &str) -> Option<Self> {
+        match s {
+            "Stretch" => Some(ImageFit::Stretch),
+            "Letterbox" => Some(ImageFit::Letterbox),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! config_fields {
+    ($($($path:ident).+),* $(,)?) => {
+        impl Config {
+            fn write_fields(&self, out: &mut String) {
+                $(
+                    writeln!(
+                        out,
+                        "{} = {}",
+                        config_fields!(@key $($path).+),
+                        self.$($path).+.write()
+                    )
+                    .unwrap();
+                )*
+            }
+
+            fn read_field(&mut self, key: &str, value: &str) -> Result<(), String> {
+                $(
+                    if key == config_fields!(@key $($path).+) {
+                        self.$($path).+ = ConfigValue::read(value)
+                            .ok_or_else(|| format!("bad value for {key}: '{value}'"))?;
+                        return Ok(());
+                    }
+                )*
+                Err(format!("unknown config key '{key}'"))
+            }
+        }
+    };
+    (@key $first:ident $(. $rest:ident)*) => {
+        config_fields!(@last $first $($rest)*)
+    };
+    (@last $last:ident) => { stringify!($last) };
+    (@last $skip:ident $($rest:ident)+) => { config_fields!(@last $($rest)+) };
+}
+
+config_fields!(
+    warmup,
+    grid_width,
+    grid_height,
+    audio.sample_rate,
+    params.wave.grad_alpha,
+    params.wave.grad_damping,
+    params.input_device,
+    params.audio_output,
+    params.output_gain,
+    params.fft_size,
+    params.fft_overlap,
+    params.spectrum_smoothing,
+    params.magnitude_transform,
+    params.spectrum_floor,
+    params.noise_gate,
+    params.gate_threshold,
+    params.gate_hysteresis,
+    params.spectrum_mapping,
+    params.spectrum_bands,
+    params.freq_scale,
+    params.injection_gain,
+    params.max_injection_rate,
+    params.animate_bar,
+    params.wave.edge_mode,
+    params.wave.advection,
+    params.seed_on_reset,
+    params.noise_seed,
+    params.noise_amplitude,
+    params.noise_smoothness,
+    params.solid_style,
+    params.solid_color,
+    params.emitter_color,
+    params.boundary_blend,
+    params.palette,
+    params.palette_fade,
+    params.non_finite_color,
+    params.trail,
+    params.display_gain,
+    params.velocity_overlay,
+    params.velocity_stride,
+    params.velocity_opacity,
+    params.auto_gain,
+    params.lock_scale,
+    params.speed,
+    params.substeps,
+    params.emitter_freq,
+    params.emitter_amp,
+    params.emitter_waveform,
+    params.emitter_modulation,
+    params.emitter_mod_freq,
+    params.emitter_mod_depth,
+    params.membrane,
+    params.membrane_tension,
+    params.dense_stiffness,
+    params.paint_mode,
+    params.brush_material,
+    params.brush_radius,
+    params.brush_target,
+    params.brush_grad_alpha,
+    params.brush_grad_damping,
+    params.brush_damping,
+    params.capture,
+    params.capture_threshold,
+    params.capture_pre_roll,
+    params.capture_post_roll,
+    params.snap_window,
+    params.y_up,
+    params.track_energy,
+    params.fit_window,
+    params.mask_fit,
+    params.mask_accent,
+);
+
+impl Config {
+    /// Serialize the whole config.
+    pub fn to_config_string(&self) -> String {
+        let mut out = String::from("# kontawa config\n");
+        self.write_fields(&mut out);
+        out
+    }
+
+    /// Apply every `key = value` line in `text` on top of the current values.
+    pub fn apply_str(&mut self, text: &str) -> Result<(), String> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected 'key = value'", n + 1))?;
+            self.read_field(key.trim(), value.trim())
+                .map_err(|e| format!("line {}: {e}", n + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Apply a config file on top of the current values.
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+        self.apply_str(&text)
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kontawa::simulation::WaveParams;
+
+    #[test]
+    fn config_round_trips() {
+        let config = Config {
+            grid_width: 300,
+            params: SimParams {
+                palette: Palette::BIPOLAR,
+                brush_material: Material::Membrane(2.5),
+                magnitude_transform: MagnitudeTransform::Db { floor_db: -80.0 },
+                input_device: DevicePref::ByName("USB Mic".to_string()),
+                solid_color: [0x12, 0xab, 0xff],
+                wave: WaveParams {
+                    grad_alpha: 0.1,
+                    edge_mode: EdgeMode::Absorb { thickness: 12 },
+                    ..WaveParams::default()
+                },
+                ..SimParams::default()
+            },
+            ..Config::default()
+        };
+
+        let text = config.to_config_string();
+        let mut parsed = Config::default();
+        parsed.apply_str(&text).unwrap();
+        assert_eq!(parsed.to_config_string(), text);
+        assert_eq!(parsed.grid_width, 300);
+        assert_eq!(parsed.params.palette, Palette::BIPOLAR);
+    }
+}
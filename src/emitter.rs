@@ -0,0 +1,188 @@
+//! The signal emitter cells put out.
+
+use std::f32::consts::TAU;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use kontawa::simulation::CellRect;
+
+/// The shape of one cycle of an emitter's output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    /// Rising from -1 to 1 over each cycle.
+    Saw,
+    /// A short positive kick at the start of each cycle, zero otherwise.
+    Pulse,
+}
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Sine,
+        Waveform::Square,
+        Waveform::Saw,
+        Waveform::Pulse,
+    ];
+
+    /// The output at `phase`, in radians from 0 to `TAU`, between -1 and 1.
+    pub fn at(self, phase: f32) -> f32 {
+        let t = phase / TAU;
+        match self {
+            Waveform::Sine => phase.sin(),
+            Waveform::Square if t < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Saw => 2.0 * t - 1.0,
+            Waveform::Pulse if t < 0.125 => 1.0,
+            Waveform::Pulse => 0.0,
+        }
+    }
+}
+
+/// What the modulator does to the emitter's tone.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) enum Modulation {
+    #[default]
+    Off,
+    /// Tremolo: the modulator swings the amplitude.
+    Am,
+    /// Vibrato: the modulator swings the frequency.
+    Fm,
+}
+impl Modulation {
+    pub const ALL: [Modulation; 3] = [Modulation::Off, Modulation::Am, Modulation::Fm];
+}
+
+/// Everything that shapes the emitter's output.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Tone {
+    /// Carrier frequency, in Hz.
+    pub freq: f32,
+    pub amp: f32,
+    pub waveform: Waveform,
+    pub modulation: Modulation,
+    /// Modulator frequency, in Hz.
+    pub mod_freq: f32,
+    /// How far the modulator swings the amplitude or frequency, as a
+    /// fraction of it.
+    pub mod_depth: f32,
+}
+
+/// Accumulates the carrier phase, so the frequency can change (under FM, or
+/// from the GUI) without the waveform jumping.
+#[derive(Default)]
+pub(crate) struct Oscillator {
+    phase: f32,
+}
+impl Oscillator {
+    /// An oscillator starting `phase` radians into its cycle.
+    pub fn starting_at(phase: f32) -> Oscillator {
+        Oscillator {
+            phase: phase.rem_euclid(TAU),
+        }
+    }
+
+    /// Advance by `dt` seconds to `time` and return the output there.
+    pub fn next(&mut self, tone: Tone, time: f32, dt: f32) -> f32 {
+        let modulator = (TAU * tone.mod_freq * time).sin() * tone.mod_depth;
+        let (freq, amp) = match tone.modulation {
+            Modulation::Off => (tone.freq, tone.amp),
+            Modulation::Am => (tone.freq, tone.amp * (1.0 + modulator)),
+            Modulation::Fm => (tone.freq * (1.0 + modulator), tone.amp),
+        };
+        self.phase = (self.phase + TAU * freq * dt) % TAU;
+        amp * tone.waveform.at(self.phase)
+    }
+}
+
+/// A block of cells driven by its own tone, independent of the emitter
+/// material's.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Emitter {
+    /// Unique to this emitter and kept through edits, so its oscillator
+    /// follows it when others are removed from the list.
+    pub id: u64,
+    pub region: CellRect,
+    pub tone: Tone,
+    /// Where in its cycle it starts, in radians.
+    pub phase: f32,
+}
+impl Emitter {
+    /// A plain sine at `freq` Hz over `region`.
+    pub fn new(region: CellRect, freq: f32, amp: f32) -> Emitter {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Emitter {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            region,
+            tone: Tone {
+                freq,
+                amp,
+                waveform: Waveform::Sine,
+                modulation: Modulation::Off,
+                mod_freq: 0.0,
+                mod_depth: 0.0,
+            },
+            phase: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    fn modulated(modulation: Modulation, mod_freq: f32, mod_depth: f32) -> Tone {
+        Tone {
+            freq: 50.0,
+            amp: 1.0,
+            waveform: Waveform::Sine,
+            modulation,
+            mod_freq,
+            mod_depth,
+        }
+    }
+
+    #[test]
+    fn am_swings_the_peaks_at_the_modulator_frequency() {
+        let tone = modulated(Modulation::Am, 2.0, 0.5);
+        let mut oscillator = Oscillator::default();
+        let samples: Vec<f32> = (1..=1000)
+            .map(|i| oscillator.next(tone, i as f32 * DT, DT))
+            .collect();
+        // The loudest sample of each 20ms carrier cycle.
+        let peaks: Vec<f32> = samples
+            .chunks(20)
+            .map(|cycle| cycle.iter().copied().fold(0.0, f32::max))
+            .collect();
+
+        // The 2 Hz envelope tops out at 1.5 at 125ms and bottoms out at 0.5
+        // at 375ms, then does it all again half a second later.
+        assert!(peaks[6] > 1.45, "{peaks:?}");
+        assert!(peaks[18] < 0.55, "{peaks:?}");
+        for (first, second) in peaks[..25].iter().zip(&peaks[25..]) {
+            assert!((first - second).abs() < 0.05, "{peaks:?}");
+        }
+    }
+
+    #[test]
+    fn fm_shifts_the_instantaneous_frequency() {
+        let tone = modulated(Modulation::Fm, 1.0, 0.2);
+        let mut oscillator = Oscillator::default();
+        let mut frequency_at = |ms: u32| {
+            let before = oscillator.phase;
+            oscillator.next(tone, ms as f32 * DT, DT);
+            (oscillator.phase - before).rem_euclid(TAU) / (TAU * DT)
+        };
+        let frequencies: Vec<f32> = (0..1000).map(&mut frequency_at).collect();
+        assert!((frequencies[0] - 50.0).abs() < 0.1);
+        assert!((frequencies[250] - 60.0).abs() < 0.1);
+        assert!((frequencies[750] - 40.0).abs() < 0.1);
+
+        let mut plain = Oscillator::default();
+        let unmodulated = modulated(Modulation::Off, 1.0, 0.2);
+        let before = plain.phase;
+        plain.next(unmodulated, 0.25, DT);
+        assert!(((plain.phase - before) / (TAU * DT) - 50.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,129 @@
+//! Writing frames out to disk.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use crate::capture::Frame;
+
+/// Save an RGBA buffer as a PNG, creating the parent directory if needed.
+pub(crate) fn save_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("couldn't create {}: {e}", dir.display()))?;
+        }
+    }
+    image::save_buffer(path, rgba, width, height, image::ColorType::Rgba8)
+        .map_err(|e| format!("couldn't write {}: {e}", path.display()))
+}
+
+/// Frames waiting to be written before new ones get dropped.
+const RECORD_QUEUE: usize = 64;
+
+/// Writes every frame it's given into a directory as `frame_000001.png`,
+/// `frame_000002.png` and so on, on a worker thread so the event loop never
+/// waits on the disk.
+pub(crate) struct Recorder {
+    dir: PathBuf,
+    frames: Option<SyncSender<Frame>>,
+    worker: Option<JoinHandle<u32>>,
+    dropped: u32,
+}
+impl Recorder {
+    /// Start recording into `dir`, which is created if need be.
+    pub fn start(dir: PathBuf) -> Recorder {
+        let (frames, queue) = mpsc::sync_channel::<Frame>(RECORD_QUEUE);
+        let worker_dir = dir.clone();
+        let worker = std::thread::spawn(move || {
+            // Numbered as written, so a dropped frame doesn't leave a gap.
+            let mut written = 0;
+            for frame in queue {
+                let path = worker_dir.join(format!("frame_{:06}.png", written + 1));
+                match save_png(&path, frame.width, frame.height, &frame.rgba) {
+                    Ok(()) => written += 1,
+                    Err(e) => log::error!("recording: {e}"),
+                }
+            }
+            written
+        });
+        Recorder {
+            dir,
+            frames: Some(frames),
+            worker: Some(worker),
+            dropped: 0,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Queue a frame for writing, dropping it if the disk can't keep up.
+    pub fn push(&mut self, frame: Frame) {
+        if let Some(frames) = &self.frames {
+            if let Err(TrySendError::Full(_)) = frames.try_send(frame) {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Finish writing everything queued and stop. Returns how many frames
+    /// were written.
+    pub fn stop(mut self) -> u32 {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> u32 {
+        // Closing the channel ends the worker's loop.
+        self.frames = None;
+        let written = self
+            .worker
+            .take()
+            .map_or(0, |worker| worker.join().unwrap_or(0));
+        if self.dropped > 0 {
+            log::warn!("recording dropped {} frames", self.dropped);
+        }
+        written
+    }
+}
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_three_frames_writes_three_numbered_files() {
+        let dir = std::env::temp_dir().join(format!("kontawa-recorder-{}", std::process::id()));
+        let mut recorder = Recorder::start(dir.clone());
+        for shade in [0x00, 0x80, 0xff] {
+            recorder.push(Frame {
+                width: 2,
+                height: 2,
+                rgba: vec![shade; 2 * 2 * 4],
+            });
+        }
+        assert_eq!(recorder.stop(), 3);
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["frame_000001.png", "frame_000002.png", "frame_000003.png"]
+        );
+        let last = image::open(dir.join("frame_000003.png"))
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(last.dimensions(), (2, 2));
+        assert!(last.pixels().all(|pixel| pixel.0 == [0xff; 4]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,7 +5,15 @@ use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
 use egui_winit::winit::{self, event_loop::EventLoopWindowTarget, window::Window};
 use pixels::{wgpu, PixelsContext};
 
-use crate::SimParams;
+use crate::audio::{self, Channels, DevicePref, FftConfig, MagnitudeTransform};
+use crate::emitter::{Emitter, Modulation, Waveform};
+use crate::injection::{self, FreqScale, SpectrumMapping};
+use crate::paint::{self, BrushTarget, PaintMode};
+use crate::{export, SimParams};
+use kontawa::render::{self, Palette, SolidStyle, TrailMode};
+use kontawa::simulation::{CellRect, EdgeMode, Material, MAX_STABLE_ALPHA};
+use kontawa::timing::SPEED_RANGE;
+use kontawa::waterfall::Waterfall;
 
 /// Manages all state required for rendering egui over `Pixels`.
 pub(crate) struct Framework {
@@ -21,14 +29,51 @@ pub(crate) struct Framework {
     gui: Gui,
 }
 
+/// Measurements taken from the world for display this frame.
+#[derive(Default)]
+pub(crate) struct Readouts {
+    /// Total pressure over the charge region, one sample per tick, oldest
+    /// first.
+    pub charge: Vec<f32>,
+    /// Total energy, one sample per tick, oldest first. Empty unless it's
+    /// being tracked.
+    pub energy: Vec<f32>,
+    /// The brush footprint around the cursor, in physical window pixels.
+    /// Empty when the cursor is off the picture.
+    pub brush_outline: Vec<(f32, f32)>,
+    /// The field has blown up, and how many cells the last tick zeroed.
+    pub diverged: bool,
+    pub sanitized: usize,
+    /// What's in the cell under the cursor, if it's over the picture.
+    pub probe: Option<Probe>,
+}
+
+/// One cell's state, read out under the cursor.
+pub(crate) struct Probe {
+    /// As displayed, so `y` follows `SimParams::y_up`.
+    pub cell: (usize, usize),
+    pub pressure: f32,
+    pub velocity: (f32, f32),
+}
+
 struct Gui {
     /// Only show the egui window when true.
     window_open: bool,
 
     params: Arc<Mutex<SimParams>>,
+    channels: Arc<Channels>,
+    /// Simulation grid size, in cells.
+    grid: (usize, usize),
+    /// Input devices found at startup.
+    input_devices: Vec<String>,
+
+    /// Recent injected spectra, and the texture showing them.
+    waterfall: Waterfall,
+    waterfall_texture: Option<egui::TextureHandle>,
 }
 
 impl Framework {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new<T>(
         event_loop: &EventLoopWindowTarget<T>,
         width: u32,
@@ -37,6 +82,8 @@ impl Framework {
         pixels: &pixels::Pixels,
 
         params: Arc<Mutex<SimParams>>,
+        channels: Arc<Channels>,
+        grid: (usize, usize),
     ) -> Self {
         let max_texture_size = pixels.device().limits().max_texture_dimension_2d as usize;
 
@@ -51,7 +98,7 @@ impl Framework {
         };
         let renderer = Renderer::new(pixels.device(), pixels.render_texture_format(), None, 1);
         let textures = TexturesDelta::default();
-        let gui = Gui::new(params);
+        let gui = Gui::new(params, channels, grid);
 
         Self {
             egui_ctx,
@@ -81,13 +128,19 @@ impl Framework {
         self.screen_descriptor.pixels_per_point = scale_factor as f32;
     }
 
+    /// Whether egui is using the mouse, so it shouldn't reach the world.
+    pub(crate) fn wants_pointer_input(&self) -> bool {
+        self.egui_ctx.wants_pointer_input()
+    }
+
     /// Prepare egui.
-    pub(crate) fn prepare(&mut self, window: &Window) {
+    pub(crate) fn prepare(&mut self, window: &Window, readouts: &Readouts) {
         // Run the egui frame and create all paint jobs to prepare for rendering.
         let raw_input = self.egui_state.take_egui_input(window);
         let output = self.egui_ctx.run(raw_input, |egui_ctx| {
             // Draw the demo application.
-            self.gui.ui(egui_ctx);
+            self.gui.ui(egui_ctx, readouts);
+            draw_brush_outline(egui_ctx, &readouts.brush_outline);
         });
 
         self.textures.append(output.textures_delta);
@@ -145,15 +198,20 @@ impl Framework {
 
 impl Gui {
     /// Create a `Gui`.
-    fn new(params: Arc<Mutex<SimParams>>) -> Self {
+    fn new(params: Arc<Mutex<SimParams>>, channels: Arc<Channels>, grid: (usize, usize)) -> Self {
         Self {
             window_open: true,
             params,
+            channels,
+            grid,
+            input_devices: audio::list_input_devices(),
+            waterfall: Waterfall::new(256),
+            waterfall_texture: None,
         }
     }
 
     /// Create the UI using egui.
-    fn ui(&mut self, ctx: &Context) {
+    fn ui(&mut self, ctx: &Context, readouts: &Readouts) {
         egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
@@ -166,6 +224,44 @@ impl Gui {
         });
 
         let mut params = self.params.lock().unwrap();
+        let spectrum = self
+            .channels
+            .subscriber(Channels::SPECTRUM, |spectra| spectra.front().clone());
+        // Nothing else reads the level, so it's ours to flip.
+        let level = self.channels.subscriber(Channels::LEVEL, |levels| {
+            levels.flip();
+            levels.front().first().copied()
+        });
+
+        if !spectrum.is_empty() {
+            self.waterfall.push(
+                (0..spectrum.len())
+                    .map(|i| {
+                        injection::point_pressure(
+                            &spectrum,
+                            i,
+                            spectrum.len(),
+                            params.freq_scale,
+                            params.spectrum_mapping,
+                            params.spectrum_floor,
+                            params.injection_gain,
+                        )
+                    })
+                    .collect(),
+            );
+            let (width, height) = self.waterfall.dimensions();
+            let rgba = self.waterfall.to_rgba(params.non_finite_color);
+            let image = egui::ColorImage::from_rgba_unmultiplied([width, height], &rgba);
+            match &mut self.waterfall_texture {
+                Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+                None => {
+                    self.waterfall_texture =
+                        Some(ctx.load_texture("waterfall", image, egui::TextureOptions::NEAREST))
+                }
+            }
+        }
+        let waterfall = &self.waterfall;
+        let waterfall_texture = &self.waterfall_texture;
 
         egui::Window::new("\u{F1924}")
             .open(&mut self.window_open)
@@ -174,20 +270,687 @@ impl Gui {
 
                 ui.separator();
 
+                if let Some(probe) = &readouts.probe {
+                    let (x, y) = probe.cell;
+                    let (vx, vy) = probe.velocity;
+                    ui.monospace(format!(
+                        "({x}, {y}) p {:+.4} v ({vx:+.4}, {vy:+.4})",
+                        probe.pressure
+                    ));
+                }
+
                 ui.add(
-                    egui::Slider::new(&mut params.grad_alpha, 0.0..=1.0)
+                    egui::Slider::new(&mut params.wave.grad_alpha, 0.0..=MAX_STABLE_ALPHA)
                         .logarithmic(true)
                         .text("󱥵󱤈󱤝"),
                 );
+                if readouts.diverged {
+                    let text = format!("\u{F1948} {}", readouts.sanitized);
+                    ui.colored_label(egui::Color32::RED, text);
+                }
                 ui.add(
-                    egui::Slider::new(&mut params.grad_damping, 0.0..=1.0)
-                        .logarithmic(true)
+                    egui::Slider::new(&mut params.wave.grad_damping, 0.9..=1.0)
+                        .fixed_decimals(5)
                         .text("󱥵󱥶"),
                 );
+                if ui.button("\u{F195D}").clicked() {
+                    reset_params(&mut params);
+                }
+                ui.checkbox(&mut params.wave.advection, "\u{F1969}\u{F1969}");
+                ui.horizontal(|ui| {
+                    for mode in [
+                        EdgeMode::Zero,
+                        EdgeMode::Radiating,
+                        EdgeMode::Absorb { thickness: 16 },
+                        EdgeMode::Reflect,
+                        EdgeMode::Periodic,
+                    ] {
+                        let selected = params.wave.edge_mode.name() == mode.name();
+                        if ui.selectable_label(selected, mode.name()).clicked() && !selected {
+                            params.wave.edge_mode = mode;
+                        }
+                    }
+                    if let EdgeMode::Absorb { thickness } = &mut params.wave.edge_mode {
+                        ui.add(egui::DragValue::new(thickness).clamp_range(1..=256));
+                    }
+                });
+
+                ui.add(
+                    egui::Slider::new(&mut params.speed, SPEED_RANGE)
+                        .logarithmic(true)
+                        .suffix("×")
+                        .text("\u{F196B}\u{F1969}"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut params.substeps, 1..=crate::MAX_SUBSTEPS)
+                        .text("\u{F196B}\u{F193D}"),
+                );
+
+                ui.separator();
+
+                let selected = match &params.input_device {
+                    DevicePref::Default => "Default",
+                    DevicePref::ByName(name) => name,
+                };
+                egui::ComboBox::from_label("\u{F190E}\u{F1920}")
+                    .selected_text(selected.to_string())
+                    .show_ui(ui, |ui| {
+                        let default = DevicePref::Default;
+                        ui.selectable_value(&mut params.input_device, default, "Default");
+                        for name in &self.input_devices {
+                            ui.selectable_value(
+                                &mut params.input_device,
+                                DevicePref::ByName(name.clone()),
+                                name,
+                            );
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("fft_size")
+                        .selected_text(params.fft_size.to_string())
+                        .show_ui(ui, |ui| {
+                            for size in FftConfig::SIZES {
+                                ui.selectable_value(&mut params.fft_size, size, size.to_string());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut params.fft_overlap, 0.0..=0.95));
+                });
+                ui.add(
+                    egui::Slider::new(&mut params.spectrum_smoothing, 0.0..=0.99).text("\u{F1908}"),
+                );
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("magnitude_transform")
+                        .selected_text(params.magnitude_transform.name())
+                        .show_ui(ui, |ui| {
+                            // Keep the floors already set when switching back.
+                            let log = match params.magnitude_transform {
+                                log @ MagnitudeTransform::Log { .. } => log,
+                                _ => MagnitudeTransform::default(),
+                            };
+                            let db = match params.magnitude_transform {
+                                db @ MagnitudeTransform::Db { .. } => db,
+                                _ => MagnitudeTransform::Db {
+                                    floor_db: MagnitudeTransform::DEFAULT_DB_FLOOR,
+                                },
+                            };
+                            for transform in [
+                                MagnitudeTransform::Linear,
+                                log,
+                                db,
+                                MagnitudeTransform::Sqrt,
+                            ] {
+                                ui.selectable_value(
+                                    &mut params.magnitude_transform,
+                                    transform,
+                                    transform.name(),
+                                );
+                            }
+                        });
+                    match &mut params.magnitude_transform {
+                        MagnitudeTransform::Log { floor } => {
+                            ui.add(
+                                egui::DragValue::new(floor)
+                                    .clamp_range(1e-12..=1.0)
+                                    .speed(1e-7),
+                            );
+                        }
+                        MagnitudeTransform::Db { floor_db } => {
+                            ui.add(
+                                egui::DragValue::new(floor_db)
+                                    .clamp_range(-200.0..=0.0)
+                                    .suffix(" dB"),
+                            );
+                        }
+                        _ => {}
+                    }
+                    ui.label("\u{F193F}\u{F1963}");
+                });
+                ui.add(
+                    egui::Slider::new(&mut params.spectrum_floor, -20.0..=10.0)
+                        .text("\u{F1905}\u{F1915}"),
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.noise_gate, "\u{F192F}");
+                    ui.add_enabled(
+                        params.noise_gate,
+                        egui::DragValue::new(&mut params.gate_threshold).speed(0.1),
+                    );
+                    ui.add_enabled(
+                        params.noise_gate,
+                        egui::DragValue::new(&mut params.gate_hysteresis)
+                            .clamp_range(0.0..=f32::MAX)
+                            .speed(0.05)
+                            .prefix("± "),
+                    );
+                });
+                ui.add(
+                    egui::Slider::new(&mut params.max_injection_rate, 0.0..=240.0)
+                        .suffix(" Hz")
+                        .text("\u{F193D}\u{F196B}"),
+                );
+                egui::ComboBox::from_label("\u{F193F}\u{F1915}")
+                    .selected_text(format!("{:?}", params.spectrum_mapping))
+                    .show_ui(ui, |ui| {
+                        for mapping in [
+                            SpectrumMapping::Wrap,
+                            SpectrumMapping::Clamp,
+                            SpectrumMapping::Resample,
+                        ] {
+                            ui.selectable_value(
+                                &mut params.spectrum_mapping,
+                                mapping,
+                                format!("{mapping:?}"),
+                            );
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    for scale in [FreqScale::Linear, FreqScale::Log, FreqScale::Mel] {
+                        ui.selectable_value(&mut params.freq_scale, scale, format!("{scale:?}"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let fft_size = params.fft_size;
+                    ui.add(
+                        egui::DragValue::new(&mut params.spectrum_bands).clamp_range(0..=fft_size),
+                    );
+                    ui.label("\u{F193D}\u{F191F}");
+                });
+                level_meter(ui, level);
+                spectrum_bars(ui, &spectrum, params.spectrum_floor);
+                spectrum_plot(ui, &spectrum, params.spectrum_floor);
+                if let Some(texture) = waterfall_texture {
+                    ui.image(texture, [ui.available_width(), 96.0]);
+                }
+                if ui.button("\u{F192A}").clicked() {
+                    export_waterfall(waterfall, params.non_finite_color);
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.drawing_injection_path, "\u{F1929}\u{F1915}");
+                    ui.label(params.injection_path.len().to_string());
+                    if ui.button("\u{F1976}").clicked() {
+                        params.injection_path.clear();
+                    }
+                });
+                let y_up = params.y_up;
+                let grid = self.grid;
+                region_editor(ui, "\u{F1920}", &mut params.listening_region, grid, y_up);
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.audio_output, "\u{F1915}");
+                    ui.add(
+                        egui::Slider::new(&mut params.output_gain, 0.0..=16.0)
+                            .logarithmic(true)
+                            .text("\u{F1920}\u{F1963}"),
+                    );
+                });
+                region_editor(ui, "\u{F1953}", &mut params.charge_region, grid, y_up);
+                if params.charge_region.is_some() {
+                    history_plot(ui, "charge", &readouts.charge);
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.track_energy, "\u{F1975}");
+                    if let Some(energy) = readouts.energy.last() {
+                        ui.monospace(format!("{energy:.4e}"));
+                    }
+                });
+                if params.track_energy {
+                    history_plot(ui, "energy", &readouts.energy);
+                }
+                region_editor(ui, "\u{F1915}", &mut params.injection_region, grid, y_up);
+                ui.add(
+                    egui::Slider::new(&mut params.injection_gain, 0.0..=4.0)
+                        .text("\u{F1915}\u{F1963}"),
+                );
+                region_editor(ui, "\u{F191B}", &mut params.dense_region, grid, y_up);
+                if params.dense_region.is_some() {
+                    ui.add(
+                        egui::Slider::new(&mut params.dense_stiffness, 0.01..=1.0)
+                            .logarithmic(true)
+                            .text("\u{F191B}"),
+                    );
+                }
+
+                ui.separator();
+
+                ui.add(
+                    egui::Slider::new(&mut params.emitter_freq, 0.05..=20.0)
+                        .logarithmic(true)
+                        .suffix(" Hz")
+                        .text("\u{F196B}\u{F1915}"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut params.emitter_amp, 0.0..=5.0)
+                        .text("\u{F1975}\u{F1915}"),
+                );
+                waveform_picker(ui, &mut params.emitter_waveform);
+                ui.horizontal(|ui| {
+                    for modulation in Modulation::ALL {
+                        ui.selectable_value(
+                            &mut params.emitter_modulation,
+                            modulation,
+                            format!("{modulation:?}"),
+                        );
+                    }
+                });
+                ui.add_enabled(
+                    params.emitter_modulation != Modulation::Off,
+                    egui::Slider::new(&mut params.emitter_mod_freq, 0.01..=10.0)
+                        .logarithmic(true)
+                        .suffix(" Hz")
+                        .text("\u{F196B}\u{F1915} \u{F193E}"),
+                );
+                ui.add_enabled(
+                    params.emitter_modulation != Modulation::Off,
+                    egui::Slider::new(&mut params.emitter_mod_depth, 0.0..=1.0)
+                        .text("\u{F1975}\u{F1915} \u{F193E}"),
+                );
+
+                // Unticking an emitter's region removes it.
+                params.emitters.retain_mut(|emitter| {
+                    let mut region = Some(emitter.region);
+                    ui.push_id(ui.next_auto_id(), |ui| {
+                        region_editor(ui, "\u{F1915}", &mut region, grid, y_up);
+                        ui.horizontal(|ui| {
+                            let tone = &mut emitter.tone;
+                            ui.add(
+                                egui::DragValue::new(&mut tone.freq)
+                                    .clamp_range(0.01..=20.0)
+                                    .speed(0.01)
+                                    .suffix(" Hz"),
+                            );
+                            ui.add(egui::DragValue::new(&mut tone.amp).speed(0.01));
+                            ui.add(
+                                egui::DragValue::new(&mut emitter.phase)
+                                    .clamp_range(0.0..=std::f32::consts::TAU)
+                                    .speed(0.01)
+                                    .suffix(" rad"),
+                            );
+                        });
+                        waveform_picker(ui, &mut emitter.tone.waveform);
+                    });
+                    match region {
+                        Some(region) => {
+                            emitter.region = region;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+                if ui.button("+ \u{F1915}").clicked() {
+                    let region = CellRect {
+                        x: grid.0 / 2,
+                        y: grid.1 / 2,
+                        width: 2,
+                        height: 2,
+                    };
+                    let emitter = Emitter::new(region, params.emitter_freq, 1.0);
+                    params.emitters.push(emitter);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.membrane, "\u{F1958}");
+                    ui.add(
+                        egui::Slider::new(&mut params.membrane_tension, 0.0..=20.0)
+                            .logarithmic(true)
+                            .text("\u{F1958}\u{F1975}"),
+                    );
+                });
+
+                ui.separator();
+
+                ui.checkbox(&mut params.animate_bar, "\u{F191B}\u{F1969}");
+                ui.checkbox(&mut params.seed_on_reset, "\u{F1915}\u{F1947}");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut params.noise_seed));
+                    ui.add(egui::DragValue::new(&mut params.noise_amplitude).speed(0.01));
+                    ui.add(egui::DragValue::new(&mut params.noise_smoothness).clamp_range(0..=32));
+                    ui.label("\u{F193E}");
+                });
+
+                egui::ComboBox::from_label("\u{F193F}\u{F191B}")
+                    .selected_text(format!("{:?}", params.solid_style))
+                    .show_ui(ui, |ui| {
+                        for style in [SolidStyle::Flat, SolidStyle::Checker, SolidStyle::Hatch] {
+                            ui.selectable_value(
+                                &mut params.solid_style,
+                                style,
+                                format!("{style:?}"),
+                            );
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(&mut params.solid_color);
+                    ui.color_edit_button_srgb(&mut params.emitter_color);
+                    ui.label("\u{F191E}");
+                });
+                ui.add(
+                    egui::Slider::new(&mut params.boundary_blend, 0.0..=1.0)
+                        .text("\u{F1929}\u{F191B}"),
+                );
+                ui.horizontal(|ui| {
+                    for mode in [TrailMode::Off, TrailMode::Max, TrailMode::Average] {
+                        ui.selectable_value(&mut params.trail, mode, format!("{mode:?}"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("palette")
+                        .selected_text(params.palette.name())
+                        .show_ui(ui, |ui| {
+                            let bipolar = match params.palette {
+                                bipolar @ Palette::Bipolar { .. } => bipolar,
+                                _ => Palette::BIPOLAR,
+                            };
+                            for palette in [
+                                Palette::RedBlue,
+                                bipolar,
+                                Palette::Viridis,
+                                Palette::Grayscale,
+                                Palette::Turbo,
+                            ] {
+                                ui.selectable_value(&mut params.palette, palette, palette.name());
+                            }
+                        });
+                    if let Palette::Bipolar {
+                        positive,
+                        negative,
+                        midpoint,
+                    } = &mut params.palette
+                    {
+                        ui.color_edit_button_srgb(positive);
+                        ui.color_edit_button_srgb(negative);
+                        ui.add(egui::DragValue::new(midpoint).speed(0.01));
+                    }
+                    ui.add(
+                        egui::DragValue::new(&mut params.palette_fade)
+                            .clamp_range(0.0..=10.0)
+                            .speed(0.01)
+                            .suffix(" s"),
+                    );
+                });
+                ui.add(
+                    egui::Slider::new(&mut params.display_gain, 0.1..=100.0)
+                        .logarithmic(true)
+                        .text("\u{F191E} \u{F1963}"),
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.velocity_overlay, "\u{F1969}\u{F191E}");
+                    ui.add_enabled(
+                        params.velocity_overlay,
+                        egui::DragValue::new(&mut params.velocity_stride).clamp_range(1..=64),
+                    );
+                    ui.add_enabled(
+                        params.velocity_overlay,
+                        egui::Slider::new(&mut params.velocity_opacity, 0.0..=1.0),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.auto_gain, "\u{F1963}\u{F191E}");
+                    ui.add_enabled(
+                        params.auto_gain,
+                        egui::Checkbox::new(&mut params.lock_scale, "\u{F1908}"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(&mut params.non_finite_color);
+                    ui.label("\u{F191E}\u{F193E}");
+                });
+                ui.horizontal(|ui| {
+                    for mode in [PaintMode::Set, PaintMode::Erase, PaintMode::Toggle] {
+                        ui.selectable_value(&mut params.paint_mode, mode, format!("{mode:?}"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("brush_material")
+                        .selected_text(params.brush_material.name())
+                        .show_ui(ui, |ui| {
+                            for material in [Material::Fluid, Material::Solid, Material::Emitter] {
+                                let name = material.name();
+                                ui.selectable_value(&mut params.brush_material, material, name);
+                            }
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut params.brush_radius, paint::BRUSH_RADIUS_RANGE)
+                            .text("\u{F1963}"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let targets = [
+                        (BrushTarget::Material, "\u{F191B}"),
+                        (BrushTarget::Params, "\u{F1930}"),
+                        (BrushTarget::Damping, "\u{F1976}"),
+                    ];
+                    for (target, label) in targets {
+                        ui.selectable_value(&mut params.brush_target, target, label);
+                    }
+                });
+                let painting_params = params.brush_target == BrushTarget::Params;
+                ui.add_enabled(
+                    painting_params,
+                    egui::Slider::new(&mut params.brush_grad_alpha, 0.0..=MAX_STABLE_ALPHA)
+                        .logarithmic(true)
+                        .text("\u{F1930}\u{F1975}\u{F1908}\u{F191D}"),
+                );
+                ui.add_enabled(
+                    painting_params,
+                    egui::Slider::new(&mut params.brush_grad_damping, 0.0..=1.0)
+                        .text("\u{F1930}\u{F1975}\u{F1976}"),
+                );
+                ui.add_enabled(
+                    params.brush_target == BrushTarget::Damping,
+                    egui::Slider::new(&mut params.brush_damping, 0.5..=1.0).text("\u{F1976}"),
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut params.capture, "\u{F1960}");
+                    ui.add(
+                        egui::DragValue::new(&mut params.capture_threshold)
+                            .clamp_range(0.0..=f32::MAX)
+                            .speed(1.0),
+                    );
+                    ui.add(egui::DragValue::new(&mut params.capture_pre_roll).prefix("- "));
+                    ui.add(egui::DragValue::new(&mut params.capture_post_roll).prefix("+ "));
+                });
+                ui.checkbox(&mut params.y_up, "y \u{2191}");
+                ui.checkbox(&mut params.fit_window, "\u{F1963}");
+                ui.checkbox(&mut params.snap_window, "\u{F1963} \u{F193D}");
             });
     }
 }
 
+/// Save the waterfall in the working directory as a timestamped PNG.
+fn export_waterfall(waterfall: &Waterfall, non_finite: [u8; 3]) {
+    let (width, height) = waterfall.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = std::path::PathBuf::from(format!("waterfall-{stamp}.png"));
+    match export::save_png(
+        &path,
+        width as u32,
+        height as u32,
+        &waterfall.to_rgba(non_finite),
+    ) {
+        Ok(()) => log::info!("saved {}", path.display()),
+        Err(e) => log::error!("{e}"),
+    }
+}
+
+fn waveform_picker(ui: &mut egui::Ui, waveform: &mut Waveform) {
+    ui.horizontal(|ui| {
+        for shape in Waveform::ALL {
+            ui.selectable_value(waveform, shape, format!("{shape:?}"));
+        }
+    });
+}
+
+/// A checkbox turning an optional region on and off, and fields to move
+/// and size it once it's on.
+fn region_editor(
+    ui: &mut egui::Ui,
+    label: &str,
+    region: &mut Option<CellRect>,
+    grid: (usize, usize),
+    y_up: bool,
+) {
+    ui.horizontal(|ui| {
+        let mut enabled = region.is_some();
+        ui.checkbox(&mut enabled, label);
+        if enabled != region.is_some() {
+            *region = enabled.then_some(CellRect {
+                x: grid.0.saturating_sub(32) / 2,
+                y: grid.1.saturating_sub(32) / 2,
+                width: 32,
+                height: 32,
+            });
+        }
+        let Some(region) = region else {
+            return;
+        };
+
+        ui.add(egui::DragValue::new(&mut region.x).prefix("x "));
+        // With y up, the region's bottom row is its y.
+        let height = grid.1;
+        let bottom = region.y + region.height - 1;
+        let (mut shown, stored_row) = if y_up {
+            (render::display_y(bottom, height, true), bottom)
+        } else {
+            (region.y, region.y)
+        };
+        ui.add(
+            egui::DragValue::new(&mut shown)
+                .clamp_range(0..=height - 1)
+                .prefix("y "),
+        );
+        let row = render::display_y(shown, height, y_up);
+        if row != stored_row {
+            region.y = if y_up {
+                (row + 1).saturating_sub(region.height)
+            } else {
+                row
+            };
+        }
+        ui.add(egui::DragValue::new(&mut region.width).clamp_range(1..=usize::MAX));
+        ui.add(egui::DragValue::new(&mut region.height).clamp_range(1..=usize::MAX));
+    });
+}
+
+/// A line plot of a value sampled once per tick, oldest first.
+fn history_plot(ui: &mut egui::Ui, id: &str, history: &[f32]) {
+    use egui::plot::{Line, Plot, PlotPoints};
+
+    let points: PlotPoints = history
+        .iter()
+        .enumerate()
+        .map(|(i, v)| [i as f64, *v as f64])
+        .collect();
+
+    Plot::new(id)
+        .height(80.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| plot_ui.line(Line::new(points)));
+}
+
+/// Input RMS below this counts as no signal at all.
+const SILENCE: f32 = 1e-6;
+/// Bands in the compact spectrum display.
+const METER_BANDS: usize = 32;
+
+/// The input level on a bar running from -60 dB to full scale, or a note
+/// that nothing is coming in.
+fn level_meter(ui: &mut egui::Ui, rms: Option<f32>) {
+    match rms.filter(|&rms| rms > SILENCE) {
+        Some(rms) => {
+            let db = 20.0 * rms.log10();
+            ui.add(
+                egui::ProgressBar::new((db / 60.0 + 1.0).clamp(0.0, 1.0))
+                    .text(format!("{db:.0} dB")),
+            );
+        }
+        None => {
+            ui.label("\u{F1915}\u{F1902}");
+        }
+    }
+}
+
+/// The spectrum as a few bars, each as tall as its band is above the floor.
+fn spectrum_bars(ui: &mut egui::Ui, spectrum: &[f32], floor: f32) {
+    use egui::plot::{Bar, BarChart, Plot};
+
+    if spectrum.is_empty() {
+        return;
+    }
+    let bars = injection::downmix(spectrum, METER_BANDS)
+        .into_iter()
+        .enumerate()
+        .map(|(i, level)| {
+            let height = if level.is_finite() {
+                (level - floor).max(0.0)
+            } else {
+                0.0
+            };
+            Bar::new(i as f64, height as f64).width(0.8)
+        })
+        .collect();
+
+    Plot::new("spectrum_bars")
+        .height(40.0)
+        .show_axes([false, false])
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+}
+
+/// Plot the incoming spectrum with a line where the floor cuts it off.
+fn spectrum_plot(ui: &mut egui::Ui, spectrum: &[f32], floor: f32) {
+    use egui::plot::{HLine, Line, Plot, PlotPoints};
+
+    let points: PlotPoints = spectrum
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| v.is_finite())
+        .map(|(i, v)| [i as f64, *v as f64])
+        .collect();
+
+    Plot::new("spectrum")
+        .height(80.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(points));
+            plot_ui.hline(HLine::new(floor).color(egui::Color32::RED));
+        });
+}
+
+/// Outline the paint brush over the picture. `outline` is in physical
+/// pixels; egui wants points.
+fn draw_brush_outline(ctx: &egui::Context, outline: &[(f32, f32)]) {
+    if outline.is_empty() {
+        return;
+    }
+    let scale = ctx.pixels_per_point();
+    let points = outline
+        .iter()
+        .map(|&(x, y)| egui::pos2(x / scale, y / scale))
+        .collect();
+    ctx.layer_painter(egui::LayerId::background())
+        .add(egui::Shape::closed_line(
+            points,
+            (1.0, egui::Color32::WHITE),
+        ));
+}
+
+/// Everything back to how it starts.
+fn reset_params(params: &mut SimParams) {
+    *params = SimParams::default();
+}
+
 fn setup_custom_fonts(ctx: &egui::Context) {
     // Start with the default fonts (we will be adding to them rather than replacing them).
     let mut fonts = egui::FontDefinitions::default();
@@ -216,3 +979,24 @@ fn setup_custom_fonts(ctx: &egui::Context) {
     // Tell egui to use these fonts:
     ctx.set_fonts(fonts);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kontawa::simulation::WaveParams;
+
+    #[test]
+    fn reset_puts_the_wave_settings_back_to_their_defaults() {
+        let mut params = SimParams {
+            wave: WaveParams {
+                grad_alpha: 0.4,
+                grad_damping: 0.95,
+                ..WaveParams::default()
+            },
+            ..SimParams::default()
+        };
+        reset_params(&mut params);
+        assert_eq!(params.wave.grad_alpha, 0.1);
+        assert_eq!(params.wave.grad_damping, 0.9999);
+    }
+}
@@ -0,0 +1,312 @@
+//! Mapping the audio spectrum onto the pressure field.
+
+/// How spectrum bins are spread over injection points when there aren't
+/// exactly as many of one as the other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum SpectrumMapping {
+    /// Start the spectrum over from the lowest bin.
+    Wrap,
+    /// Hold the highest bin.
+    Clamp,
+    /// Stretch or squeeze the spectrum to fit, interpolating between bins.
+    #[default]
+    Resample,
+}
+
+/// How frequency runs along the injection points.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum FreqScale {
+    /// Bins in order, spread over the points by the `SpectrumMapping`.
+    #[default]
+    Linear,
+    /// Equal ratios of frequency get equal numbers of points, so the low end
+    /// isn't crammed into a corner.
+    Log,
+    /// Like `Log`, but following pitch perception: close to linear below
+    /// about 1 kHz.
+    Mel,
+}
+
+/// Nominal top of the spectrum for `Mel`, which needs real frequencies. The
+/// shape of the curve barely depends on it.
+const MEL_NYQUIST_HZ: f32 = 24_000.0;
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// The (fractional) bin shown at column `x` of `width`, running from the
+/// bottom of the spectrum at the first column to Nyquist, half of
+/// `spectrum_len`, at the last. The bins above Nyquist just mirror the ones
+/// below, so they're never used.
+pub fn bin_for_column(x: usize, width: usize, spectrum_len: usize, scale: FreqScale) -> f32 {
+    let nyquist = (spectrum_len / 2).max(1) as f32;
+    let t = if width < 2 {
+        0.0
+    } else {
+        x.min(width - 1) as f32 / (width - 1) as f32
+    };
+    match scale {
+        FreqScale::Linear => t * nyquist,
+        // Starts at bin 1: DC has no place on a log axis.
+        FreqScale::Log => nyquist.powf(t),
+        FreqScale::Mel => mel_to_hz(t * hz_to_mel(MEL_NYQUIST_HZ)) / MEL_NYQUIST_HZ * nyquist,
+    }
+}
+
+/// `spectrum` read at a fractional bin, interpolating between neighbors.
+pub fn sample_spectrum(spectrum: &[f32], pos: f32) -> f32 {
+    let last = spectrum.len() - 1;
+    let lo = (pos.max(0.0).floor() as usize).min(last);
+    let hi = (lo + 1).min(last);
+    let t = (pos - lo as f32).clamp(0.0, 1.0);
+    spectrum[lo] * (1.0 - t) + spectrum[hi] * t
+}
+
+/// Screens out spectrum frames of the wrong length (e.g. a partial frame
+/// published while the stream starts up), complaining about the first one.
+pub struct SpectrumCheck {
+    expected_len: usize,
+    warned: bool,
+}
+
+impl SpectrumCheck {
+    pub fn new(expected_len: usize) -> SpectrumCheck {
+        SpectrumCheck {
+            expected_len,
+            warned: false,
+        }
+    }
+
+    /// Expect spectra of `len` from now on, complaining afresh about the
+    /// first one that isn't.
+    pub fn expect(&mut self, len: usize) {
+        if len != self.expected_len {
+            self.expected_len = len;
+            self.warned = false;
+        }
+    }
+
+    /// Whether `spectrum` is fit to inject.
+    pub fn accept(&mut self, spectrum: &[f32]) -> bool {
+        if spectrum.len() == self.expected_len {
+            return true;
+        }
+        if !self.warned {
+            log::warn!(
+                "skipping spectrum with {} bins (expected {})",
+                spectrum.len(),
+                self.expected_len
+            );
+            self.warned = true;
+        }
+        false
+    }
+}
+
+/// Bins quieter than `floor` inject nothing, so background hiss doesn't keep
+/// the field jittering when there's no real signal.
+pub fn apply_floor(value: f32, floor: f32) -> f32 {
+    if value < floor {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// The spectrum value for the `i`th of `points` injection points.
+pub fn bin_value(spectrum: &[f32], i: usize, points: usize, mapping: SpectrumMapping) -> f32 {
+    let last = spectrum.len() - 1;
+    match mapping {
+        SpectrumMapping::Wrap => spectrum[i % spectrum.len()],
+        SpectrumMapping::Clamp => spectrum[i.min(last)],
+        SpectrumMapping::Resample => {
+            if points < 2 || last == 0 {
+                return spectrum[0];
+            }
+            let pos = i.min(points - 1) as f32 * last as f32 / (points - 1) as f32;
+            let lo = (pos.floor() as usize).min(last);
+            let hi = (lo + 1).min(last);
+            let t = pos - lo as f32;
+            spectrum[lo] * (1.0 - t) + spectrum[hi] * t
+        }
+    }
+}
+
+/// `spectrum` averaged down to `bands` contiguous bands of (nearly) equal
+/// width. With more bands than bins, bins are repeated.
+pub fn downmix(spectrum: &[f32], bands: usize) -> Vec<f32> {
+    let len = spectrum.len();
+    (0..bands)
+        .map(|b| {
+            let lo = (b * len / bands).min(len - 1);
+            let hi = ((b + 1) * len / bands).clamp(lo + 1, len);
+            spectrum[lo..hi].iter().sum::<f32>() / (hi - lo) as f32
+        })
+        .collect()
+}
+
+/// Which of `bands` equal, contiguous groups the `i`th of `points` injection
+/// points belongs to.
+pub fn band_of(i: usize, points: usize, bands: usize) -> usize {
+    (i * bands / points.max(1)).min(bands - 1)
+}
+
+/// The level for the `i`th of `points` injection points.
+pub fn level_at(
+    spectrum: &[f32],
+    i: usize,
+    points: usize,
+    mapping: SpectrumMapping,
+    floor: f32,
+) -> f32 {
+    apply_floor(bin_value(spectrum, i, points, mapping), floor)
+}
+
+/// The pressure written into the field for the `i`th of `points` injection
+/// points.
+pub fn injected_pressure(
+    spectrum: &[f32],
+    i: usize,
+    points: usize,
+    mapping: SpectrumMapping,
+    floor: f32,
+    gain: f32,
+) -> f32 {
+    to_pressure(level_at(spectrum, i, points, mapping, floor), gain)
+}
+
+/// Like [`injected_pressure`], but with frequency laid out along the points
+/// by `scale` instead of bin by bin.
+pub fn scaled_pressure(
+    spectrum: &[f32],
+    i: usize,
+    points: usize,
+    scale: FreqScale,
+    floor: f32,
+    gain: f32,
+) -> f32 {
+    let pos = bin_for_column(i, points, spectrum.len(), scale);
+    to_pressure(apply_floor(sample_spectrum(spectrum, pos), floor), gain)
+}
+
+/// The pressure for the `i`th of `points` injection points: laid out by
+/// `scale`, or bin by bin through `mapping` when that's linear.
+pub fn point_pressure(
+    spectrum: &[f32],
+    i: usize,
+    points: usize,
+    scale: FreqScale,
+    mapping: SpectrumMapping,
+    floor: f32,
+    gain: f32,
+) -> f32 {
+    match scale {
+        FreqScale::Linear => injected_pressure(spectrum, i, points, mapping, floor, gain),
+        _ => scaled_pressure(spectrum, i, points, scale, floor, gain),
+    }
+}
+
+fn to_pressure(level: f32, gain: f32) -> f32 {
+    let pres = level * gain;
+    if pres.is_finite() {
+        pres
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_zeroes_quiet_bins_only() {
+        let spectrum = [-30.0, -20.5, -19.5, 3.0];
+        let injected: Vec<f32> = (0..spectrum.len())
+            .map(|i| injected_pressure(&spectrum, i, 4, SpectrumMapping::Clamp, -20.0, 1.0))
+            .collect();
+        assert_eq!(injected, [0.0, 0.0, -19.5, 3.0]);
+        assert_eq!(apply_floor(-20.0, -20.0), -20.0);
+    }
+
+    #[test]
+    fn clamp_holds_the_last_bin_where_wrap_starts_over() {
+        let spectrum = [1.0, 2.0, 3.0];
+        let columns = |mapping| -> Vec<f32> {
+            (0..7)
+                .map(|i| bin_value(&spectrum, i, 7, mapping))
+                .collect()
+        };
+        assert_eq!(
+            columns(SpectrumMapping::Clamp),
+            [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 3.0]
+        );
+        assert_eq!(
+            columns(SpectrumMapping::Wrap),
+            [1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 1.0]
+        );
+        let resampled = columns(SpectrumMapping::Resample);
+        assert_eq!((resampled[0], resampled[3], resampled[6]), (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn short_spectrum_frames_are_skipped() {
+        let mut check = SpectrumCheck::new(8);
+        assert!(!check.accept(&[0.5; 4]));
+        assert!(!check.accept(&[]));
+        assert!(check.accept(&[0.5; 8]));
+        check.expect(4);
+        assert!(check.accept(&[0.5; 4]));
+        assert!(!check.warned);
+    }
+
+    #[test]
+    fn bands_split_the_columns_into_equal_groups() {
+        let spectrum = [1.0, 3.0, 5.0, 7.0, 2.0, 2.0, 0.0, 4.0];
+        let bands = downmix(&spectrum, 4);
+        assert_eq!(bands, [2.0, 6.0, 2.0, 2.0]);
+
+        for columns in [12, 20] {
+            let groups: Vec<usize> = (0..columns).map(|i| band_of(i, columns, 4)).collect();
+            for (band, group) in groups.chunks(columns / 4).enumerate() {
+                assert!(group.iter().all(|&b| b == band), "{groups:?}");
+            }
+        }
+        let carried: Vec<f32> = (0..12).map(|i| bands[band_of(i, 12, 4)]).collect();
+        assert_eq!(
+            carried,
+            [2.0, 2.0, 2.0, 6.0, 6.0, 6.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn log_and_mel_give_the_low_bins_more_columns() {
+        let (width, len) = (200, 1024);
+        let low_columns = |scale| {
+            (0..width)
+                .filter(|&x| bin_for_column(x, width, len, scale) < 64.0)
+                .count()
+        };
+        let linear = low_columns(FreqScale::Linear);
+        assert!(low_columns(FreqScale::Log) > 2 * linear);
+        assert!(low_columns(FreqScale::Mel) > linear);
+
+        for scale in [FreqScale::Linear, FreqScale::Log, FreqScale::Mel] {
+            let bins: Vec<f32> = (0..width)
+                .map(|x| bin_for_column(x, width, len, scale))
+                .collect();
+            assert!(bins.windows(2).all(|pair| pair[0] <= pair[1]), "{scale:?}");
+            // The last column is the top bin below Nyquist, never wrapped
+            // into the mirrored half.
+            assert!((bins[width - 1] - 512.0).abs() < 0.01, "{scale:?}");
+            assert_eq!(
+                bin_for_column(width + 10, width, len, scale),
+                bins[width - 1]
+            );
+        }
+    }
+}
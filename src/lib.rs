@@ -0,0 +1,8 @@
+#![deny(clippy::all)]
+#![forbid(unsafe_code)]
+
+pub mod noise;
+pub mod render;
+pub mod simulation;
+pub mod timing;
+pub mod waterfall;
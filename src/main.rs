@@ -1,84 +1,461 @@
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
-use crate::gui::Framework;
-use audio::DoubleBuffer;
+use crate::gui::{Framework, Probe, Readouts};
+use audio::{AudioOutput, AudioSource, Channels, DevicePref, FftConfig, MagnitudeTransform};
+use capture::{Frame, Trigger};
 use egui_winit::winit::{
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
     event::{Event, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
-use glam::Vec2;
+use emitter::{Emitter, Modulation, Oscillator, Tone, Waveform};
+use export::Recorder;
+use injection::{FreqScale, SpectrumCheck, SpectrumMapping};
+use kontawa::noise;
+use kontawa::render::{self, MaterialStyle, Palette, PaletteFade, SolidStyle, ToneMap, TrailMode};
+use kontawa::simulation::{
+    self, Array2D, CellRect, DampingRamp, LocalParams, Material, WaveField, WaveParams,
+};
+use kontawa::timing::{Clock, RateLimiter, StepAccumulator, StepGate, SystemClock};
 use log::error;
+use mask::ImageFit;
+use paint::{BrushTarget, PaintMode, Stroke};
 use pixels::{Error, Pixels, SurfaceTexture};
 use rayon::prelude::*;
-use simulation::Array2D;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use window_state::WindowGeometry;
 use winit_input_helper::WinitInputHelper;
 
 mod audio;
+mod capture;
+mod cli;
+mod config;
+mod emitter;
+mod export;
 mod gui;
-mod simulation;
+mod injection;
+mod mask;
+mod paint;
+mod state;
+mod sweep;
+mod wav;
+mod window_state;
 
-const WIDTH: u32 = 512;
-const HEIGHT: u32 = 512;
+/// Grid size, in cells, unless configured otherwise.
+const DEFAULT_WIDTH: u32 = 512;
+const DEFAULT_HEIGHT: u32 = 512;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum Material {
-    Fluid,
-    Solid,
-    Emitter,
-}
+/// Simulation steps per frame at normal speed, unless configured otherwise.
+const DEFAULT_SUBSTEPS: u32 = 3;
+/// Most substeps per frame that can be picked.
+const MAX_SUBSTEPS: u32 = 12;
+/// Frame rate the fixed timestep is built around.
+const FRAMES_PER_SECOND: f32 = 60.0;
+
+/// How many rows at the top of the grid the spectrum drives, unless an
+/// injection region is set.
+const DEFAULT_INJECTION_ROWS: usize = 4;
 
+/// Rows the moving bar sweeps along.
+const BAR_ROWS: std::ops::Range<isize> = 380..384;
+/// How far down the grid the optional membrane is stretched (a ring on
+/// screen), as a fraction of its height.
+const MEMBRANE_DEPTH: f32 = 0.4;
+
+/// Where F5 saves the whole simulation and F9 loads it from.
+const STATE_FILE: &str = "kontawa.state";
+
+#[derive(Clone)]
 struct SimParams {
-    grad_alpha: f32,
-    grad_damping: f32,
+    /// The physics, which [`WaveField`] steps with. Everything else here is
+    /// about what's around it: audio, injection, display and painting.
+    wave: WaveParams,
+    /// Where the audio comes from.
+    input_device: DevicePref,
+    /// Play what the listening region hears, with pressure scaled by
+    /// `output_gain` into samples.
+    audio_output: bool,
+    output_gain: f32,
+    /// Samples per FFT frame, and the fraction of each shared with the next.
+    fft_size: usize,
+    fft_overlap: f32,
+    /// How much of the previous spectrum each new one keeps, from 0 (none;
+    /// raw FFT output) toward 1 (frozen).
+    spectrum_smoothing: f32,
+    /// How the audio thread turns FFT magnitudes into spectrum levels.
+    magnitude_transform: MagnitudeTransform,
+    /// Spectrum bins below this (log magnitude) are injected as zero.
+    spectrum_floor: f32,
+    /// Zero spectrum bins in the audio thread until they reach
+    /// `gate_threshold`, and again once they fall `gate_hysteresis` below
+    /// it. In the same units as the spectrum.
+    noise_gate: bool,
+    gate_threshold: f32,
+    gate_hysteresis: f32,
+    /// How spectrum bins are spread over the injection points.
+    spectrum_mapping: SpectrumMapping,
+    /// Average the spectrum down to this many bands, each driving an equal
+    /// share of the injection points, so spectral detail doesn't depend on
+    /// the grid width. Zero injects bins directly.
+    spectrum_bands: usize,
+    /// How frequency runs along the injection points.
+    freq_scale: FreqScale,
+    /// Pressure injected per unit of spectrum level.
+    injection_gain: f32,
+    /// Most spectrum injections per second; zero for no limit.
+    max_injection_rate: f32,
+    /// Sweep a solid grating along the lower part of the field.
+    animate_bar: bool,
+    /// Start with a pulse in the middle of the field after a reset.
+    seed_on_reset: bool,
+    /// Settings for filling the field with noise (the N key).
+    noise_seed: u64,
+    noise_amplitude: f32,
+    noise_smoothness: usize,
+    solid_style: SolidStyle,
+    solid_color: [u8; 3],
+    emitter_color: [u8; 3],
+    /// How strongly fluid cells next to solids take on the solid color.
+    boundary_blend: f32,
+    palette: Palette,
+    /// Seconds to crossfade over when the palette changes.
+    palette_fade: f32,
+    /// What NaN and infinite cells are drawn as.
+    non_finite_color: [u8; 3],
+    /// How substeps within a frame are combined for display.
+    trail: TrailMode,
+    /// Pressure is multiplied by this before it's colored, to bring out
+    /// quiet regions.
+    display_gain: f32,
+    /// Tint the picture by velocity: direction as hue, speed as opacity, up
+    /// to `velocity_opacity`. Only every `velocity_stride`th cell each way
+    /// is shown, as a block.
+    velocity_overlay: bool,
+    velocity_stride: usize,
+    velocity_opacity: f32,
+    /// Scale colors to the field's current peak instead of a fixed range.
+    auto_gain: bool,
+    /// Hold the auto gain where it is.
+    lock_scale: bool,
+    /// Playback speed relative to real time.
+    speed: f32,
+    /// Simulation ticks per frame's worth of time at normal speed; each
+    /// simulated second is this many times `FRAMES_PER_SECOND` ticks.
+    substeps: u32,
+    /// Emitter frequency, in Hz.
+    emitter_freq: f32,
+    emitter_amp: f32,
+    emitter_waveform: Waveform,
+    emitter_modulation: Modulation,
+    /// Modulator frequency, in Hz, and depth as a fraction of what it
+    /// modulates.
+    emitter_mod_freq: f32,
+    emitter_mod_depth: f32,
+    /// Stretch a membrane across the field.
+    membrane: bool,
+    membrane_tension: f32,
+    /// What left-dragging does to the cells under the cursor; right-dragging
+    /// always erases.
+    paint_mode: PaintMode,
+    /// What the brush paints.
+    brush_material: Material,
+    /// How far, in cells, the paint brush reaches from the cursor.
+    brush_radius: usize,
+    /// What the brush paints: materials, parameter overrides or damping.
+    brush_target: BrushTarget,
+    /// The overrides painted when `brush_target` is `Params`.
+    brush_grad_alpha: f32,
+    brush_grad_damping: f32,
+    /// The damping painted when `brush_target` is `Damping`.
+    brush_damping: f32,
+    /// Save bursts of frames when the field gets loud.
+    capture: bool,
+    /// Total energy that sets off a capture when crossed from below. This is
+    /// [`WaveField::total_energy`], where velocity counts divided by alpha,
+    /// so a moving wave's energy is about twice its `0.5 * p²` alone; a bump
+    /// of height 1 and radius 3 comes to about 7.
+    capture_threshold: f32,
+    /// Frames kept from before the crossing, and captured after it.
+    capture_pre_roll: usize,
+    capture_post_roll: usize,
+    /// Keep the window a whole multiple of the grid size so cells line up
+    /// with screen pixels.
+    snap_window: bool,
+    /// Report coordinates with y counting up from the bottom.
+    y_up: bool,
+    /// Record the field's total energy every tick and plot it.
+    track_energy: bool,
+    /// Scale the picture up to fill the window (keeping its aspect ratio)
+    /// instead of sticking to whole-pixel multiples of the grid.
+    fit_window: bool,
+    /// How an obstacle image (`--materials`) is fitted to the grid, and the
+    /// color in it that marks emitters.
+    mask_fit: ImageFit,
+    mask_accent: [u8; 3],
+
+    /// Cells the spectrum is injected along, in order. Empty means the top
+    /// rows of the grid.
+    injection_path: Vec<(isize, isize)>,
+    /// Dragging the mouse extends `injection_path`.
+    drawing_injection_path: bool,
+    /// Where the spectrum is driven in, column by column, when there's no
+    /// injection path. `None` is the top few rows.
+    injection_region: Option<CellRect>,
+    /// The "virtual microphone": pressure averaged over this region is what
+    /// gets sonified.
+    listening_region: Option<CellRect>,
+    /// Total pressure over this region is tracked and plotted.
+    charge_region: Option<CellRect>,
+    /// Blocks of cells each driven by their own tone, on top of any painted
+    /// emitter cells.
+    emitters: Vec<Emitter>,
+    /// A block of denser (or, above 1, stiffer) medium, and how stiff.
+    dense_region: Option<CellRect>,
+    dense_stiffness: f32,
+}
+impl SimParams {
+    /// How many ticks make up a simulated second.
+    fn ticks_per_second(&self) -> f32 {
+        self.substeps.clamp(1, MAX_SUBSTEPS) as f32 * FRAMES_PER_SECOND
+    }
+
+    fn fft_config(&self) -> FftConfig {
+        FftConfig {
+            size: self.fft_size,
+            overlap: self.fft_overlap,
+        }
+    }
+
+    /// Everything a tick needs except the emitters, copied out so the lock
+    /// can be let go before stepping.
+    fn snapshot(&self) -> SimParamsSnapshot {
+        SimParamsSnapshot {
+            animate_bar: self.animate_bar,
+            membrane: self.membrane.then_some(self.membrane_tension),
+            dense: self.dense_region.map(|rect| (rect, self.dense_stiffness)),
+            ticks_per_second: self.ticks_per_second(),
+            tone: Tone {
+                freq: self.emitter_freq,
+                amp: self.emitter_amp,
+                waveform: self.emitter_waveform,
+                modulation: self.emitter_modulation,
+                mod_freq: self.emitter_mod_freq,
+                mod_depth: self.emitter_mod_depth,
+            },
+            wave_params: self.wave,
+            trail: self.trail,
+            listening_region: self.listening_region,
+            charge_region: self.charge_region,
+            track_energy: self.track_energy,
+        }
+    }
+}
+
+/// The [`SimParams`] one tick runs on, fixed for the whole tick so the GUI
+/// can change settings while the field steps.
+#[derive(Copy, Clone)]
+struct SimParamsSnapshot {
+    animate_bar: bool,
+    /// The membrane's tension, if there is one.
+    membrane: Option<f32>,
+    /// The dense region and its stiffness, if there is one.
+    dense: Option<(CellRect, f32)>,
+    ticks_per_second: f32,
+    tone: Tone,
+    wave_params: WaveParams,
+    trail: TrailMode,
+    listening_region: Option<CellRect>,
+    charge_region: Option<CellRect>,
+    track_energy: bool,
 }
 impl Default for SimParams {
     fn default() -> Self {
         SimParams {
-            grad_alpha: 0.1,
-            grad_damping: 0.9999,
+            wave: WaveParams::default(),
+            input_device: DevicePref::default(),
+            audio_output: false,
+            output_gain: 1.0,
+            fft_size: FftConfig::default().size,
+            fft_overlap: FftConfig::default().overlap,
+            spectrum_smoothing: 0.0,
+            magnitude_transform: MagnitudeTransform::default(),
+            spectrum_floor: -20.0,
+            noise_gate: false,
+            gate_threshold: -10.0,
+            gate_hysteresis: 0.5,
+            spectrum_mapping: SpectrumMapping::default(),
+            spectrum_bands: 0,
+            freq_scale: FreqScale::default(),
+            injection_gain: 0.5,
+            max_injection_rate: FRAMES_PER_SECOND,
+            animate_bar: true,
+            seed_on_reset: false,
+            noise_seed: 0,
+            noise_amplitude: 1.0,
+            noise_smoothness: 4,
+            solid_style: SolidStyle::Flat,
+            solid_color: render::SOLID_RGB,
+            emitter_color: render::EMITTER_RGB,
+            boundary_blend: 0.5,
+            palette: Palette::default(),
+            palette_fade: 0.5,
+            non_finite_color: render::NON_FINITE_RGB,
+            trail: TrailMode::default(),
+            display_gain: 1.0,
+            velocity_overlay: false,
+            velocity_stride: 8,
+            velocity_opacity: 0.6,
+            auto_gain: false,
+            lock_scale: false,
+            speed: 1.0,
+            substeps: DEFAULT_SUBSTEPS,
+            emitter_freq: 0.6,
+            emitter_amp: 2.5,
+            emitter_waveform: Waveform::default(),
+            emitter_modulation: Modulation::default(),
+            emitter_mod_freq: 0.1,
+            emitter_mod_depth: 0.5,
+            membrane: false,
+            membrane_tension: 1.0,
+            paint_mode: PaintMode::default(),
+            brush_material: Material::Solid,
+            brush_radius: 1,
+            brush_target: BrushTarget::default(),
+            brush_grad_alpha: 0.5,
+            brush_grad_damping: 0.9,
+            brush_damping: 0.95,
+            capture: false,
+            capture_threshold: 2000.0,
+            capture_pre_roll: 30,
+            capture_post_roll: 90,
+            snap_window: false,
+            y_up: false,
+            track_energy: false,
+            fit_window: false,
+            mask_fit: ImageFit::default(),
+            mask_accent: [0xff, 0x00, 0x00],
+            injection_path: Vec::new(),
+            drawing_injection_path: false,
+            listening_region: None,
+            charge_region: None,
+            injection_region: None,
+            emitters: Vec::new(),
+            dense_region: None,
+            dense_stiffness: 0.25,
         }
     }
 }
 
 struct World {
-    pressures: Array2D<f32>,
-    pressures_back: Array2D<f32>,
-    velocities: Array2D<glam::Vec2>,
-    velocities_back: Array2D<glam::Vec2>,
-    materials: Array2D<Material>,
+    field: WaveField,
     params: Arc<Mutex<SimParams>>,
     ticks: u32,
+    /// Simulated seconds are this many ticks long. Follows
+    /// `SimParams::substeps`.
+    ticks_per_second: f32,
+    /// Simulated seconds since the start.
+    time: f32,
+    /// Tension of the membrane currently in `materials`, if any.
+    membrane: Option<f32>,
+    /// What the membrane row held before the membrane went in, put back when
+    /// it comes out. Empty if that isn't known, as after loading a state.
+    under_membrane: Vec<Material>,
+    /// The dense region and stiffness currently in `local_params`, if any.
+    dense: Option<(CellRect, f32)>,
+    /// Mean pressure over the listening region, one sample per tick, oldest
+    /// first. Holds the last second's worth.
+    listened: VecDeque<f32>,
+    /// Total pressure over the charge region, one sample per tick, oldest
+    /// first. Holds the last ten seconds' worth.
+    charge: VecDeque<f32>,
+    /// Total energy, one sample per tick while `SimParams::track_energy` is
+    /// on, oldest first. Holds the last ten seconds' worth.
+    energy: VecDeque<f32>,
+    /// What palette fades are timed by.
+    clock: Box<dyn Clock + Send>,
+    /// Drawing only looks at the world, bar these two, which carry the
+    /// display from one frame to the next.
+    tone_map: RefCell<ToneMap>,
+    palette_fade: RefCell<PaletteFade>,
+    emitter: Oscillator,
+    /// One per entry in `SimParams::emitters`, by `Emitter::id`, with the
+    /// phase it was started at.
+    emitter_oscillators: HashMap<u64, (f32, Oscillator)>,
+    /// Whether the moving bar is currently in `materials`.
+    bar_drawn: bool,
+    /// Pressure combined over this frame's substeps, per `SimParams::trail`,
+    /// and how many substeps went into it.
+    trail: Array2D<f32>,
+    trail_samples: u32,
+    /// The materials loaded from an image, if any, and the damping ramps
+    /// laid down, all put back on every reset.
+    mask: Option<Array2D<Material>>,
+    damping_ramps: Vec<DampingRamp>,
 }
 
 fn main() -> Result<(), Error> {
-    let audio_dbuf = std::sync::Arc::new(DoubleBuffer::new([
-        Vec::new(), Vec::new()
-    ]));
-    let audio_dbuf_cloned = audio_dbuf.clone();
-    let audio_stream = audio::do_audio(audio_dbuf_cloned);
+    let args = cli::Args::parse();
+    if args.dump_config {
+        print!("{}", args.config.to_config_string());
+        if !args.keep_running {
+            return Ok(());
+        }
+    }
+    if args.config.warmup {
+        warm_up_thread_pool();
+    }
+    if let Some(sweep) = args.sweep() {
+        if let Err(e) = sweep.run(&args.config.params) {
+            eprintln!("sweep failed: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let grid = (args.config.grid_width, args.config.grid_height);
+    let channels = Arc::new(Channels::new());
+    let params = Arc::new(Mutex::new(args.config.params));
+    let source = match args.wav {
+        Some(path) => AudioSource::WavFile(path),
+        None => AudioSource::Microphone,
+    };
+    let mut audio_input =
+        audio::AudioInput::start(channels.clone(), source, args.config.audio, params.clone());
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
+    let geometry_path = WindowGeometry::default_path();
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        WindowBuilder::new()
+        let size = LogicalSize::new(grid.0 as f64, grid.1 as f64);
+        let builder = WindowBuilder::new()
             .with_title("kon tawa")
             .with_inner_size(size)
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
+            .with_min_inner_size(size);
+        let saved = match &geometry_path {
+            Some(path) if !args.reset_window && path.exists() => WindowGeometry::load(path)
+                .map_err(|e| log::warn!("ignoring saved window geometry: {e}"))
+                .ok(),
+            _ => None,
+        };
+        let builder = match saved {
+            Some(geometry) => builder
+                .with_inner_size(PhysicalSize::new(geometry.width, geometry.height))
+                .with_position(PhysicalPosition::new(geometry.x, geometry.y)),
+            None => builder,
+        };
+        builder.build(&event_loop).unwrap()
     };
 
-    let params = Arc::new(Mutex::new(SimParams::default()));
-
     let (mut pixels, mut framework) = {
         let window_size = window.inner_size();
         let scale_factor = window.scale_factor() as f32;
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        let pixels = Pixels::new(WIDTH, HEIGHT, surface_texture)?;
+        let pixels = Pixels::new(grid.0, grid.1, surface_texture)?;
         let framework = Framework::new(
             &event_loop,
             window_size.width,
@@ -86,32 +463,145 @@ fn main() -> Result<(), Error> {
             scale_factor,
             &pixels,
             params.clone(),
+            channels.clone(),
+            (grid.0 as usize, grid.1 as usize),
         );
 
         (pixels, framework)
     };
 
-    let mut world = World::new(params);
+    let mut world = World::new(params, grid.0 as usize, grid.1 as usize);
+    if let Some(path) = &args.materials {
+        if let Err(e) = world.load_materials_from_image(path) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+    for &ramp in &args.damping {
+        world.add_damping_ramp(ramp);
+    }
+    let mut buffer_size = grid;
+    let mut stepper = StepAccumulator::new(world.ticks_per_second, 6 * DEFAULT_SUBSTEPS);
+    let mut last_frame = world.clock.now();
+    let mut injection_limiter = RateLimiter::new();
+    let mut spectrum_check = SpectrumCheck::new(FftConfig::default().size);
+    let mut stroke: Option<Stroke> = None;
+    let mut last_hovered = None;
+    let mut snapped = false;
+    let max_ticks = args.max_ticks;
+    let mut reported_max_ticks = false;
+    let mut trigger = Trigger::new();
+    let mut recorder: Option<Recorder> = None;
+    let mut gate = StepGate::default();
+    let audio_settings = args.config.audio;
+    let mut audio_output: Option<AudioOutput> = None;
 
     event_loop.run(move |event, _, control_flow| {
         // Handle input events
         if input.update(&event) {
             // Close events
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                // The event loop never returns, so finish writing now.
+                if let Some(recording) = recorder.take() {
+                    recording.stop();
+                }
                 *control_flow = ControlFlow::Exit;
                 return;
             }
 
             if input.key_pressed(VirtualKeyCode::R) || input.quit() {
-                world = World::new(world.params.clone());
+                world.reset();
                 return;
             }
 
+            if input.key_pressed(VirtualKeyCode::N) {
+                let (seed, amplitude, smoothness) = {
+                    let params = world.params.lock().unwrap();
+                    (
+                        params.noise_seed,
+                        params.noise_amplitude,
+                        params.noise_smoothness,
+                    )
+                };
+                world.init_noise(seed, amplitude, smoothness);
+            }
+
+            if input.key_pressed(VirtualKeyCode::S) {
+                save_screenshot(&world);
+            }
+
+            // Save the whole simulation, or pick a saved one back up
+            if input.key_pressed(VirtualKeyCode::F5) {
+                match world.save(Path::new(STATE_FILE)) {
+                    Ok(()) => log::info!("saved the simulation to {STATE_FILE}"),
+                    Err(e) => error!("save failed: {e}"),
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F9) {
+                match world.load(Path::new(STATE_FILE)) {
+                    Ok(()) => log::info!("loaded the simulation from {STATE_FILE}"),
+                    Err(e) => error!("load failed: {e}"),
+                }
+            }
+
+            // Pause, and step one tick at a time while paused
+            if input.key_pressed(VirtualKeyCode::Space) {
+                gate.toggle_pause();
+                log::info!("{}", if gate.paused() { "paused" } else { "running" });
+            }
+            if input.key_pressed(VirtualKeyCode::Period) {
+                gate.request_step();
+            }
+
+            // Start or stop recording every frame
+            if input.key_pressed(VirtualKeyCode::V) {
+                match recorder.take() {
+                    Some(recording) => {
+                        let dir = recording.dir().to_path_buf();
+                        let written = recording.stop();
+                        log::info!("recorded {written} frames to {}", dir.display());
+                    }
+                    None => {
+                        let stamp = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map_or(0, |d| d.as_millis());
+                        let dir = Path::new("recordings").join(format!("recording-{stamp}"));
+                        log::info!("recording to {}", dir.display());
+                        recorder = Some(Recorder::start(dir));
+                    }
+                }
+            }
+
+            // Resize the brush
+            for (key, delta) in [
+                (VirtualKeyCode::LBracket, -1),
+                (VirtualKeyCode::RBracket, 1),
+            ] {
+                if input.key_pressed(key) {
+                    let mut params = world.params.lock().unwrap();
+                    params.brush_radius = paint::adjust_radius(params.brush_radius, delta);
+                }
+            }
+
             // Update the scale factor
             if let Some(scale_factor) = input.scale_factor() {
                 framework.scale_factor(scale_factor);
             }
 
+            // Keep the window a whole multiple of the grid if asked to;
+            // the resize this causes is handled just below.
+            let snap = world.params.lock().unwrap().snap_window;
+            let rescaled = input.scale_factor().is_some();
+            if snap && (input.window_resized().is_some() || rescaled || !snapped) {
+                let size = window.inner_size();
+                let (width, height) =
+                    render::snap_to_grid((size.width, size.height), grid, window.scale_factor());
+                if (width, height) != (size.width, size.height) {
+                    window.set_inner_size(PhysicalSize::new(width, height));
+                }
+            }
+            snapped = snap;
+
             // Resize the window
             if let Some(size) = input.window_resized() {
                 if let Err(err) = pixels.resize_surface(size.width, size.height) {
@@ -122,30 +612,188 @@ fn main() -> Result<(), Error> {
                 framework.resize(size.width, size.height);
             }
 
-            // Update internal state and request a redraw
-            audio_dbuf.flip();
-
-            {
-                let front = audio_dbuf.front();
-                if !front.is_empty() {
-                    for x in 0..WIDTH {
-                        let pres = front[x as usize % front.len()] * 0.5;
-                        let pres = if pres.is_finite() { pres } else { 0.0 };
-                        for y in 0..4 {
-                            *world.pressures.get_mut(x as isize, y).unwrap()
-                                = pres;
+            // Extend the injection path under the mouse
+            if input.mouse_held(0) && !framework.wants_pointer_input() {
+                let mut params = world.params.lock().unwrap();
+                if params.drawing_injection_path {
+                    let cell = input
+                        .mouse()
+                        .and_then(|pos| cursor_cell(&pixels, pos, buffer_size, grid));
+                    if let Some(cell) = cell {
+                        if params.injection_path.last() != Some(&cell) {
+                            params.injection_path.push(cell);
                         }
                     }
                 }
             }
 
-            world.update();
-            world.update();
-            world.update();
+            // Show the cell under the cursor in the title bar
+            let hovered = input
+                .mouse()
+                .and_then(|pos| cursor_cell(&pixels, pos, buffer_size, grid));
+            if hovered != last_hovered {
+                let title = match hovered {
+                    Some((x, y)) => {
+                        let y_up = world.params.lock().unwrap().y_up;
+                        let y = render::display_y(y as usize, grid.1 as usize, y_up);
+                        format!("kon tawa ({x}, {y})")
+                    }
+                    None => "kon tawa".to_string(),
+                };
+                window.set_title(&title);
+                last_hovered = hovered;
+            }
+
+            // Paint materials with the left mouse button (unless it's drawing
+            // the injection path) and erase them with the right
+            let over_gui = framework.wants_pointer_input();
+            if input.mouse_pressed(0) && !over_gui {
+                let params = world.params.lock().unwrap();
+                if !params.drawing_injection_path {
+                    stroke = Some(Stroke::new(params.paint_mode));
+                }
+            }
+            if input.mouse_pressed(1) && !over_gui {
+                stroke = Some(Stroke::new(PaintMode::Erase));
+            }
+            if input.mouse_released(0) || input.mouse_released(1) {
+                stroke = None;
+            }
+            if let Some(stroke) = &mut stroke {
+                let cell = input
+                    .mouse()
+                    .filter(|_| !over_gui)
+                    .and_then(|pos| cursor_cell(&pixels, pos, buffer_size, grid));
+                if let Some(center) = cell {
+                    let mode = stroke.mode;
+                    let (target, material, local, damping, radius) = {
+                        let params = world.params.lock().unwrap();
+                        let local = LocalParams {
+                            grad_alpha: Some(params.brush_grad_alpha),
+                            grad_damping: Some(params.brush_grad_damping),
+                            stiffness: None,
+                        };
+                        (
+                            params.brush_target,
+                            params.brush_material,
+                            local,
+                            params.brush_damping,
+                            params.brush_radius,
+                        )
+                    };
+                    for (x, y) in paint::brush_cells(center, radius) {
+                        if stroke.touch((x, y)) {
+                            match target {
+                                BrushTarget::Material => world.paint(x, y, material, mode),
+                                BrushTarget::Params => world.paint_params(x, y, local, mode),
+                                BrushTarget::Damping => world.paint_damping(x, y, damping, mode),
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Match the pixel buffer to the window if we're filling it
+            let wanted_size = if world.params.lock().unwrap().fit_window {
+                let window_size = window.inner_size();
+                let viewport =
+                    render::letterbox_viewport((window_size.width, window_size.height), grid);
+                (viewport.width, viewport.height)
+            } else {
+                grid
+            };
+            if wanted_size != buffer_size {
+                if let Err(err) = pixels.resize_buffer(wanted_size.0, wanted_size.1) {
+                    error!("pixels.resize_buffer() failed: {err}");
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                buffer_size = wanted_size;
+            }
+
+            // Update internal state and request a redraw
+            audio_input.poll();
+
+            // The audio thread publishes a spectrum on every FFT, so
+            // flipping only now and then picks up just the latest one.
+            let now = world.clock.now();
+            let (max_rate, fft_size) = {
+                let params = world.params.lock().unwrap();
+                (params.max_injection_rate, params.fft_size)
+            };
+            spectrum_check.expect(fft_size);
+            // Nothing gets in while paused, so the frozen field stays put.
+            if !gate.paused() && injection_limiter.ready(now, max_rate) {
+                channels.subscriber(Channels::SPECTRUM, |spectra| {
+                    spectra.flip();
+                    if spectrum_check.accept(spectra.front()) {
+                        world.inject_spectrum(spectra.front());
+                    }
+                });
+            }
+
+            let running = if gate.paused() {
+                0
+            } else {
+                world.ticks_due(&mut stepper, now - last_frame)
+            };
+            world.run_capped(gate.steps(running), max_ticks);
+            if let Some(max) = max_ticks {
+                if world.ticks >= max && !reported_max_ticks {
+                    log::info!("reached --max-ticks {max}, pausing");
+                    reported_max_ticks = true;
+                }
+            }
+
+            // Play what the listening region heard over those ticks
+            let (play, output_gain, speed, ticks_per_second) = {
+                let params = world.params.lock().unwrap();
+                (
+                    params.audio_output,
+                    params.output_gain,
+                    params.speed,
+                    params.ticks_per_second(),
+                )
+            };
+            if audio_output.as_ref().is_some_and(AudioOutput::failed) {
+                log::warn!("audio output stream errored, turning it off");
+                audio_output = None;
+                world.params.lock().unwrap().audio_output = false;
+            } else if !play {
+                audio_output = None;
+            } else if audio_output.is_none() {
+                match AudioOutput::start(audio_settings) {
+                    Ok(output) => audio_output = Some(output),
+                    Err(e) => {
+                        log::warn!("couldn't start audio output: {e}");
+                        world.params.lock().unwrap().audio_output = false;
+                    }
+                }
+            }
+            if let Some(output) = &mut audio_output {
+                let heard = world.take_listened();
+                output.play(&heard, ticks_per_second * speed, output_gain);
+            }
+            last_frame = now;
             window.request_redraw();
         }
 
         match event {
+            Event::LoopDestroyed => {
+                let (Some(path), Ok(position)) = (&geometry_path, window.outer_position()) else {
+                    return;
+                };
+                let size = window.inner_size();
+                let geometry = WindowGeometry {
+                    x: position.x,
+                    y: position.y,
+                    width: size.width,
+                    height: size.height,
+                };
+                if let Err(e) = geometry.save(path) {
+                    log::warn!("couldn't save window geometry: {e}");
+                }
+            }
             Event::WindowEvent { event, .. } => {
                 // Update egui inputs
                 framework.handle_event(&event);
@@ -153,10 +801,51 @@ fn main() -> Result<(), Error> {
             // Draw the current frame
             Event::RedrawRequested(_) => {
                 // Draw the world
-                world.draw(pixels.get_frame_mut());
+                world.draw(pixels.get_frame_mut(), buffer_size.0, buffer_size.1);
+
+                let (capturing, threshold, pre, post) = {
+                    let params = world.params.lock().unwrap();
+                    (
+                        params.capture,
+                        params.capture_threshold,
+                        params.capture_pre_roll,
+                        params.capture_post_roll,
+                    )
+                };
+                if capturing {
+                    let frame = Frame {
+                        width: buffer_size.0,
+                        height: buffer_size.1,
+                        rgba: pixels.get_frame_mut().to_vec(),
+                    };
+                    let energy = world.total_energy();
+                    if let Some(burst) = trigger.push(frame, energy, threshold, pre, post) {
+                        match capture::save_burst(Path::new("captures"), &burst) {
+                            Ok(dir) => {
+                                log::info!("captured {} frames to {}", burst.len(), dir.display())
+                            }
+                            Err(e) => error!("capture failed: {e}"),
+                        }
+                    }
+                }
+
+                if let Some(recorder) = &mut recorder {
+                    recorder.push(Frame {
+                        width: buffer_size.0,
+                        height: buffer_size.1,
+                        rgba: pixels.get_frame_mut().to_vec(),
+                    });
+                }
 
                 // Prepare egui
-                framework.prepare(&window);
+                let mut readouts = world.readouts();
+                if let Some(cell) = last_hovered {
+                    let size = window.inner_size();
+                    readouts.brush_outline =
+                        brush_outline(cell, &world, buffer_size, (size.width, size.height));
+                    readouts.probe = world.probe(cell);
+                }
+                framework.prepare(&window, &readouts);
 
                 // Render everything together
                 let render_result = pixels.render_with(|encoder, render_target, context| {
@@ -180,139 +869,1361 @@ fn main() -> Result<(), Error> {
     });
 }
 
+/// Get every rayon worker thread started now, so the first `World::update`
+/// doesn't stall on thread creation.
+fn warm_up_thread_pool() {
+    rayon::broadcast(|_| ());
+}
+
+/// Save the field, at grid resolution, as a timestamped PNG under
+/// `screenshots/`.
+fn save_screenshot(world: &World) {
+    let (width, height) = world.dimensions();
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let path = Path::new("screenshots").join(format!("screenshot-{stamp}.png"));
+    match export::save_png(&path, width as u32, height as u32, &world.draw_to_rgba()) {
+        Ok(()) => log::info!("saved {}", path.display()),
+        Err(e) => error!("screenshot failed: {e}"),
+    }
+}
+
+/// The grid cell under a window position, if it's over the picture.
+fn cursor_cell(
+    pixels: &Pixels,
+    pos: (f32, f32),
+    buffer_size: (u32, u32),
+    grid: (u32, u32),
+) -> Option<(isize, isize)> {
+    let (px, py) = pixels.window_pos_to_pixel(pos).ok()?;
+    let (x, y) = render::pixel_to_cell(px, py, buffer_size, grid);
+    Some((x as isize, y as isize))
+}
+
+/// The outline of the brush footprint around `cell`, in physical window
+/// pixels.
+fn brush_outline(
+    cell: (isize, isize),
+    world: &World,
+    buffer_size: (u32, u32),
+    surface: (u32, u32),
+) -> Vec<(f32, f32)> {
+    let (brush_radius, fit_window) = {
+        let params = world.params.lock().unwrap();
+        (params.brush_radius, params.fit_window)
+    };
+    // Half a cell out, so the outline runs round the edge cells, not through them.
+    let radius = brush_radius as f32 + 0.5;
+    let (width, height) = world.dimensions();
+    let grid = (width as u32, height as u32);
+    render::circle_points(cell, radius, 48)
+        .into_iter()
+        .map(|(x, y)| {
+            let (px, py) = render::cell_to_pixel(x, y, buffer_size, grid);
+            render::pixel_to_surface(px, py, buffer_size, surface, fit_window)
+        })
+        .collect()
+}
+
 impl World {
-    fn new(params: Arc<Mutex<SimParams>>) -> Self {
-        let mut materials = Array2D::new(WIDTH as usize, HEIGHT as usize, Material::Fluid);
-
-        Self {
-            pressures: Array2D::new(WIDTH as usize, HEIGHT as usize, 0.0),
-            pressures_back: Array2D::new(WIDTH as usize, HEIGHT as usize, 0.0),
-            velocities: Array2D::new(WIDTH as usize, HEIGHT as usize, Vec2::ZERO),
-            velocities_back: Array2D::new(WIDTH as usize, HEIGHT as usize, Vec2::ZERO),
-            materials,
+    fn new(params: Arc<Mutex<SimParams>>, width: usize, height: usize) -> Self {
+        let (palette, wave_params, ticks_per_second) = {
+            let params = params.lock().unwrap();
+            (params.palette, params.wave, params.ticks_per_second())
+        };
+
+        let world = Self {
+            field: WaveField::new(width, height, wave_params),
             params,
             ticks: 0,
+            ticks_per_second,
+            time: 0.0,
+            membrane: None,
+            under_membrane: Vec::new(),
+            dense: None,
+            listened: VecDeque::new(),
+            charge: VecDeque::new(),
+            energy: VecDeque::new(),
+            clock: Box::new(SystemClock),
+            tone_map: RefCell::new(ToneMap::new()),
+            palette_fade: RefCell::new(PaletteFade::new(palette)),
+            emitter: Oscillator::default(),
+            emitter_oscillators: HashMap::new(),
+            bar_drawn: false,
+            trail: Array2D::new(width, height, 0.0),
+            trail_samples: 0,
+            mask: None,
+            damping_ramps: Vec::new(),
+        };
+        world.field.assert_consistent();
+        world
+    }
+
+    /// Width and height of the simulation grid, in cells.
+    fn dimensions(&self) -> (usize, usize) {
+        self.field.dimensions()
+    }
+
+    /// Start over from a quiet field, seeding a pulse if asked to. A mask
+    /// loaded from an image and any damping ramps stay.
+    fn reset(&mut self) {
+        let (width, height) = self.dimensions();
+        let clock = std::mem::replace(&mut self.clock, Box::new(SystemClock));
+        let mask = self.mask.take();
+        let damping_ramps = std::mem::take(&mut self.damping_ramps);
+        *self = World::new(self.params.clone(), width, height);
+        self.clock = clock;
+        if let Some(mask) = mask {
+            *self.field.materials_mut() = mask.clone();
+            self.mask = Some(mask);
+        }
+        for ramp in damping_ramps {
+            self.add_damping_ramp(ramp);
+        }
+        if self.params.lock().unwrap().seed_on_reset {
+            self.seed_wave();
+        }
+    }
+
+    /// Drop a small pulse in the center of the field.
+    fn seed_wave(&mut self) {
+        let (width, height) = self.dimensions();
+        self.field
+            .pressures
+            .stamp_gaussian(width as f32 / 2.0, height as f32 / 2.0, 4.0, 1.0);
+    }
+
+    /// Set a rectangle of cells to `mat`, clipped to the grid.
+    fn set_material_rect(&mut self, x0: isize, y0: isize, w: usize, h: usize, mat: Material) {
+        self.field.materials_mut().fill_rect(x0, y0, w, h, mat);
+    }
+
+    /// Paint the cell at `(x, y)` with `brush`.
+    fn paint(&mut self, x: isize, y: isize, brush: Material, mode: PaintMode) {
+        if let Some(cell) = self.field.materials_mut().get_mut(x, y) {
+            *cell = paint::painted(*cell, brush, mode);
+        }
+    }
+
+    /// Set the stiffness of every cell in `rect` that's on the grid.
+    fn set_stiffness_rect(&mut self, rect: CellRect, stiffness: Option<f32>) {
+        for y in rect.y..rect.y.saturating_add(rect.height) {
+            for x in rect.x..rect.x.saturating_add(rect.width) {
+                if let Some(cell) = self.field.local_params.get_mut(x as isize, y as isize) {
+                    cell.stiffness = stiffness;
+                }
+            }
+        }
+    }
+
+    /// Paint the parameter overrides at `(x, y)` with `brush`.
+    fn paint_params(&mut self, x: isize, y: isize, brush: LocalParams, mode: PaintMode) {
+        if let Some(cell) = self.field.local_params.get_mut(x, y) {
+            // Stiffness belongs to the dense region, not the brush.
+            let stiffness = cell.stiffness.take();
+            *cell = paint::painted(*cell, brush, mode);
+            cell.stiffness = stiffness;
+        }
+    }
+
+    /// Replace every cell's material with what the image at `path` shows,
+    /// fitted to the grid per `SimParams::mask_fit`.
+    fn load_materials_from_image(&mut self, path: &Path) -> Result<(), String> {
+        let (fit, accent) = {
+            let params = self.params.lock().unwrap();
+            (params.mask_fit, params.mask_accent)
+        };
+        let materials = mask::materials_from_image(path, self.dimensions(), fit, accent)?;
+        *self.field.materials_mut() = materials.clone();
+        self.mask = Some(materials);
+        Ok(())
+    }
+
+    /// Lay `ramp` over the extra damping, and again after every reset.
+    fn add_damping_ramp(&mut self, ramp: DampingRamp) {
+        self.field.damp(ramp);
+        self.damping_ramps.push(ramp);
+    }
+
+    /// Paint the extra damping at `(x, y)` with `brush`; erasing takes it
+    /// back to none (1).
+    fn paint_damping(&mut self, x: isize, y: isize, brush: f32, mode: PaintMode) {
+        if let Some(cell) = self.field.damping.get_mut(x, y) {
+            // Painted as the fraction taken away, whose default is zero.
+            *cell = 1.0 - paint::painted(1.0 - *cell, 1.0 - brush, mode);
+        }
+    }
+
+    /// Fill the pressure field with seeded noise, smoothed by `smoothness`
+    /// box-blur passes. The same arguments always give the same field.
+    fn init_noise(&mut self, seed: u64, amplitude: f32, smoothness: usize) {
+        let (width, height) = self.dimensions();
+        let field = noise::noise_field(width, height, seed, amplitude, smoothness);
+        self.field.set_pressures(field);
+    }
+
+    /// Drive the field from the audio spectrum, along the injection path if
+    /// there is one or across the top rows otherwise.
+    fn inject_spectrum(&mut self, spectrum: &[f32]) {
+        if spectrum.is_empty() {
+            return;
+        }
+
+        let (width, height) = self.dimensions();
+        let params = self.params.lock().unwrap();
+        let region = params.injection_region.unwrap_or(CellRect {
+            x: 0,
+            y: 0,
+            width,
+            height: DEFAULT_INJECTION_ROWS,
+        });
+        // Only the part on the grid gets spectrum points.
+        let columns = region.x.min(width)..region.x.saturating_add(region.width).min(width);
+        let rows = region.y.min(height)..region.y.saturating_add(region.height).min(height);
+
+        let points = if params.injection_path.is_empty() {
+            columns.len()
+        } else {
+            params.injection_path.len()
+        };
+        let bands = (params.spectrum_bands > 0)
+            .then(|| injection::downmix(spectrum, params.spectrum_bands));
+        let pressure_at = |i| match &bands {
+            Some(bands) => injection::injected_pressure(
+                bands,
+                injection::band_of(i, points, bands.len()),
+                bands.len(),
+                params.spectrum_mapping,
+                params.spectrum_floor,
+                params.injection_gain,
+            ),
+            None => injection::point_pressure(
+                spectrum,
+                i,
+                points,
+                params.freq_scale,
+                params.spectrum_mapping,
+                params.spectrum_floor,
+                params.injection_gain,
+            ),
+        };
+
+        if params.injection_path.is_empty() {
+            for (i, x) in columns.enumerate() {
+                let pres = pressure_at(i);
+                let y0 = rows.start as isize;
+                self.field
+                    .pressures
+                    .fill_rect(x as isize, y0, 1, rows.len(), pres);
+            }
+        } else {
+            for (i, &(x, y)) in params.injection_path.iter().enumerate() {
+                if let Some(cell) = self.field.pressures.get_mut(x, y) {
+                    *cell = pressure_at(i);
+                }
+            }
+        }
+    }
+
+    /// How many ticks are due after `elapsed` of real time, at the current
+    /// speed and tick rate.
+    fn ticks_due(&self, stepper: &mut StepAccumulator, elapsed: Duration) -> u32 {
+        let (speed, substeps, ticks_per_second) = {
+            let params = self.params.lock().unwrap();
+            (params.speed, params.substeps, params.ticks_per_second())
+        };
+        stepper.set_rate(ticks_per_second, 6 * substeps);
+        stepper.advance(elapsed, speed)
+    }
+
+    /// Run up to `steps` ticks, as a new frame's worth, but none past
+    /// `max_ticks`.
+    fn run_capped(&mut self, steps: u32, max_ticks: Option<u32>) {
+        let steps = steps.min(max_ticks.map_or(u32::MAX, |max| max.saturating_sub(self.ticks)));
+        if steps > 0 {
+            self.clear_trail();
+        }
+        for _ in 0..steps {
+            self.update();
         }
     }
 
     /// Update the `World` internal state; bounce the box around the screen.
     fn update(&mut self) {
-        if self.ticks % 6 == 0 {
-            for x in 0..WIDTH as isize {
+        let (width, _) = self.dimensions();
+        // Held only long enough to copy out of, never through the step.
+        let (params, emitters) = {
+            let params = self.params.lock().unwrap();
+            (params.snapshot(), params.emitters.clone())
+        };
+
+        let animate_bar = params.animate_bar;
+        if !animate_bar && self.bar_drawn {
+            let rows = BAR_ROWS.len();
+            self.set_material_rect(0, BAR_ROWS.start, width, rows, Material::Fluid);
+            self.bar_drawn = false;
+        }
+        if animate_bar && self.ticks.is_multiple_of(6) {
+            for x in 0..width as isize {
                 let offset = (self.ticks / 6) as isize;
                 let mat = if x.wrapping_add(offset) & 0x7F < 0x40 {
                     Material::Solid
                 } else {
                     Material::Fluid
                 };
-                for y in 380..384 {
-                    *self.materials.get_mut(x, y).unwrap() = mat;
-                }
+                // Clipped, since small grids may not reach the bar at all.
+                self.field
+                    .materials_mut()
+                    .fill_rect(x, BAR_ROWS.start, 1, BAR_ROWS.len(), mat);
             }
+            self.bar_drawn = true;
         }
 
-        std::mem::swap(&mut self.pressures, &mut self.pressures_back);
-        std::mem::swap(&mut self.velocities, &mut self.velocities_back);
         self.ticks += 1;
 
-        let params = self.params.lock().unwrap();
+        let membrane = params.membrane;
+        if membrane != self.membrane {
+            self.set_membrane(membrane);
+        }
+        let dense = params.dense;
+        if dense != self.dense {
+            if let Some((old, _)) = self.dense {
+                self.set_stiffness_rect(old, None);
+            }
+            if let Some((rect, stiffness)) = dense {
+                self.set_stiffness_rect(rect, Some(stiffness));
+            }
+            self.dense = dense;
+        }
 
-        let time = self.ticks as f32 / 16.0;
-
-        self.pressures
-            .par_iter_mut()
-            .zip(self.pressures_back.par_iter().cloned())
-            .zip(
-                self.velocities
-                    .par_iter_mut()
-                    .zip(self.velocities_back.par_iter().cloned()),
-            )
-            .enumerate()
-            .for_each(|(i, ((front, back), (front_v, _back_v)))| {
-                assert!(!back.is_infinite());
-                let x = i as isize % WIDTH as isize;
-                let y = i as isize / WIDTH as isize;
-
-                let left = self.pressures_back.get(x - 1, y).copied().unwrap_or(0.0);
-                let right = self.pressures_back.get(x + 1, y).copied().unwrap_or(0.0);
-                let up = self.pressures_back.get(x, y - 1).copied().unwrap_or(0.0);
-                let down = self.pressures_back.get(x, y + 1).copied().unwrap_or(0.0);
-
-                let hgrad = right - left;
-                let vgrad = down - up;
-
-                let grad = Vec2::new(hgrad, vgrad);
-                *front_v += grad * params.grad_alpha;
-                *front_v *= 1.0 - params.grad_damping;
-
-                let mut accum = 0.0;
-
-                match self.materials.get(x, y).unwrap() {
-                    Material::Fluid => {
-                        accum += self
-                            .velocities_back
-                            .get(x - 1, y)
-                            .copied()
-                            .unwrap_or(Vec2::ZERO)
-                            .x;
-                        accum -= self
-                            .velocities_back
-                            .get(x + 1, y)
-                            .copied()
-                            .unwrap_or(Vec2::ZERO)
-                            .x;
-                        accum += self
-                            .velocities_back
-                            .get(x, y - 1)
-                            .copied()
-                            .unwrap_or(Vec2::ZERO)
-                            .y;
-                        accum -= self
-                            .velocities_back
-                            .get(x, y + 1)
-                            .copied()
-                            .unwrap_or(Vec2::ZERO)
-                            .y;
-
-                        *front -= accum;
-                    }
-                    Material::Emitter => {
-                        *front = 2.5 * (time / 3.0).sin();
-                    }
-                    Material::Solid => {
-                        *front = 0.0;
-                        *front_v = Vec2::ZERO;
-                    }
-                }
-            });
+        // Time adds up tick by tick so changing the tick rate doesn't jump it.
+        self.ticks_per_second = params.ticks_per_second;
+        let tick = self.ticks_per_second.recip();
+        self.time += tick;
+        let time = self.time;
+        self.field.emitter_pressure = self.emitter.next(params.tone, time, tick);
+        let wave_params = params.wave_params;
+        if !wave_params.is_stable() && self.field.params.is_stable() {
+            log::warn!(
+                "grad_alpha {} is past the stable limit of {}; capping it",
+                wave_params.grad_alpha,
+                simulation::MAX_STABLE_ALPHA
+            );
+        }
+        self.field.params = wave_params;
+        self.field.step();
+
+        // Emitters added since the last tick, or given a new phase, start
+        // there; removed ones take their oscillators with them.
+        self.emitter_oscillators
+            .retain(|&id, _| emitters.iter().any(|emitter| emitter.id == id));
+        for emitter in &emitters {
+            let start = || (emitter.phase, Oscillator::starting_at(emitter.phase));
+            let voice = self
+                .emitter_oscillators
+                .entry(emitter.id)
+                .or_insert_with(start);
+            if voice.0 != emitter.phase {
+                *voice = start();
+            }
+            let value = voice.1.next(emitter.tone, time, tick);
+            let CellRect {
+                x,
+                y,
+                width,
+                height,
+            } = emitter.region;
+            self.field
+                .pressures
+                .fill_rect(x as isize, y as isize, width, height, value);
+        }
+
+        if params.trail != TrailMode::Off {
+            let trail = params.trail;
+            self.trail
+                .par_iter_mut()
+                .zip(self.field.pressures.par_iter())
+                .for_each(|(acc, &p)| trail.accumulate(acc, p));
+            self.trail_samples += 1;
+        }
+
+        if let Some(region) = params.listening_region {
+            if self.listened.len() >= self.ticks_per_second as usize {
+                self.listened.pop_front();
+            }
+            self.listened
+                .push_back(self.field.pressures.mean_over(region));
+        }
+        if let Some(region) = params.charge_region {
+            if self.charge.len() >= 10 * self.ticks_per_second as usize {
+                self.charge.pop_front();
+            }
+            self.charge.push_back(self.field.charge_over(region));
+        }
+        if params.track_energy {
+            if self.energy.len() >= 10 * self.ticks_per_second as usize {
+                self.energy.pop_front();
+            }
+            self.energy.push_back(self.field.total_energy() as f32);
+        } else {
+            self.energy.clear();
+        }
     }
 
-    fn draw(&self, frame: &mut [u8]) {
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let i = i as isize;
-            let pixel_x = (i % WIDTH as isize - (WIDTH as isize / 2)) as f32 / (HEIGHT as f32);
-            let pixel_y = (i / WIDTH as isize - (HEIGHT as isize / 2)) as f32 / (HEIGHT as f32);
+    /// The row the membrane is stretched along.
+    fn membrane_row(&self) -> usize {
+        let (_, height) = self.dimensions();
+        (height as f32 * MEMBRANE_DEPTH) as usize
+    }
+
+    /// Stretch a membrane of `tension` across the field, retension it, or
+    /// with `None` take it out again, restoring whatever it covered. Cells
+    /// painted over the membrane in the meantime are left as painted.
+    fn set_membrane(&mut self, membrane: Option<f32>) {
+        let y = self.membrane_row();
+        let materials = self.field.materials_mut();
+        let row = materials
+            .rows_mut()
+            .nth(y)
+            .expect("membrane row off the grid");
+        let was_up = self.membrane.is_some();
+        if !was_up {
+            self.under_membrane = row.to_vec();
+        }
+        for (x, cell) in row.iter_mut().enumerate() {
+            if was_up && !matches!(cell, Material::Membrane(_)) {
+                continue;
+            }
+            *cell = match membrane {
+                Some(tension) => Material::Membrane(tension),
+                None => self.under_membrane.get(x).copied().unwrap_or_default(),
+            };
+        }
+        self.membrane = membrane;
+    }
+
+    /// What the GUI should show about the current state.
+    fn readouts(&self) -> Readouts {
+        Readouts {
+            charge: self.charge.iter().copied().collect(),
+            energy: self.energy.iter().copied().collect(),
+            diverged: self.field.diverged(),
+            sanitized: self.field.sanitized(),
+            ..Readouts::default()
+        }
+    }
+
+    /// The pressure and velocity at `cell`, if it's on the grid.
+    fn probe(&self, (x, y): (isize, isize)) -> Option<Probe> {
+        let pressure = *self.field.pressures.get(x, y)?;
+        let velocity = *self.field.velocities.get(x, y)?;
+        let y_up = self.params.lock().unwrap().y_up;
+        let (_, height) = self.dimensions();
+        Some(Probe {
+            cell: (x as usize, render::display_y(y as usize, height, y_up)),
+            pressure,
+            // Flipped along with y, so it points the way it's drawn.
+            velocity: (velocity.x, if y_up { -velocity.y } else { velocity.y }),
+        })
+    }
+
+    /// Kinetic plus potential energy over the whole field.
+    fn total_energy(&self) -> f32 {
+        self.field.total_energy() as f32
+    }
+
+    /// Start collecting a new frame's trail.
+    fn clear_trail(&mut self) {
+        self.trail.fill(0.0);
+        self.trail_samples = 0;
+    }
+
+    /// Hand over everything heard since the last call, oldest first, for the
+    /// audio output stream.
+    fn take_listened(&mut self) -> Vec<f32> {
+        self.listened.drain(..).collect()
+    }
+
+    /// Draw the field at grid resolution into a new RGBA buffer.
+    fn draw_to_rgba(&self) -> Vec<u8> {
+        let (width, height) = self.dimensions();
+        let mut frame = vec![0; width * height * 4];
+        self.draw(&mut frame, width as u32, height as u32);
+        frame
+    }
+
+    /// [`World::draw_to_rgba`], by the name it first had.
+    fn render_to_vec(&self) -> Vec<u8> {
+        self.draw_to_rgba()
+    }
 
-            let r = (pixel_x * pixel_x + pixel_y * pixel_y).sqrt();
-            let theta = ((f32::atan2(pixel_y, pixel_x) / std::f32::consts::PI) * 0.5) + 0.5;
+    /// Run `ticks` ticks, then render the result.
+    fn run_and_render(&mut self, ticks: u32) -> Vec<u8> {
+        for _ in 0..ticks {
+            self.update();
+        }
+        self.render_to_vec()
+    }
+
+    /// Whether `(x, y)` is a wall. Emitters don't count: fluid next to
+    /// them shouldn't look like it's next to a wall.
+    fn is_solid_at(&self, x: isize, y: isize) -> bool {
+        matches!(self.field.materials().get(x, y), Some(Material::Solid))
+    }
 
-            let x = (theta * WIDTH as f32) as usize;
-            let y = (r * HEIGHT as f32) as usize;
+    /// Draw the field into an RGBA `frame` of any size; the grid is mapped
+    /// onto it in polar coordinates.
+    fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+        let params = self.params.lock().unwrap();
+        let (boundary_blend, non_finite_color) = (params.boundary_blend, params.non_finite_color);
+        let style = MaterialStyle {
+            solid_style: params.solid_style,
+            solid_color: params.solid_color,
+            emitter_color: params.emitter_color,
+        };
+        let mut regions = vec![
+            params.listening_region,
+            params.charge_region,
+            params.dense_region,
+            params.injection_region,
+        ];
+        regions.extend(params.emitters.iter().map(|emitter| Some(emitter.region)));
+        let palette = params.palette;
+        let fade = self.palette_fade.borrow_mut().update(
+            palette,
+            Duration::from_secs_f32(params.palette_fade.max(0.0)),
+            self.clock.now(),
+        );
+        let gain = self.tone_map.borrow_mut().gain(
+            params.auto_gain,
+            params.lock_scale,
+            &self.field.pressures,
+        ) * params.display_gain;
+        let trail =
+            (params.trail != TrailMode::Off && self.trail_samples > 0).then_some(params.trail);
+        let velocity_overlay = params
+            .velocity_overlay
+            .then_some((params.velocity_stride, params.velocity_opacity));
+        drop(params);
 
-            let p = self.pressures[x + (y * WIDTH as usize)];
-            let pos = p > 0.0;
-            let is_solid = matches!(
-                self.materials[x + (y * WIDTH as usize)],
-                Material::Solid | Material::Emitter
+        let (width, height) = self.dimensions();
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let fw = frame_width as usize;
+            let (x, y) = render::pixel_to_cell(
+                i % fw,
+                i / fw,
+                (frame_width, frame_height),
+                (width as u32, height as u32),
             );
-            let g = if is_solid { 0xff } else { 0x00 };
-            let rgba = if pos {
-                [(p * 255.0) as u8, g, 0x0, 0xff]
+
+            let i = (x as isize, y as isize);
+            let p = match trail {
+                Some(mode) => mode.resolve(self.trail[i], self.trail_samples),
+                None => self.field.pressures[i],
+            } * gain;
+            if !p.is_finite() {
+                let [r, g, b] = non_finite_color;
+                pixel.copy_from_slice(&[r, g, b, 0xff]);
+                continue;
+            }
+
+            let material = self.field.materials()[i];
+            let is_solid = matches!(material, Material::Solid | Material::Emitter);
+            let rgba = render::material_color(material, p, (x, y), &style, |p, g| match fade {
+                Some((old, t)) => render::blend(
+                    old.rgba(p, g, non_finite_color),
+                    palette.rgba(p, g, non_finite_color),
+                    t,
+                ),
+                None => palette.rgba(p, g, non_finite_color),
+            });
+
+            // Soften the edge between fluid and solid.
+            let rgba = if !is_solid && boundary_blend > 0.0 {
+                let (cx, cy) = (x as isize, y as isize);
+                let solid_neighbors = [(cx - 1, cy), (cx + 1, cy), (cx, cy - 1), (cx, cy + 1)]
+                    .into_iter()
+                    .filter(|&(nx, ny)| self.is_solid_at(nx, ny))
+                    .count();
+                let green = render::solid_green(style.solid_style, x, y);
+                let solid = render::shade(style.solid_color, green);
+                render::blend(rgba, solid, boundary_blend * solid_neighbors as f32 / 4.0)
             } else {
-                [0, g, (-p * 255.0) as u8, 0xff]
+                rgba
+            };
+
+            let rgba = match velocity_overlay {
+                Some((stride, opacity)) => {
+                    let (vx, vy) = render::stride_cell(x, y, stride);
+                    let v = self.field.velocities[(vx as isize, vy as isize)];
+                    let tint = render::velocity_rgba(v, gain);
+                    render::blend(rgba, tint, opacity * tint[3] as f32 / 255.0)
+                }
+                None => rgba,
+            };
+
+            let rgba = if regions.iter().flatten().any(|r| r.on_edge(x, y)) {
+                [0xff, 0xff, 0xff, 0xff]
+            } else {
+                rgba
             };
 
             pixel.copy_from_slice(&rgba);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kontawa::timing::MockClock;
+
+    /// A world of the given size with `params`.
+    fn world_with(params: SimParams, width: usize, height: usize) -> World {
+        World::new(Arc::new(Mutex::new(params)), width, height)
+    }
+
+    #[test]
+    fn update_after_warmup_matches_single_threaded() {
+        let params = SimParams {
+            animate_bar: false,
+            ..SimParams::default()
+        };
+        let mut warmed = world_with(params.clone(), 64, 48);
+        let mut reference = world_with(params, 64, 48);
+        warmed.seed_wave();
+        reference.seed_wave();
+
+        warm_up_thread_pool();
+        for _ in 0..4 {
+            warmed.update();
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        pool.install(|| (0..4).for_each(|_| reference.update()));
+
+        assert_eq!(*warmed.field.pressures, *reference.field.pressures);
+        assert_eq!(*warmed.field.velocities, *reference.field.velocities);
+        assert!(warmed.field.pressures.iter().any(|&p| p != 0.0));
+    }
+
+    #[test]
+    fn reset_seeds_only_when_asked() {
+        for seed_on_reset in [true, false] {
+            let params = SimParams {
+                seed_on_reset,
+                ..SimParams::default()
+            };
+            let mut world = world_with(params, 32, 32);
+            world.reset();
+            let center = world.field.pressures[(16, 16)];
+            if seed_on_reset {
+                assert!(center > 0.5);
+            } else {
+                assert_eq!(world.total_energy(), 0.0);
+            }
+        }
+    }
+
+    /// The RGBA of the first pixel `draw_to_rgba` maps to `cell`.
+    fn drawn_color(world: &World, cell: (usize, usize)) -> [u8; 4] {
+        let (width, height) = world.dimensions();
+        let grid = (width as u32, height as u32);
+        let frame = world.draw_to_rgba();
+        let i = (0..width * height)
+            .find(|i| render::pixel_to_cell(i % width, i / width, grid, grid) == cell)
+            .expect("cell isn't drawn anywhere");
+        frame[i * 4..i * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn fluid_next_to_solid_blends_in_proportion() {
+        let greens: Vec<u8> = [0.0, 0.5, 1.0]
+            .into_iter()
+            .map(|boundary_blend| {
+                let params = SimParams {
+                    animate_bar: false,
+                    boundary_blend,
+                    ..SimParams::default()
+                };
+                let mut world = world_with(params, 32, 32);
+                world.set_material_rect(20, 10, 1, 1, Material::Solid);
+                let [r, g, b, _] = drawn_color(&world, (21, 10));
+                assert_eq!((r, b), (0, 0));
+                g
+            })
+            .collect();
+        // One solid neighbor of four: a quarter of the way at full blend.
+        assert_eq!(greens, [0, 32, 64]);
+    }
+
+    #[test]
+    fn one_hertz_emitter_cycles_once_per_second_of_ticks() {
+        let params = SimParams {
+            animate_bar: false,
+            emitter_freq: 1.0,
+            emitter_amp: 1.0,
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 8, 8);
+        let ticks = world.ticks_per_second as usize;
+        assert_eq!(
+            ticks,
+            DEFAULT_SUBSTEPS as usize * FRAMES_PER_SECOND as usize
+        );
+
+        let outputs: Vec<f32> = (0..ticks)
+            .map(|_| {
+                world.update();
+                world.field.emitter_pressure
+            })
+            .collect();
+        // A sine: up at a quarter, through zero at a half, and back around
+        // to zero on the last tick.
+        assert!((outputs[ticks / 4 - 1] - 1.0).abs() < 1e-3);
+        assert!(outputs[ticks / 2 - 1].abs() < 1e-3);
+        assert!(outputs[ticks - 2] < 0.0);
+        assert!(outputs[ticks - 1].abs() < 1e-3);
+    }
+
+    #[test]
+    fn spectrum_runs_along_the_injection_path_in_order() {
+        let path = vec![(5, 5), (6, 5), (6, 6), (2, 7), (9, 1)];
+        let params = SimParams {
+            spectrum_mapping: SpectrumMapping::Wrap,
+            spectrum_floor: f32::NEG_INFINITY,
+            injection_gain: 1.0,
+            injection_path: path.clone(),
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 12, 12);
+        world.inject_spectrum(&[1.0, 2.0, 3.0]);
+
+        let injected: Vec<f32> = path
+            .iter()
+            .map(|&cell| world.field.pressures[cell])
+            .collect();
+        assert_eq!(injected, [1.0, 2.0, 3.0, 1.0, 2.0]);
+        // Nothing off the path, including the default top rows.
+        assert_eq!(world.field.pressures.iter().sum::<f32>(), 9.0);
+    }
+
+    #[test]
+    fn membrane_row_comes_out_leaving_what_was_under_it() {
+        let params = SimParams {
+            animate_bar: false,
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 16, 20);
+        let row = world.membrane_row() as isize;
+        assert_eq!(row, 8);
+        world.set_material_rect(3, row, 2, 1, Material::Solid);
+
+        world.params.lock().unwrap().membrane = true;
+        world.update();
+        assert_eq!(world.field.materials()[(3, row)], Material::Membrane(1.0));
+        assert_eq!(world.field.materials()[(0, row)], Material::Membrane(1.0));
+        world.paint(10, row, Material::Emitter, PaintMode::Set);
+
+        world.params.lock().unwrap().membrane = false;
+        world.update();
+        let after: Vec<Material> = (0..16).map(|x| world.field.materials()[(x, row)]).collect();
+        let mut expected = vec![Material::Fluid; 16];
+        expected[3] = Material::Solid;
+        expected[4] = Material::Solid;
+        expected[10] = Material::Emitter;
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn dimensions_match_construction_and_mismatches_are_rejected() {
+        let mut world = world_with(SimParams::default(), 30, 20);
+        assert_eq!(world.dimensions(), (30, 20));
+        assert_eq!((world.trail.width(), world.trail.height()), (30, 20));
+
+        let mut state = world.field.state();
+        state.damping = Array2D::new(20, 30, 1.0);
+        assert!(world.field.restore(state).is_err());
+        assert_eq!(world.dimensions(), (30, 20));
+    }
+
+    #[test]
+    fn empty_spectrum_injects_nothing() {
+        let mut world = world_with(SimParams::default(), 16, 16);
+        world.inject_spectrum(&[]);
+        assert!(world.field.pressures.iter().all(|&p| p == 0.0));
+    }
+
+    #[test]
+    fn non_finite_cells_draw_in_the_debug_color() {
+        let params = SimParams {
+            animate_bar: false,
+            non_finite_color: [0x12, 0x34, 0x56],
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 32, 32);
+        world.field.pressures[(20, 10)] = f32::NAN;
+        world.field.pressures[(22, 10)] = f32::INFINITY;
+        assert_eq!(drawn_color(&world, (20, 10)), [0x12, 0x34, 0x56, 0xff]);
+        assert_eq!(drawn_color(&world, (22, 10)), [0x12, 0x34, 0x56, 0xff]);
+        assert_ne!(drawn_color(&world, (24, 10)), [0x12, 0x34, 0x56, 0xff]);
+    }
+
+    #[test]
+    fn set_material_rect_fills_clamped_and_nothing_else() {
+        let mut world = world_with(SimParams::default(), 10, 8);
+        world.set_material_rect(2, 3, 3, 2, Material::Solid);
+        world.set_material_rect(-4, -4, 6, 5, Material::Emitter);
+        world.set_material_rect(8, 6, 100, 100, Material::Membrane(2.0));
+
+        for (x, y, &material) in world.field.materials().iter_coords() {
+            let expected = if (2..5).contains(&x) && (3..5).contains(&y) {
+                Material::Solid
+            } else if x < 2 && y < 1 {
+                Material::Emitter
+            } else if x >= 8 && y >= 6 {
+                Material::Membrane(2.0)
+            } else {
+                Material::Fluid
+            };
+            assert_eq!(material, expected, "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn max_trail_holds_each_cells_peak_over_the_substeps() {
+        let params = SimParams {
+            animate_bar: false,
+            trail: TrailMode::Max,
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 32, 32);
+        world.seed_wave();
+        world.clear_trail();
+
+        let mut peak = Array2D::new(32, 32, 0.0f32);
+        for _ in 0..6 {
+            world.update();
+            for (peak, &p) in peak.iter_mut().zip(world.field.pressures.iter()) {
+                if p.abs() > peak.abs() {
+                    *peak = p;
+                }
+            }
+        }
+        assert_eq!(world.trail_samples, 6);
+        assert_eq!(*world.trail, *peak);
+        // The pulse has moved on, so the trail isn't just the last substep.
+        assert_ne!(*world.trail, *world.field.pressures);
+    }
+
+    #[test]
+    fn mock_clock_drives_ticks_and_emitter_phase() {
+        let params = SimParams {
+            animate_bar: false,
+            emitter_freq: 0.25,
+            emitter_amp: 1.0,
+            ..SimParams::default()
+        };
+        let clock = MockClock::new();
+        let mut world = world_with(params, 8, 8);
+        world.clock = Box::new(clock.clone());
+        let mut stepper = StepAccumulator::new(world.ticks_per_second, 6 * DEFAULT_SUBSTEPS);
+
+        // A second of frames, paced as the event loop paces them.
+        let mut last_frame = world.clock.now();
+        for _ in 0..60 {
+            clock.advance(Duration::from_secs_f64(1.0 / 60.0));
+            let now = world.clock.now();
+            for _ in 0..world.ticks_due(&mut stepper, now - last_frame) {
+                world.update();
+            }
+            last_frame = now;
+        }
+        assert!(
+            (world.ticks as i32 - 180).abs() <= 1,
+            "{} ticks",
+            world.ticks
+        );
+        // A quarter of the way around a 0.25 Hz sine: the peak.
+        assert!((world.field.emitter_pressure - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mock_clock_times_palette_fades() {
+        let params = SimParams {
+            animate_bar: false,
+            palette_fade: 0.5,
+            ..SimParams::default()
+        };
+        let clock = MockClock::new();
+        let mut world = world_with(params, 32, 32);
+        world.clock = Box::new(clock.clone());
+        world.field.pressures[(20, 10)] = 0.5;
+        let before = drawn_color(&world, (20, 10));
+
+        world.params.lock().unwrap().palette = Palette::Grayscale;
+        let after = Palette::Grayscale.rgba(0.5, 0, render::NON_FINITE_RGB);
+        assert_eq!(drawn_color(&world, (20, 10)), before);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(
+            drawn_color(&world, (20, 10)),
+            render::blend(before, after, 0.5)
+        );
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(drawn_color(&world, (20, 10)), after);
+    }
+
+    #[test]
+    fn y_up_flips_only_the_reported_row() {
+        let mut world = world_with(SimParams::default(), 10, 8);
+        world.field.pressures[(3, 1)] = 0.75;
+        world.field.velocities[(3, 1)] = glam::Vec2::new(0.5, 0.25);
+        let stored = world.field.state();
+
+        let down = world.probe((3, 1)).unwrap();
+        assert_eq!(down.cell, (3, 1));
+        world.params.lock().unwrap().y_up = true;
+        let up = world.probe((3, 1)).unwrap();
+        assert_eq!(up.cell, (3, 8 - 1 - 1));
+        assert_eq!(up.pressure, 0.75);
+        assert_eq!(up.velocity, (0.5, -0.25));
+
+        assert_eq!(*world.field.pressures, *stored.pressures[0]);
+        assert_eq!(*world.field.velocities, *stored.velocities[0]);
+        assert_eq!(render::display_y(render::display_y(5, 8, true), 8, true), 5);
+    }
+
+    #[test]
+    fn no_bar_leaves_the_grid_fluid() {
+        let args = cli::Args::parse_from(["--no-bar".to_string()]).unwrap();
+        let mut world = world_with(args.config.params, 8, 400);
+        for _ in 0..60 {
+            world.update();
+        }
+        assert!(world
+            .field
+            .materials()
+            .iter()
+            .all(|&m| m == Material::Fluid));
+
+        let mut with_bar = world_with(SimParams::default(), 8, 400);
+        with_bar.update();
+        assert!(with_bar.field.materials().contains(&Material::Solid));
+    }
+
+    #[test]
+    fn run_and_render_fills_a_grid_sized_frame() {
+        let args = cli::Args::parse_from(["--no-bar".to_string()]).unwrap();
+        let mut world = world_with(args.config.params, 24, 16);
+        let lit = |frame: &[u8]| {
+            frame
+                .chunks_exact(4)
+                .filter(|pixel| pixel[..3] != [0, 0, 0])
+                .count()
+        };
+
+        let still = world.draw_to_rgba();
+        assert_eq!(still.len(), 24 * 16 * 4);
+        assert_eq!(lit(&still), 0);
+
+        let mut impulse = Array2D::from_fn(24, 16, |_, _| 0.0);
+        impulse[(12, 8)] = 1.0;
+        world.field.set_pressures(impulse);
+        let evolved = world.run_and_render(10);
+        assert_eq!(evolved.len(), 24 * 16 * 4);
+        assert!(lit(&evolved) > 1);
+    }
+
+    #[test]
+    fn emitters_and_solids_draw_in_their_own_colors() {
+        let args = cli::Args::parse_from(["--no-bar".to_string()]).unwrap();
+        let mut world = world_with(args.config.params, 32, 32);
+        let (solid, emitter) = ((8, 8), (24, 8));
+        world.field.materials_mut()[(8, 8)] = Material::Solid;
+        world.field.materials_mut()[(24, 8)] = Material::Emitter;
+
+        let solid_before = drawn_color(&world, solid);
+        let emitter_before = drawn_color(&world, emitter);
+        assert_ne!(solid_before, emitter_before);
+
+        world.params.lock().unwrap().emitter_color = [0x20, 0x40, 0xff];
+        assert_eq!(drawn_color(&world, solid), solid_before);
+        assert_ne!(drawn_color(&world, emitter), emitter_before);
+    }
+
+    #[test]
+    fn max_ticks_stops_the_step_loop() {
+        let mut world = world_with(SimParams::default(), 16, 16);
+        for expected in [4, 8, 10, 10] {
+            world.run_capped(4, Some(10));
+            assert_eq!(world.ticks, expected);
+        }
+        world.run_capped(4, None);
+        assert_eq!(world.ticks, 14);
+    }
+
+    /// Plays a gliding tone through the spectrum processor, and drives a
+    /// small world with what comes out through injection, stepping and
+    /// drawing, then checks the picture against `testdata/pipeline.golden`.
+    /// After a deliberate change to any of them, rerun with
+    /// `KONTAWA_BLESS=1` to write a new golden.
+    #[test]
+    fn audio_to_picture_matches_the_golden() {
+        let golden_path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/pipeline.golden");
+        let mut world = world_with(SimParams::default(), 48, 32);
+        world.clock = Box::new(MockClock::new());
+        let channels = Channels::new();
+        let mut process = audio::spectrum_processor(
+            channels.publisher(Channels::SPECTRUM),
+            channels.publisher(Channels::LEVEL),
+            world.params.clone(),
+        );
+        let mut check = SpectrumCheck::new(world.params.lock().unwrap().fft_size);
+
+        // 8 kHz audio in 20 ms blocks, sweeping from 200 Hz to about 3 kHz.
+        const RATE: f32 = 8000.0;
+        const BLOCK: usize = 160;
+        let mut phase = 0.0f32;
+        let mut injected = 0;
+        for frame in 0..30 {
+            let block: Vec<f32> = (0..BLOCK)
+                .map(|i| {
+                    let freq = 200.0 + (frame * BLOCK + i) as f32 * 0.6;
+                    phase = (phase + std::f32::consts::TAU * freq / RATE) % std::f32::consts::TAU;
+                    phase.sin() * 0.5
+                })
+                .collect();
+            process(&block);
+            channels.subscriber(Channels::SPECTRUM, |spectra| {
+                if spectra.flip() && check.accept(spectra.front()) {
+                    world.inject_spectrum(spectra.front());
+                    injected += 1;
+                }
+            });
+            world.run_capped(4, None);
+        }
+        assert!(injected > 20, "only {injected} spectra made it through");
+        let rgba = world.draw_to_rgba();
+
+        // FNV-1a, for a hash that won't change between Rust versions.
+        let hash = rgba.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        let mut sums = [0u64; 3];
+        for pixel in rgba.chunks_exact(4) {
+            for (sum, &channel) in sums.iter_mut().zip(pixel) {
+                *sum += channel as u64;
+            }
+        }
+        let lit = rgba
+            .chunks_exact(4)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .count();
+        let summary = format!(
+            "hash {hash:016x}\nlit {lit}\nsums {} {} {}\n",
+            sums[0], sums[1], sums[2]
+        );
+
+        if std::env::var_os("KONTAWA_BLESS").is_some() {
+            std::fs::write(golden_path, &summary).unwrap();
+        }
+        let golden = std::fs::read_to_string(golden_path).unwrap_or_else(|e| {
+            panic!("can't read {golden_path} ({e}); run with KONTAWA_BLESS=1 to write it")
+        });
+        assert_eq!(
+            summary, golden,
+            "rerun with KONTAWA_BLESS=1 if this change is intended"
+        );
+    }
+
+    #[test]
+    fn non_square_world_updates_and_draws() {
+        let mut world = world_with(SimParams::default(), 256, 128);
+        assert_eq!(world.dimensions(), (256, 128));
+        world.seed_wave();
+        let frame = world.run_and_render(12);
+        assert_eq!(frame.len(), 256 * 128 * 4);
+        assert!(world.field.pressures.iter().all(|p| p.is_finite()));
+    }
+
+    #[test]
+    fn fronts_slow_down_in_the_dense_region() {
+        let args = cli::Args::parse_from(["--no-bar".to_string()]).unwrap();
+        let params = SimParams {
+            wave: WaveParams {
+                grad_damping: 1.0,
+                ..WaveParams::default()
+            },
+            dense_region: Some(CellRect {
+                x: 0,
+                y: 150,
+                width: 8,
+                height: 150,
+            }),
+            dense_stiffness: 0.25,
+            ..args.config.params
+        };
+        let mut world = world_with(params, 8, 300);
+        world.field.set_pressures(Array2D::from_fn(8, 300, |_, y| {
+            (-((y as f32 - 150.0) / 4.0).powi(2)).exp()
+        }));
+        for _ in 0..200 {
+            world.update();
+        }
+
+        // How far from the start each half of the pulse has got.
+        let reached: Vec<usize> = world
+            .field
+            .pressures
+            .iter_coords()
+            .filter(|&(_, _, p)| p.abs() > 0.05)
+            .map(|(_, y, _)| y)
+            .collect();
+        let up = 150 - reached.iter().min().unwrap();
+        let down = reached.iter().max().unwrap() - 150;
+        // Half the speed, at a quarter the stiffness.
+        assert!(down * 10 < up * 7, "{up} up vs {down} down");
+        assert!(down * 10 > up * 4, "{up} up vs {down} down");
+    }
+
+    #[test]
+    fn one_row_region_injects_into_that_row_only() {
+        let params = SimParams {
+            spectrum_floor: f32::NEG_INFINITY,
+            injection_gain: 2.0,
+            injection_region: Some(CellRect {
+                x: 3,
+                y: 7,
+                width: 40,
+                height: 1,
+            }),
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 16, 12);
+        world.inject_spectrum(&[0.5; 8]);
+
+        for (x, y, &p) in world.field.pressures.iter_coords() {
+            if y == 7 && x >= 3 {
+                assert!(p > 0.0, "({x}, {y}) wasn't injected into");
+            } else {
+                assert_eq!(p, 0.0, "({x}, {y}) was touched");
+            }
+        }
+    }
+
+    #[test]
+    fn world_steps_bit_for_bit_like_a_headless_run() {
+        let args = cli::Args::parse_from(["--no-bar".to_string()]).unwrap();
+        let mut world = world_with(args.config.params, 48, 32);
+        world.field.pressures.stamp_gaussian(20.0, 12.0, 2.0, 1.0);
+        let mut headless = WaveField::new(48, 32, world.field.params);
+        headless.restore(world.field.state()).unwrap();
+
+        for _ in 0..40 {
+            world.update();
+        }
+        let done = simulation::run_headless(headless, 40, None);
+        assert!(done.peak_pressure() > 0.01);
+        let bits = |field: &WaveField| -> Vec<u32> {
+            field.pressures.iter().map(|p| p.to_bits()).collect()
+        };
+        assert_eq!(bits(&world.field), bits(&done.field));
+    }
+
+    #[test]
+    fn draw_to_rgba_is_what_draw_writes() {
+        let mut world = world_with(SimParams::default(), 24, 18);
+        world.seed_wave();
+        world.run_capped(5, None);
+        let world = &world;
+
+        let rgba = world.draw_to_rgba();
+        assert_eq!(rgba.len(), 24 * 18 * 4);
+        let mut frame = vec![0; 24 * 18 * 4];
+        world.draw(&mut frame, 24, 18);
+        assert_eq!(rgba, frame);
+        assert_eq!(world.render_to_vec(), rgba);
+    }
+
+    #[test]
+    fn brush_defaults_sit_inside_the_clamped_range() {
+        let params = SimParams::default();
+        assert_eq!(params.brush_material, Material::Solid);
+        assert_eq!(params.brush_radius, 1);
+        assert_eq!(paint::BRUSH_RADIUS_RANGE, 1..=64);
+        assert_eq!(paint::adjust_radius(params.brush_radius, -1), 1);
+        assert_eq!(paint::adjust_radius(0, 0), 1);
+        assert_eq!(paint::adjust_radius(60, 10), 64);
+    }
+
+    #[test]
+    fn emitters_keep_their_oscillators_when_one_before_them_goes() {
+        let cell = |x| CellRect {
+            x,
+            y: 8,
+            width: 1,
+            height: 1,
+        };
+        let mut params = cli::Args::parse_from(["--no-bar".to_string()])
+            .unwrap()
+            .config
+            .params;
+        let mut last = Emitter::new(cell(24), 3.0, 1.0);
+        last.phase = 1.0;
+        params.emitters = vec![
+            Emitter::new(cell(8), 1.0, 1.0),
+            Emitter::new(cell(16), 2.0, 1.0),
+            last,
+        ];
+        let tick = params.ticks_per_second().recip();
+        let mut world = world_with(params, 32, 16);
+
+        let mut oscillator = Oscillator::starting_at(last.phase);
+        let mut time = 0.0;
+        let mut expect_last = |world: &mut World, oscillator: &mut Oscillator| {
+            world.update();
+            time += tick;
+            let expected = oscillator.next(last.tone, time, tick);
+            assert_eq!(world.field.pressures[(24, 8)], expected);
+        };
+        for _ in 0..20 {
+            expect_last(&mut world, &mut oscillator);
+        }
+        // Removing the middle one leaves the last on its own oscillator...
+        world.params.lock().unwrap().emitters.remove(1);
+        for _ in 0..20 {
+            expect_last(&mut world, &mut oscillator);
+        }
+        // ...and a new phase starts it over from there.
+        world.params.lock().unwrap().emitters[1].phase = 2.5;
+        oscillator = Oscillator::starting_at(2.5);
+        for _ in 0..20 {
+            expect_last(&mut world, &mut oscillator);
+        }
+    }
+
+    #[test]
+    fn reset_keeps_the_materials_loaded_from_an_image() {
+        let dir = std::env::temp_dir().join(format!("kontawa-mask-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mask.png");
+        // A solid left half, on a grid four times the size each way.
+        image::RgbaImage::from_fn(4, 4, |x, _| match x {
+            0 | 1 => image::Rgba([0, 0, 0, 0xff]),
+            _ => image::Rgba([0xff, 0xff, 0xff, 0xff]),
+        })
+        .save(&path)
+        .unwrap();
+
+        let mut world = world_with(SimParams::default(), 16, 16);
+        world.load_materials_from_image(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = world.field.materials().clone();
+        assert_eq!(loaded[(7, 3)], Material::Solid);
+        assert_eq!(loaded[(8, 3)], Material::Fluid);
+
+        world.set_material_rect(10, 10, 2, 2, Material::Solid);
+        world.reset();
+        assert!(world.field.materials().iter().eq(loaded.iter()));
+        world.reset();
+        assert!(world.field.materials().iter().eq(loaded.iter()));
+    }
+
+    #[test]
+    fn probe_reads_the_cell_and_nothing_off_the_grid() {
+        let mut world = world_with(SimParams::default(), 16, 8);
+        world.field.pressures[(3, 2)] = 0.75;
+        world.field.velocities[(3, 2)] = glam::Vec2::new(0.5, -0.25);
+        let probe = world.probe((3, 2)).unwrap();
+        assert_eq!(
+            (probe.cell, probe.pressure, probe.velocity),
+            ((3, 2), 0.75, (0.5, -0.25))
+        );
+        for outside in [(-1, 2), (16, 2), (3, -1), (3, 8)] {
+            assert!(world.probe(outside).is_none(), "{outside:?}");
+        }
+
+        // Counting up from the bottom flips the row and the vertical flow.
+        world.params.lock().unwrap().y_up = true;
+        let probe = world.probe((3, 2)).unwrap();
+        assert_eq!((probe.cell, probe.velocity), ((3, 5), (0.5, 0.25)));
+    }
+    #[test]
+    fn params_change_freely_mid_tick_and_each_tick_sees_one_setting() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let settings = [(0.1, 0.9999), (0.2, 0.99)];
+        let params = cli::Args::parse_from(["--no-bar".to_string()])
+            .unwrap()
+            .config
+            .params;
+        let mut world = world_with(params, 256, 256);
+        let shared = Arc::clone(&world.params);
+        let stop = AtomicBool::new(false);
+
+        let writes = std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                let mut writes = 0usize;
+                while !stop.load(Ordering::Relaxed) {
+                    let (alpha, damping) = settings[writes % 2];
+                    let mut params = shared.lock().unwrap();
+                    params.wave.grad_alpha = alpha;
+                    params.wave.grad_damping = damping;
+                    drop(params);
+                    writes += 1;
+                }
+                writes
+            });
+            for _ in 0..30 {
+                world.update();
+                let used = (
+                    world.field.params.grad_alpha,
+                    world.field.params.grad_damping,
+                );
+                assert!(settings.contains(&used), "{used:?}");
+            }
+            stop.store(true, Ordering::Relaxed);
+            writer.join().unwrap()
+        });
+        assert!(writes > 0);
+    }
+    #[test]
+    fn sealed_box_keeps_its_charge() {
+        let region = CellRect {
+            x: 10,
+            y: 10,
+            width: 20,
+            height: 16,
+        };
+        let params = SimParams {
+            animate_bar: false,
+            wave: WaveParams {
+                grad_damping: 1.0,
+                ..WaveParams::default()
+            },
+            substeps: 1,
+            charge_region: Some(region),
+            ..SimParams::default()
+        };
+        let mut world = world_with(params, 40, 36);
+        world.set_material_rect(10, 10, 20, 16, Material::Solid);
+        world.set_material_rect(11, 11, 18, 14, Material::Fluid);
+        world.field.pressures.stamp_gaussian(20.0, 17.5, 1.5, 1.0);
+        let start = world.field.charge_over(region);
+        let cap = 10 * world.ticks_per_second as usize;
+        for _ in 0..cap + 100 {
+            world.update();
+        }
+
+        assert_eq!(world.charge.len(), cap);
+        for (tick, &charge) in world.charge.iter().enumerate() {
+            assert!(
+                (charge - start).abs() < start * 1e-4,
+                "tick {tick}: {charge} vs {start}"
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Drawing obstacles in an image editor instead of in code.
+
+use std::path::Path;
+
+use image::RgbaImage;
+use kontawa::simulation::{Array2D, Material};
+
+/// How an image whose shape doesn't match the grid's is fitted onto it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) enum ImageFit {
+    /// Stretch each way to cover the whole grid.
+    #[default]
+    Stretch,
+    /// Scale evenly to fit inside the grid, centered, with fluid around it.
+    Letterbox,
+}
+
+/// How far each channel can be from the accent color and still count.
+const ACCENT_TOLERANCE: u8 = 32;
+
+/// Decode a PNG or BMP at `path` into a `width` x `height` material grid;
+/// see [`materials_from_rgba`].
+pub(crate) fn materials_from_image(
+    path: &Path,
+    (width, height): (usize, usize),
+    fit: ImageFit,
+    accent: [u8; 3],
+) -> Result<Array2D<Material>, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("couldn't load {}: {e}", path.display()))?
+        .into_rgba8();
+    if image.width() == 0 || image.height() == 0 {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(materials_from_rgba(&image, (width, height), fit, accent))
+}
+
+/// Resample `image` (nearest neighbor) onto a `width` x `height` grid. Dark
+/// pixels are solid, light or transparent ones fluid, and ones close to
+/// `accent` emitters.
+pub(crate) fn materials_from_rgba(
+    image: &RgbaImage,
+    (width, height): (usize, usize),
+    fit: ImageFit,
+    accent: [u8; 3],
+) -> Array2D<Material> {
+    let (image_width, image_height) = (image.width() as usize, image.height() as usize);
+    // The part of the grid the image covers.
+    let (drawn_width, drawn_height) = match fit {
+        ImageFit::Stretch => (width, height),
+        ImageFit::Letterbox => {
+            let scale =
+                (width as f64 / image_width as f64).min(height as f64 / image_height as f64);
+            let drawn =
+                |len: usize, limit: usize| ((len as f64 * scale).round() as usize).clamp(1, limit);
+            (drawn(image_width, width), drawn(image_height, height))
+        }
+    };
+    let (left, top) = ((width - drawn_width) / 2, (height - drawn_height) / 2);
+
+    Array2D::from_fn(width, height, |x, y| {
+        let (Some(x), Some(y)) = (x.checked_sub(left), y.checked_sub(top)) else {
+            return Material::Fluid;
+        };
+        if x >= drawn_width || y >= drawn_height {
+            return Material::Fluid;
+        }
+        // The pixel under the cell's center.
+        let px = (2 * x + 1) * image_width / (2 * drawn_width);
+        let py = (2 * y + 1) * image_height / (2 * drawn_height);
+        classify(image.get_pixel(px as u32, py as u32).0, accent)
+    })
+}
+
+/// The material a pixel stands for.
+fn classify([r, g, b, a]: [u8; 4], accent: [u8; 3]) -> Material {
+    if a < 0x80 {
+        return Material::Fluid;
+    }
+    if [r, g, b]
+        .iter()
+        .zip(accent)
+        .all(|(&c, a)| c.abs_diff(a) <= ACCENT_TOLERANCE)
+    {
+        return Material::Emitter;
+    }
+    let luma = (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000;
+    if luma < 0x80 {
+        Material::Solid
+    } else {
+        Material::Fluid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCENT: [u8; 3] = [0xff, 0x00, 0x00];
+
+    /// Rows of `S`olid, `.` fluid, `E`mitter and `_` transparent pixels.
+    fn image(rows: &[&str]) -> RgbaImage {
+        RgbaImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            image::Rgba(match rows[y as usize].as_bytes()[x as usize] {
+                b'S' => [0x10, 0x10, 0x10, 0xff],
+                b'.' => [0xf0, 0xf0, 0xf0, 0xff],
+                b'E' => [0xf0, 0x10, 0x08, 0xff],
+                _ => [0x00, 0x00, 0x00, 0x00],
+            })
+        })
+    }
+
+    fn letters(materials: &Array2D<Material>) -> Vec<String> {
+        materials
+            .rows()
+            .map(|row| {
+                row.iter()
+                    .map(|material| match material {
+                        Material::Solid => 'S',
+                        Material::Emitter => 'E',
+                        _ => '.',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn four_by_four_image_becomes_the_same_materials() {
+        let rows = ["SS..", "S.E.", "..._", "SSSS"];
+        let materials = materials_from_rgba(&image(&rows), (4, 4), ImageFit::Stretch, ACCENT);
+        assert_eq!(letters(&materials), ["SS..", "S.E.", "....", "SSSS"]);
+
+        // Twice the size each way, every pixel covers a 2x2 block.
+        let doubled = materials_from_rgba(&image(&rows), (8, 8), ImageFit::Stretch, ACCENT);
+        assert_eq!(letters(&doubled)[2..4], ["SS..EE..", "SS..EE.."]);
+
+        // Fitted into a wider grid, it keeps its shape, centered in fluid.
+        let boxed = materials_from_rgba(&image(&rows), (8, 4), ImageFit::Letterbox, ACCENT);
+        assert_eq!(
+            letters(&boxed),
+            ["..SS....", "..S.E...", "........", "..SSSS.."]
+        );
+    }
+}
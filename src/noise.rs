@@ -0,0 +1,57 @@
+//! Deterministic random initial conditions.
+
+use crate::simulation::Array2D;
+
+/// SplitMix64: tiny and fast, and plenty random for initial conditions.
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[-1, 1)`.
+    pub fn next_signed(&mut self) -> f32 {
+        // Top 24 bits, so every value is exactly representable.
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        unit * 2.0 - 1.0
+    }
+}
+
+/// Uniform noise in `[-amplitude, amplitude)`, box-blurred `smoothness` times.
+/// The same seed always gives the same field.
+pub fn noise_field(
+    width: usize,
+    height: usize,
+    seed: u64,
+    amplitude: f32,
+    smoothness: usize,
+) -> Array2D<f32> {
+    let mut rng = SplitMix64::new(seed);
+    let mut field = Array2D::from_fn(width, height, |_, _| rng.next_signed() * amplitude);
+    for _ in 0..smoothness {
+        field.box_blur();
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_field() {
+        let field = |seed| noise_field(24, 16, seed, 0.5, 2);
+        assert_eq!(*field(7), *field(7));
+        assert_ne!(*field(7), *field(8));
+        assert!(field(7).iter().all(|p| p.abs() <= 0.5));
+    }
+}
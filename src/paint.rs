@@ -0,0 +1,155 @@
+//! Painting materials into the grid with the mouse.
+
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// What a brush stroke does to the cells it passes over.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) enum PaintMode {
+    /// Make cells the brush material.
+    #[default]
+    Set,
+    /// Make cells fluid again.
+    Erase,
+    /// Flip cells between fluid and the brush material.
+    Toggle,
+}
+
+/// Which of a cell's properties the brush paints.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) enum BrushTarget {
+    /// Its material.
+    #[default]
+    Material,
+    /// Overrides of the global wave parameters.
+    Params,
+    /// Its extra damping.
+    Damping,
+}
+
+/// Brush radii, in cells, that can be picked.
+pub(crate) const BRUSH_RADIUS_RANGE: RangeInclusive<usize> = 1..=64;
+
+/// `radius` grown (or, for negative `delta`, shrunk) by `delta`, kept within
+/// [`BRUSH_RADIUS_RANGE`].
+pub(crate) fn adjust_radius(radius: usize, delta: isize) -> usize {
+    radius
+        .saturating_add_signed(delta)
+        .clamp(*BRUSH_RADIUS_RANGE.start(), *BRUSH_RADIUS_RANGE.end())
+}
+
+/// Every cell within `radius` cells of `center`. Radius 0 is just `center`.
+pub(crate) fn brush_cells(center: (isize, isize), radius: usize) -> Vec<(isize, isize)> {
+    let r = radius as isize;
+    let mut cells = Vec::new();
+    for dy in -r..=r {
+        for dx in -r..=r {
+            if dx * dx + dy * dy <= r * r {
+                cells.push((center.0 + dx, center.1 + dy));
+            }
+        }
+    }
+    cells
+}
+
+/// What a cell of `current` becomes when painted with `brush` in `mode`;
+/// erasing goes back to the default (fluid, or no overrides).
+pub(crate) fn painted<T: PartialEq + Default>(current: T, brush: T, mode: PaintMode) -> T {
+    match mode {
+        PaintMode::Set => brush,
+        PaintMode::Erase => T::default(),
+        PaintMode::Toggle if current == T::default() => brush,
+        PaintMode::Toggle => T::default(),
+    }
+}
+
+/// One press-drag-release of the mouse. Each cell is painted at most once per
+/// stroke, so toggling doesn't flicker while the cursor sits still.
+pub(crate) struct Stroke {
+    touched: HashSet<(isize, isize)>,
+    /// What this stroke does, fixed when the button went down.
+    pub mode: PaintMode,
+}
+impl Stroke {
+    pub fn new(mode: PaintMode) -> Stroke {
+        Stroke {
+            touched: HashSet::new(),
+            mode,
+        }
+    }
+
+    /// Whether `cell` is new to this stroke, remembering it if so.
+    pub fn touch(&mut self, cell: (isize, isize)) -> bool {
+        self.touched.insert(cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kontawa::simulation::Material;
+
+    #[test]
+    fn toggle_flips_where_set_sticks() {
+        let toggled = painted(Material::Fluid, Material::Solid, PaintMode::Toggle);
+        assert_eq!(toggled, Material::Solid);
+        assert_eq!(
+            painted(toggled, Material::Solid, PaintMode::Toggle),
+            Material::Fluid
+        );
+
+        let set = painted(Material::Fluid, Material::Solid, PaintMode::Set);
+        assert_eq!(
+            painted(set, Material::Solid, PaintMode::Set),
+            Material::Solid
+        );
+        assert_eq!(
+            painted(set, Material::Solid, PaintMode::Erase),
+            Material::Fluid
+        );
+    }
+
+    #[test]
+    fn stroke_touches_each_cell_once() {
+        let mut stroke = Stroke::new(PaintMode::Toggle);
+        assert!(stroke.touch((3, 4)));
+        assert!(!stroke.touch((3, 4)));
+        assert!(stroke.touch((4, 4)));
+    }
+
+    #[test]
+    fn radius_adjustments_stay_in_range() {
+        let (min, max) = BRUSH_RADIUS_RANGE.into_inner();
+        assert_eq!(adjust_radius(10, 3), 13);
+        assert_eq!(adjust_radius(10, -3), 7);
+        assert_eq!(adjust_radius(max, 1), max);
+        assert_eq!(adjust_radius(max - 1, 10), max);
+        assert_eq!(adjust_radius(min, -1), min);
+        assert_eq!(adjust_radius(min + 1, -10), min);
+    }
+
+    #[test]
+    fn brush_marks_the_cells_within_its_radius() {
+        assert_eq!(brush_cells((5, -3), 0), [(5, -3)]);
+
+        let mut cells = brush_cells((10, 10), 2);
+        cells.sort();
+        let mut expected = vec![(10, 8), (10, 12), (8, 10), (12, 10)];
+        for y in 9..=11 {
+            for x in 9..=11 {
+                expected.push((x, y));
+            }
+        }
+        expected.sort();
+        assert_eq!(cells, expected);
+
+        // Each cell once, and only those no further out than the radius.
+        let big = brush_cells((0, 0), 7);
+        assert_eq!(big.iter().collect::<HashSet<_>>().len(), big.len());
+        for y in -8..=8_isize {
+            for x in -8..=8_isize {
+                assert_eq!(big.contains(&(x, y)), x * x + y * y <= 49, "({x}, {y})");
+            }
+        }
+    }
+}
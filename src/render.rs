@@ -0,0 +1,839 @@
+//! Turning simulation state into colors.
+
+use std::time::{Duration, Instant};
+
+use glam::Vec2;
+
+use crate::simulation::Material;
+
+/// A rectangle within the window, in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The largest rectangle with the grid's aspect ratio that fits in the
+/// surface, centered, leaving bars on the sides or top and bottom.
+pub fn letterbox_viewport(surface: (u32, u32), grid: (u32, u32)) -> Viewport {
+    let (sw, sh) = surface;
+    let (gw, gh) = grid;
+
+    // Compare sw/sh against gw/gh without dividing.
+    let (width, height) = if sw as u64 * gh as u64 > sh as u64 * gw as u64 {
+        // Surface is wider than the grid: pillarbox.
+        ((sh as u64 * gw as u64 / gh as u64) as u32, sh)
+    } else {
+        // Surface is taller than the grid: letterbox.
+        (sw, (sw as u64 * gh as u64 / gw as u64) as u32)
+    };
+    let (width, height) = (width.max(1), height.max(1));
+
+    Viewport {
+        x: sw.saturating_sub(width) / 2,
+        y: sh.saturating_sub(height) / 2,
+        width,
+        height,
+    }
+}
+
+/// The window size, in physical pixels, nearest below `requested` that
+/// shows each grid cell as a whole square block of pixels. Never smaller
+/// than one pixel per cell.
+///
+/// At a fractional `scale_factor` a block size whose window isn't also a
+/// whole number of logical pixels is passed over for a smaller one that
+/// is, as the window system would otherwise round it back off the grid.
+pub fn snap_to_grid(requested: (u32, u32), grid: (u32, u32), scale_factor: f64) -> (u32, u32) {
+    let fit = (requested.0 / grid.0).min(requested.1 / grid.1).max(1);
+    let whole_logical = |pixels: u32| {
+        let logical = pixels as f64 / scale_factor;
+        (logical - logical.round()).abs() < 1e-6
+    };
+    let scale = (1..=fit)
+        .rev()
+        .find(|&scale| whole_logical(grid.0 * scale) && whole_logical(grid.1 * scale))
+        .unwrap_or(fit);
+    (grid.0 * scale, grid.1 * scale)
+}
+
+/// The grid cell shown at pixel `(px, py)` of a `frame`-sized picture. The
+/// grid is drawn in polar form: `x` runs around the center, `y` outward.
+pub fn pixel_to_cell(px: usize, py: usize, frame: (u32, u32), grid: (u32, u32)) -> (usize, usize) {
+    let (fw, fh) = (frame.0 as isize, frame.1 as isize);
+    let pixel_x = (px as isize - (fw / 2)) as f32 / (fh as f32);
+    let pixel_y = (py as isize - (fh / 2)) as f32 / (fh as f32);
+
+    let r = (pixel_x * pixel_x + pixel_y * pixel_y).sqrt();
+    let theta = ((f32::atan2(pixel_y, pixel_x) / std::f32::consts::PI) * 0.5) + 0.5;
+
+    let x = (theta * grid.0 as f32) as usize;
+    let y = (r * grid.1 as f32) as usize;
+    (x.min(grid.0 as usize - 1), y.min(grid.1 as usize - 1))
+}
+
+/// Where on a `frame`-sized picture the point `(x, y)`, in fractional cells,
+/// is drawn. The inverse of [`pixel_to_cell`].
+pub fn cell_to_pixel(x: f32, y: f32, frame: (u32, u32), grid: (u32, u32)) -> (f32, f32) {
+    let (fw, fh) = (frame.0 as isize, frame.1 as isize);
+    let theta = (x / grid.0 as f32 - 0.5) * 2.0 * std::f32::consts::PI;
+    let r = y / grid.1 as f32 * fh as f32;
+    (
+        r * theta.cos() + (fw / 2) as f32,
+        r * theta.sin() + (fh / 2) as f32,
+    )
+}
+
+/// Where pixel `(px, py)` of a `buffer`-sized picture lands in a `surface`.
+/// With `fit_window` the buffer fills the [`letterbox_viewport`]; otherwise
+/// it's scaled up the way `pixels` does it: by the largest whole factor
+/// that fits, centered.
+pub fn pixel_to_surface(
+    px: f32,
+    py: f32,
+    buffer: (u32, u32),
+    surface: (u32, u32),
+    fit_window: bool,
+) -> (f32, f32) {
+    let (bw, bh) = (buffer.0 as f32, buffer.1 as f32);
+    if fit_window {
+        let viewport = letterbox_viewport(surface, buffer);
+        return (
+            viewport.x as f32 + px * viewport.width as f32 / bw,
+            viewport.y as f32 + py * viewport.height as f32 / bh,
+        );
+    }
+    let (sw, sh) = (surface.0 as f32, surface.1 as f32);
+    let scale = (sw / bw).min(sh / bh).max(1.0).floor();
+    (
+        (sw - bw * scale) / 2.0 + px * scale,
+        (sh - bh * scale) / 2.0 + py * scale,
+    )
+}
+
+/// `segments` points, in fractional cells, evenly spaced around a circle of
+/// `radius` cells centered on cell `center`.
+pub fn circle_points(center: (isize, isize), radius: f32, segments: usize) -> Vec<(f32, f32)> {
+    // Cell centers sit half a cell in from their corner.
+    let (cx, cy) = (center.0 as f32 + 0.5, center.1 as f32 + 0.5);
+    (0..segments)
+        .map(|i| {
+            let a = i as f32 / segments as f32 * 2.0 * std::f32::consts::PI;
+            (cx + radius * a.cos(), cy + radius * a.sin())
+        })
+        .collect()
+}
+
+/// The y coordinate shown to the user for grid row `row`. Rows count down
+/// from the top, but with `y_up` readouts count up from the bottom like a
+/// physics plot; only the labeling flips, never the storage. Its own inverse.
+pub fn display_y(row: usize, height: usize, y_up: bool) -> usize {
+    if y_up {
+        height - 1 - row
+    } else {
+        row
+    }
+}
+
+/// Red for positive pressure, blue for negative, with `g` in the green
+/// channel. Saturates at one unit either way; NaN and infinities get
+/// `non_finite`.
+pub fn pressure_rgba(p: f32, g: u8, non_finite: [u8; 3]) -> [u8; 4] {
+    if !p.is_finite() {
+        return shade(non_finite, 0xff);
+    }
+    let level = (p.abs() * 255.0).clamp(0.0, 255.0) as u8;
+    if p > 0.0 {
+        [level, g, 0x0, 0xff]
+    } else {
+        [0, g, level, 0xff]
+    }
+}
+
+/// The range of the finite values in a field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FieldStats {
+    pub min: f32,
+    pub max: f32,
+}
+impl FieldStats {
+    /// The largest magnitude in the field.
+    pub fn peak(&self) -> f32 {
+        self.min.abs().max(self.max.abs())
+    }
+}
+
+/// Min and max of `field`, skipping NaNs and infinities. `None` if there are
+/// no finite values at all.
+pub fn field_stats(field: &[f32]) -> Option<FieldStats> {
+    field
+        .iter()
+        .copied()
+        .filter(|p| p.is_finite())
+        .fold(None, |stats, p| {
+            Some(match stats {
+                None => FieldStats { min: p, max: p },
+                Some(FieldStats { min, max }) => FieldStats {
+                    min: min.min(p),
+                    max: max.max(p),
+                },
+            })
+        })
+}
+
+/// Picks the gain pressures are multiplied by before they're colored.
+pub struct ToneMap {
+    last: Option<FieldStats>,
+}
+impl ToneMap {
+    pub fn new() -> ToneMap {
+        ToneMap { last: None }
+    }
+
+    /// With `auto_gain`, scale so the field's peak is full brightness;
+    /// otherwise leave pressures alone. With `lock`, keep using the range
+    /// from the last unlocked frame so colors hold still.
+    pub fn gain(&mut self, auto_gain: bool, lock: bool, field: &[f32]) -> f32 {
+        if !auto_gain {
+            return 1.0;
+        }
+        if !lock || self.last.is_none() {
+            self.last = field_stats(field);
+        }
+        match self.last {
+            Some(stats) if stats.peak() > f32::EPSILON => stats.peak().recip(),
+            _ => 1.0,
+        }
+    }
+}
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap::new()
+    }
+}
+
+/// How the substeps run within one frame are combined for display.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum TrailMode {
+    /// Show only the last substep.
+    #[default]
+    Off,
+    /// Show whichever substep strayed furthest from zero.
+    Max,
+    /// Show the mean over the substeps.
+    Average,
+}
+impl TrailMode {
+    /// Fold the pressure `p` from one more substep into `acc`.
+    pub fn accumulate(self, acc: &mut f32, p: f32) {
+        match self {
+            TrailMode::Off => *acc = p,
+            TrailMode::Max => {
+                if p.abs() > acc.abs() {
+                    *acc = p;
+                }
+            }
+            TrailMode::Average => *acc += p,
+        }
+    }
+
+    /// The displayed pressure once `samples` substeps are in `acc`.
+    pub fn resolve(self, acc: f32, samples: u32) -> f32 {
+        match self {
+            TrailMode::Average if samples > 0 => acc / samples as f32,
+            _ => acc,
+        }
+    }
+}
+
+/// Default colors for solid and emitter cells.
+pub const SOLID_RGB: [u8; 3] = [0x00, 0xff, 0x00];
+pub const EMITTER_RGB: [u8; 3] = [0xff, 0xff, 0x00];
+
+/// Default color for cells whose pressure is NaN or infinite, loud enough
+/// that a blow-up can't be missed.
+pub const NON_FINITE_RGB: [u8; 3] = [0xff, 0x00, 0xff];
+
+/// How pressure is colored.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Palette {
+    /// Red for positive, blue for negative, black at zero.
+    #[default]
+    RedBlue,
+    /// Fades from black at `midpoint` toward `positive` above it and
+    /// `negative` below, reaching full color one unit away.
+    Bipolar {
+        positive: [u8; 3],
+        negative: [u8; 3],
+        midpoint: f32,
+    },
+    /// Perceptually uniform dark purple through green to yellow, from -1 to
+    /// 1.
+    Viridis,
+    /// Black at -1 to white at 1.
+    Grayscale,
+    /// A brighter rainbow, dark blue through green to dark red, from -1 to 1.
+    Turbo,
+}
+impl Palette {
+    /// A bipolar palette with the stock hues around zero.
+    pub const BIPOLAR: Palette = Palette::Bipolar {
+        positive: [0xff, 0x80, 0x00],
+        negative: [0x00, 0x80, 0xff],
+        midpoint: 0.0,
+    };
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::RedBlue => "RedBlue",
+            Palette::Bipolar { .. } => "Bipolar",
+            Palette::Viridis => "Viridis",
+            Palette::Grayscale => "Grayscale",
+            Palette::Turbo => "Turbo",
+        }
+    }
+
+    /// The color for pressure `p`, clamped to `-1..=1`.
+    pub fn sample(&self, p: f32) -> [u8; 4] {
+        let p = if p.is_nan() { 0.0 } else { p.clamp(-1.0, 1.0) };
+        let t = (p + 1.0) / 2.0;
+        match *self {
+            Palette::RedBlue | Palette::Bipolar { .. } => self.rgba(p, 0, NON_FINITE_RGB),
+            Palette::Viridis => ramp(&VIRIDIS, t),
+            Palette::Grayscale => {
+                let v = (t * 255.0).round() as u8;
+                [v, v, v, 0xff]
+            }
+            Palette::Turbo => ramp(&TURBO, t),
+        }
+    }
+
+    /// The color for pressure `p`, with `g` mixed into the green channel.
+    /// NaN and infinities get `non_finite`, whatever the palette.
+    pub fn rgba(&self, p: f32, g: u8, non_finite: [u8; 3]) -> [u8; 4] {
+        if !p.is_finite() {
+            return shade(non_finite, 0xff);
+        }
+        match *self {
+            Palette::RedBlue => pressure_rgba(p, g, non_finite),
+            Palette::Bipolar {
+                positive,
+                negative,
+                midpoint,
+            } => {
+                let d = p - midpoint;
+                let hue = if d > 0.0 { positive } else { negative };
+                let t = d.abs().min(1.0);
+                let [r, hg, b] = hue.map(|c| (c as f32 * t) as u8);
+                [r, hg.max(g), b, 0xff]
+            }
+            Palette::Viridis | Palette::Grayscale | Palette::Turbo => {
+                let [r, sg, b, a] = self.sample(p);
+                [r, sg.max(g), b, a]
+            }
+        }
+    }
+}
+
+/// Evenly spaced stops along the viridis and turbo colormaps.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [0x44, 0x01, 0x54],
+    [0x48, 0x28, 0x78],
+    [0x3e, 0x49, 0x89],
+    [0x31, 0x68, 0x8e],
+    [0x26, 0x82, 0x8e],
+    [0x1f, 0x9e, 0x89],
+    [0x35, 0xb7, 0x79],
+    [0x6e, 0xce, 0x58],
+    [0xfd, 0xe7, 0x25],
+];
+const TURBO: [[u8; 3]; 11] = [
+    [0x30, 0x12, 0x3b],
+    [0x44, 0x54, 0xc4],
+    [0x44, 0x90, 0xfe],
+    [0x1f, 0xc8, 0xde],
+    [0x29, 0xef, 0xa2],
+    [0x7e, 0xff, 0x55],
+    [0xc1, 0xf3, 0x34],
+    [0xf1, 0xca, 0x3a],
+    [0xfe, 0x92, 0x2a],
+    [0xea, 0x4f, 0x0d],
+    [0x7a, 0x04, 0x03],
+];
+
+/// The color `t` of the way along `stops`, interpolating between them.
+fn ramp(stops: &[[u8; 3]], t: f32) -> [u8; 4] {
+    let pos = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (pos as usize).min(stops.len() - 2);
+    let (a, b) = (stops[i], stops[i + 1]);
+    let [r, g, bl] = [0, 1, 2].map(|c| {
+        let (a, b) = (a[c] as f32, b[c] as f32);
+        (a + (b - a) * (pos - i as f32)).round() as u8
+    });
+    [r, g, bl, 0xff]
+}
+
+/// Crossfades to a newly chosen palette instead of snapping to it.
+pub struct PaletteFade {
+    current: Palette,
+    /// The palette being faded out, and when the fade started.
+    from: Option<(Palette, Instant)>,
+}
+impl PaletteFade {
+    pub fn new(palette: Palette) -> PaletteFade {
+        PaletteFade {
+            current: palette,
+            from: None,
+        }
+    }
+
+    /// Follow `target`, taking `duration` to fade over to it whenever it
+    /// changes. While a fade is running, returns the old palette and how far
+    /// along (in `0..1`) the fade is at `now`.
+    pub fn update(
+        &mut self,
+        target: Palette,
+        duration: Duration,
+        now: Instant,
+    ) -> Option<(Palette, f32)> {
+        if target != self.current {
+            self.from = Some((self.current, now));
+            self.current = target;
+        }
+
+        let (old, start) = self.from?;
+        let t = now.saturating_duration_since(start).as_secs_f32() / duration.as_secs_f32();
+        if t < 1.0 {
+            Some((old, t))
+        } else {
+            self.from = None;
+            None
+        }
+    }
+}
+
+/// How solid cells are filled in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SolidStyle {
+    Flat,
+    Checker,
+    Hatch,
+}
+
+const SOLID_BRIGHT: u8 = 0xff;
+const SOLID_DIM: u8 = 0xa0;
+
+/// Green channel for a solid cell at `(x, y)`.
+pub fn solid_green(style: SolidStyle, x: usize, y: usize) -> u8 {
+    let bright = match style {
+        SolidStyle::Flat => true,
+        SolidStyle::Checker => (x + y).is_multiple_of(2),
+        SolidStyle::Hatch => (x + y).is_multiple_of(4),
+    };
+    if bright {
+        SOLID_BRIGHT
+    } else {
+        SOLID_DIM
+    }
+}
+
+/// How the non-fluid materials are drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialStyle {
+    pub solid_style: SolidStyle,
+    pub solid_color: [u8; 3],
+    pub emitter_color: [u8; 3],
+}
+
+/// The color of a `material` cell at `(x, y)` holding pressure `p`. Fluid
+/// and membranes are colored by `fluid`, given the pressure and a green
+/// level to mix in.
+pub fn material_color(
+    material: Material,
+    p: f32,
+    (x, y): (usize, usize),
+    style: &MaterialStyle,
+    fluid: impl FnOnce(f32, u8) -> [u8; 4],
+) -> [u8; 4] {
+    match material {
+        Material::Solid => shade(style.solid_color, solid_green(style.solid_style, x, y)),
+        // Pulses with what it's putting out, so it reads as a source.
+        Material::Emitter => {
+            let level = 0x80 + (p.abs().min(1.0) * 127.0) as u8;
+            shade(style.emitter_color, level)
+        }
+        Material::Membrane(_) => fluid(p, 0x60),
+        Material::Fluid => fluid(p, 0x00),
+    }
+}
+
+/// `color` dimmed to `level` out of 0xff, as opaque RGBA.
+pub fn shade(color: [u8; 3], level: u8) -> [u8; 4] {
+    let [r, g, b] = color.map(|c| (c as u16 * level as u16 / 0xff) as u8);
+    [r, g, b, 0xff]
+}
+
+/// Linear blend from `a` toward `b`; `t` is clamped to `0..=1`.
+pub fn blend(a: [u8; 4], b: [u8; 4], t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8;
+    }
+    out
+}
+
+/// A velocity as a color: its direction picks the hue (rightward is red,
+/// then through yellow and green as it turns toward +y), and its magnitude
+/// times `gain`, capped at 1, is the alpha. Meant to be blended over the
+/// pressure colors.
+pub fn velocity_rgba(v: Vec2, gain: f32) -> [u8; 4] {
+    // Checked before capping, since `min` would turn NaN into 1.
+    let magnitude = v.length() * gain;
+    if !magnitude.is_finite() || magnitude <= 0.0 {
+        return [0, 0, 0, 0];
+    }
+    let magnitude = magnitude.min(1.0);
+    let hue = v.y.atan2(v.x).to_degrees().rem_euclid(360.0);
+    let [r, g, b] = hue_rgb(hue);
+    [r, g, b, (magnitude * 255.0).round() as u8]
+}
+
+/// A fully saturated, full brightness color of `hue` degrees.
+fn hue_rgb(hue: f32) -> [u8; 3] {
+    let sector = hue / 60.0;
+    let rising = ((sector % 1.0) * 255.0).round() as u8;
+    let falling = 0xff - rising;
+    match sector as u32 % 6 {
+        0 => [0xff, rising, 0x00],
+        1 => [falling, 0xff, 0x00],
+        2 => [0x00, 0xff, rising],
+        3 => [0x00, falling, 0xff],
+        4 => [rising, 0x00, 0xff],
+        _ => [0xff, 0x00, falling],
+    }
+}
+
+/// The cell whose velocity stands for `(x, y)` when the overlay shows only
+/// every `stride`th cell each way: the corner of its `stride` x `stride`
+/// block. Strides below 1 count as 1.
+pub fn stride_cell(x: usize, y: usize, stride: usize) -> (usize, usize) {
+    let stride = stride.max(1);
+    (x - x % stride, y - y % stride)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_patterns_alternate_between_neighbors() {
+        assert_ne!(
+            solid_green(SolidStyle::Checker, 3, 5),
+            solid_green(SolidStyle::Checker, 4, 5)
+        );
+        assert_ne!(
+            solid_green(SolidStyle::Checker, 3, 5),
+            solid_green(SolidStyle::Checker, 3, 6)
+        );
+        assert_eq!(
+            solid_green(SolidStyle::Checker, 3, 5),
+            solid_green(SolidStyle::Checker, 4, 6)
+        );
+        let hatch: Vec<u8> = (0..8)
+            .map(|x| solid_green(SolidStyle::Hatch, x, 1))
+            .collect();
+        assert_eq!(hatch.iter().filter(|&&g| g == SOLID_BRIGHT).count(), 2);
+        assert_eq!(hatch[3], SOLID_BRIGHT);
+        assert_eq!(
+            solid_green(SolidStyle::Flat, 1, 0),
+            solid_green(SolidStyle::Flat, 0, 0)
+        );
+    }
+
+    #[test]
+    fn letterbox_keeps_the_grid_aspect_centered() {
+        let pillarbox = letterbox_viewport((1000, 500), (100, 100));
+        assert_eq!(
+            pillarbox,
+            Viewport {
+                x: 250,
+                y: 0,
+                width: 500,
+                height: 500
+            }
+        );
+        let letterbox = letterbox_viewport((400, 900), (200, 100));
+        assert_eq!(
+            letterbox,
+            Viewport {
+                x: 0,
+                y: 350,
+                width: 400,
+                height: 200
+            }
+        );
+        let exact = letterbox_viewport((640, 480), (320, 240));
+        assert_eq!(
+            exact,
+            Viewport {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 480
+            }
+        );
+        let tiny = letterbox_viewport((1, 1000), (1000, 1));
+        assert_eq!((tiny.width, tiny.height), (1, 1));
+    }
+
+    #[test]
+    fn locked_scale_keeps_colors_still() {
+        let mut tone_map = ToneMap::new();
+        let quiet = [0.1, -0.5, 0.25];
+        let loud = [0.1, -2.0, 0.25];
+        let locked = tone_map.gain(true, false, &quiet);
+        assert_eq!(locked, 2.0);
+        for _ in 0..3 {
+            let gain = tone_map.gain(true, true, &loud);
+            assert_eq!(
+                pressure_rgba(0.25 * gain, 0, NON_FINITE_RGB),
+                pressure_rgba(0.25 * locked, 0, NON_FINITE_RGB)
+            );
+        }
+        assert_eq!(tone_map.gain(true, false, &loud), 0.5);
+    }
+
+    #[test]
+    fn bipolar_splits_at_its_midpoint() {
+        let palette = Palette::Bipolar {
+            positive: [0xff, 0x80, 0x00],
+            negative: [0x00, 0x40, 0xff],
+            midpoint: 0.5,
+        };
+        assert_eq!(palette.rgba(0.5, 0, NON_FINITE_RGB), [0, 0, 0, 0xff]);
+        assert_eq!(
+            palette.rgba(1.5, 0, NON_FINITE_RGB),
+            [0xff, 0x80, 0x00, 0xff]
+        );
+        assert_eq!(
+            palette.rgba(-0.5, 0, NON_FINITE_RGB),
+            [0x00, 0x40, 0xff, 0xff]
+        );
+        // Half way to full color, still in the positive hue.
+        assert_eq!(
+            palette.rgba(1.0, 0, NON_FINITE_RGB),
+            [0x7f, 0x40, 0x00, 0xff]
+        );
+    }
+
+    #[test]
+    fn halfway_through_a_fade_is_the_average_of_both_palettes() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut fade = PaletteFade::new(Palette::Viridis);
+        assert_eq!(fade.update(Palette::Viridis, second, start), None);
+        assert_eq!(
+            fade.update(Palette::Grayscale, second, start),
+            Some((Palette::Viridis, 0.0))
+        );
+
+        let (old, t) = fade
+            .update(Palette::Grayscale, second, start + second / 2)
+            .unwrap();
+        let from = old.rgba(0.3, 0, NON_FINITE_RGB);
+        let to = Palette::Grayscale.rgba(0.3, 0, NON_FINITE_RGB);
+        let mid = blend(from, to, t);
+        for c in 0..4 {
+            let average = (from[c] as f32 + to[c] as f32) / 2.0;
+            assert!(
+                (mid[c] as f32 - average).abs() <= 0.5,
+                "{mid:?} vs {from:?}/{to:?}"
+            );
+        }
+        assert_eq!(
+            fade.update(Palette::Grayscale, second, start + second),
+            None
+        );
+    }
+
+    #[test]
+    fn snapping_keeps_whole_blocks_and_whole_logical_pixels() {
+        assert_eq!(snap_to_grid((1600, 1200), (512, 512), 1.0), (1024, 1024));
+        assert_eq!(snap_to_grid((300, 200), (512, 512), 1.0), (512, 512));
+        // 6 pixels a cell is 400x200 logical at 1.5x; 5 or 4 wouldn't be.
+        assert_eq!(snap_to_grid((650, 400), (100, 50), 1.5), (600, 300));
+        assert_eq!(snap_to_grid((550, 400), (100, 50), 1.5), (300, 150));
+        // No block size lands on whole logical pixels; keep the biggest fit.
+        assert_eq!(snap_to_grid((300, 300), (64, 64), 1.25), (256, 256));
+    }
+
+    #[test]
+    fn surface_mapping_follows_the_scaling_mode() {
+        // Scaled by a whole 2x and centered in the spare 150x50.
+        let scaled = pixel_to_surface(10.0, 20.0, (100, 100), (350, 250), false);
+        assert_eq!(scaled, (95.0, 65.0));
+        // Fitted: the buffer is the viewport, pillarboxed 250 in from the left.
+        let fitted = pixel_to_surface(100.0, 50.0, (500, 500), (1000, 500), true);
+        assert_eq!(fitted, (350.0, 50.0));
+        // A buffer that's a touch off the viewport is stretched onto it.
+        let stretched = pixel_to_surface(300.0, 0.0, (600, 300), (1201, 600), true);
+        assert_eq!(stretched, (600.0, 0.0));
+    }
+
+    #[test]
+    fn circle_points_ring_the_cell_center() {
+        let points = circle_points((4, -2), 3.0, 8);
+        assert_eq!(points.len(), 8);
+        assert_eq!(points[0], (7.5, -1.5));
+        for (x, y) in &points {
+            let r = ((x - 4.5).powi(2) + (y + 1.5).powi(2)).sqrt();
+            assert!((r - 3.0).abs() < 1e-5);
+        }
+        assert!((points[2].0 - 4.5).abs() < 1e-5 && (points[2].1 - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn emitters_and_solids_color_differently_at_the_same_pressure() {
+        let style = MaterialStyle {
+            solid_style: SolidStyle::Flat,
+            solid_color: SOLID_RGB,
+            emitter_color: EMITTER_RGB,
+        };
+        let fluid = |p, g| pressure_rgba(p, g, NON_FINITE_RGB);
+        let color = |material, p| material_color(material, p, (2, 3), &style, fluid);
+        for p in [-1.5, -0.3, 0.0, 0.4, 2.0] {
+            let (solid, emitter) = (color(Material::Solid, p), color(Material::Emitter, p));
+            assert_ne!(solid, emitter, "at {p}");
+            assert_ne!(emitter, color(Material::Fluid, p), "at {p}");
+        }
+        // Emitters brighten with their output; walls don't.
+        assert!(color(Material::Emitter, 0.9)[0] > color(Material::Emitter, 0.1)[0]);
+        assert_eq!(color(Material::Solid, 0.9), color(Material::Solid, 0.1));
+    }
+
+    #[test]
+    fn grayscale_brightens_steadily_and_zero_sits_mid_palette() {
+        let levels: Vec<u8> = (-12..=12)
+            .map(|i| Palette::Grayscale.sample(i as f32 / 10.0)[0])
+            .collect();
+        assert!(
+            levels.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{levels:?}"
+        );
+        assert_eq!((levels[0], levels[24]), (0, 0xff));
+        assert_eq!(Palette::Grayscale.sample(0.0), [0x80, 0x80, 0x80, 0xff]);
+
+        assert_eq!(Palette::RedBlue.sample(0.0), [0, 0, 0, 0xff]);
+        assert_eq!(Palette::Viridis.sample(0.0), [0x26, 0x82, 0x8e, 0xff]);
+        assert_eq!(Palette::Turbo.sample(0.0), [0x7e, 0xff, 0x55, 0xff]);
+        for palette in [
+            Palette::RedBlue,
+            Palette::Viridis,
+            Palette::Grayscale,
+            Palette::Turbo,
+        ] {
+            assert_eq!(
+                palette.sample(40.0),
+                palette.sample(1.0),
+                "{}",
+                palette.name()
+            );
+            assert_eq!(
+                palette.sample(-40.0),
+                palette.sample(-1.0),
+                "{}",
+                palette.name()
+            );
+        }
+    }
+
+    #[test]
+    fn big_pressures_saturate_and_non_finite_ones_take_the_configured_color() {
+        let debug = [0x12, 0x34, 0x56];
+        assert_eq!(pressure_rgba(2.0, 0, debug), [0xff, 0, 0, 0xff]);
+        assert_eq!(pressure_rgba(-2.0, 0, debug), [0, 0, 0xff, 0xff]);
+        for p in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(pressure_rgba(p, 0x60, debug), [0x12, 0x34, 0x56, 0xff]);
+            for palette in [
+                Palette::RedBlue,
+                Palette::BIPOLAR,
+                Palette::Viridis,
+                Palette::Turbo,
+            ] {
+                assert_eq!(palette.rgba(p, 0, debug), [0x12, 0x34, 0x56, 0xff]);
+            }
+        }
+    }
+
+    #[test]
+    fn velocity_direction_picks_the_hue_and_speed_the_alpha() {
+        assert_eq!(
+            velocity_rgba(Vec2::new(1.0, 0.0), 1.0),
+            [0xff, 0x00, 0x00, 0xff]
+        );
+        assert_eq!(
+            velocity_rgba(Vec2::new(0.0, 2.0), 1.0),
+            [0x7f, 0xff, 0x00, 0xff]
+        );
+        assert_eq!(
+            velocity_rgba(Vec2::new(-3.0, 0.0), 1.0),
+            [0x00, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            velocity_rgba(Vec2::new(0.0, -1.0), 1.0),
+            [0x80, 0x00, 0xff, 0xff]
+        );
+        // Half the speed is half as opaque, the same hue either way.
+        assert_eq!(
+            velocity_rgba(Vec2::new(0.25, 0.0), 2.0),
+            [0xff, 0x00, 0x00, 0x80]
+        );
+        assert_eq!(velocity_rgba(Vec2::ZERO, 1.0), [0; 4]);
+        assert_eq!(velocity_rgba(Vec2::new(f32::NAN, 0.0), 1.0), [0; 4]);
+    }
+
+    #[test]
+    fn stride_snaps_cells_to_the_corner_of_their_block() {
+        assert_eq!(stride_cell(0, 0, 8), (0, 0));
+        assert_eq!(stride_cell(7, 15, 8), (0, 8));
+        assert_eq!(stride_cell(8, 16, 8), (8, 16));
+        assert_eq!(stride_cell(13, 4, 3), (12, 3));
+        // A stride of one (or a nonsensical zero) shows every cell.
+        assert_eq!(stride_cell(13, 4, 1), (13, 4));
+        assert_eq!(stride_cell(13, 4, 0), (13, 4));
+        // A 512-cell row at stride 8 draws 64 distinct samples.
+        let mut samples: Vec<usize> = (0..512).map(|x| stride_cell(x, 0, 8).0).collect();
+        samples.dedup();
+        assert_eq!(samples.len(), 64);
+    }
+
+    #[test]
+    fn pixels_map_to_cells_round_the_center_and_clamp_at_the_edges() {
+        let (frame, grid) = ((200, 100), (64, 32));
+        // The center is the innermost ring; straight right is half way round.
+        assert_eq!(pixel_to_cell(100, 50, frame, grid), (32, 0));
+        assert_eq!(pixel_to_cell(125, 50, frame, grid), (32, 8));
+        // Straight up is a quarter of the way round, straight down three.
+        assert_eq!(pixel_to_cell(100, 25, frame, grid), (16, 8));
+        assert_eq!(pixel_to_cell(100, 75, frame, grid), (48, 8));
+        // Straight left wraps to the last column rather than past it, and
+        // the corners, past the outermost ring, land on it.
+        assert_eq!(pixel_to_cell(75, 50, frame, grid), (63, 8));
+        assert_eq!(pixel_to_cell(0, 0, frame, grid).1, 31);
+        assert_eq!(pixel_to_cell(199, 99, frame, grid).1, 31);
+        for (px, py) in [(0, 0), (199, 0), (0, 99), (199, 99), (100, 0)] {
+            let (x, y) = pixel_to_cell(px, py, frame, grid);
+            assert!(x < 64 && y < 32, "({px}, {py}) went to ({x}, {y})");
+        }
+
+        // Away from the middle, where cells shrink under a pixel, going out
+        // to a cell's center and back lands on that cell.
+        for (x, y) in [(3, 20), (20, 9), (40, 14), (60, 25)] {
+            let (px, py) = cell_to_pixel(x as f32 + 0.5, y as f32 + 0.5, frame, grid);
+            assert_eq!(pixel_to_cell(px as usize, py as usize, frame, grid), (x, y));
+        }
+    }
+}
@@ -1,3 +1,174 @@
+use glam::Vec2;
+use rayon::prelude::*;
+
+/// What happens to waves at the edges of the grid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum EdgeMode {
+    /// Everything past the edge reads as zero.
+    #[default]
+    Zero,
+    /// First-order radiating (Mur) boundary: edge cells are extrapolated
+    /// from the interior as if the wave kept going, so most of it leaves.
+    Radiating,
+    /// A border `thickness` cells deep where waves are damped more and more
+    /// heavily toward the edge, so they fade out instead of bouncing back.
+    Absorb { thickness: usize },
+    /// Hard walls: nothing flows across the edge, and pressure just past it
+    /// matches the edge cell.
+    Reflect,
+    /// The grid wraps around, so waves leaving one side come back in on the
+    /// other.
+    Periodic,
+}
+impl EdgeMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Zero => "Zero",
+            EdgeMode::Radiating => "Radiating",
+            EdgeMode::Absorb { .. } => "Absorb",
+            EdgeMode::Reflect => "Reflect",
+            EdgeMode::Periodic => "Periodic",
+        }
+    }
+
+    /// The pressure the stencil sees at `(x, y)`, which may be off the grid.
+    pub fn pressure_at<T: Copy + Default>(self, grid: &Array2D<T>, x: isize, y: isize) -> T {
+        match (grid.get(x, y), self) {
+            (Some(&cell), _) => cell,
+            (None, EdgeMode::Periodic) => *grid.get_wrapping(x, y),
+            (None, EdgeMode::Reflect) => {
+                let x = x.clamp(0, grid.width as isize - 1);
+                let y = y.clamp(0, grid.height as isize - 1);
+                grid[(x, y)]
+            }
+            (None, _) => T::default(),
+        }
+    }
+
+    /// The velocity the stencil sees at `(x, y)`, which may be off the grid.
+    /// Walls don't move, so it's zero past a reflecting edge.
+    pub fn velocity_at<T: Copy + Default>(self, grid: &Array2D<T>, x: isize, y: isize) -> T {
+        match (grid.get(x, y), self) {
+            (Some(&cell), _) => cell,
+            (None, EdgeMode::Periodic) => *grid.get_wrapping(x, y),
+            (None, _) => T::default(),
+        }
+    }
+}
+
+/// Most of a cell's value lost per tick, right at the edge of an absorbing
+/// border.
+const ABSORB_STRENGTH: f32 = 0.15;
+
+/// Damp every cell within `thickness` of an edge of `field`, with a ramp
+/// that's gentle on the inside (so the border itself doesn't reflect) and
+/// strongest at the edge.
+pub fn absorb_edges<T>(field: &mut Array2D<T>, thickness: usize)
+where
+    T: Copy + Send + std::ops::Mul<f32, Output = T>,
+{
+    if thickness == 0 {
+        return;
+    }
+    let (w, h) = (field.width, field.height);
+    field.par_for_each_mut_coords(|x, y, cell| {
+        let depth = x.min(y).min(w - 1 - x).min(h - 1 - y);
+        if depth < thickness {
+            let t = (thickness - depth) as f32 / thickness as f32;
+            *cell = *cell * (1.0 - ABSORB_STRENGTH * t * t);
+        }
+    });
+}
+
+/// Each edge cell paired with the cell two in from it, and whether the
+/// edge's normal runs along x.
+fn edge_pairs(w: isize, h: isize) -> impl Iterator<Item = ((isize, isize), (isize, isize), bool)> {
+    let columns = (0..h).flat_map(move |y| [((0, y), (2, y)), ((w - 1, y), (w - 3, y))]);
+    let rows = (0..w).flat_map(move |x| [((x, 0), (x, 2)), ((x, h - 1), (x, h - 3))]);
+    let columns = columns.map(|(edge, inner)| (edge, inner, true));
+    let rows = rows.map(|(edge, inner)| (edge, inner, false));
+    columns.chain(rows)
+}
+
+fn normal(v: Vec2, along_x: bool) -> f32 {
+    if along_x {
+        v.x
+    } else {
+        v.y
+    }
+}
+
+/// The pressure and normal velocity at each of `edge_pairs`' cells, edge
+/// then inner, as [`radiate_edges`] needs them from before a step.
+pub fn sample_edges(pressures: &Array2D<f32>, velocities: &Array2D<Vec2>) -> Vec<[f32; 4]> {
+    let (w, h) = (pressures.width as isize, pressures.height as isize);
+    if w < 3 || h < 3 {
+        return Vec::new();
+    }
+    edge_pairs(w, h)
+        .map(|(edge, inner, along_x)| {
+            [
+                pressures[edge],
+                pressures[inner],
+                normal(velocities[edge], along_x),
+                normal(velocities[inner], along_x),
+            ]
+        })
+        .collect()
+}
+
+/// Mur's first-order absorbing update along every edge, given the wave
+/// speed in cells per tick and what [`sample_edges`] saw before the step.
+///
+/// Each step reads its neighbors from the step before and adds onto the
+/// step before that, so the grid is really two lattices leapfrogging each
+/// other, with a cell's pressure on one and its velocity on the other.
+/// The update runs on each in turn: a cell is extrapolated from the one
+/// two in, two ticks earlier, and both pressure and the normal velocity
+/// are set this way.
+pub fn radiate_edges(
+    pressures: &mut Array2D<f32>,
+    velocities: &mut Array2D<Vec2>,
+    stale: &[[f32; 4]],
+    speed: f32,
+) {
+    let k = (speed - 1.0) / (speed + 1.0);
+    let (w, h) = (pressures.width as isize, pressures.height as isize);
+    for ((edge, inner, along_x), &[p_edge, p_inner, v_edge, v_inner]) in edge_pairs(w, h).zip(stale)
+    {
+        pressures[edge] = p_inner + k * (pressures[inner] - p_edge);
+        let v = normal(velocities[inner], along_x);
+        let v = v_inner + k * (v - v_edge);
+        if along_x {
+            velocities[edge].x = v;
+        } else {
+            velocities[edge].y = v;
+        }
+    }
+}
+
+/// An axis-aligned rectangle of cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CellRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl CellRect {
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+    /// Whether `(x, y)` is one of the rectangle's outermost cells.
+    pub fn on_edge(&self, x: usize, y: usize) -> bool {
+        self.contains(x, y)
+            && (x == self.x
+                || y == self.y
+                || x == self.x + self.width - 1
+                || y == self.y + self.height - 1)
+    }
+}
+
 #[derive(Clone)]
 pub struct Array2D<T> {
     width: usize,
@@ -15,25 +186,255 @@ impl<T> Array2D<T> {
         Array2D {
             width,
             height,
-            storage: std::iter::repeat(val)
-                .take(width.checked_mul(height).unwrap())
-                .collect(),
+            storage: vec![val; width.checked_mul(height).unwrap()],
         }
     }
 
-    pub fn get(&self, x: isize, y: isize) -> Option<&T> {
-        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
-            None
-        } else {
-            self.storage.get((x + (self.width as isize * y)) as usize)
+    /// A grid whose cell `(x, y)` is `f(x, y)`, called once per cell in
+    /// row-major order.
+    pub fn from_fn(
+        width: usize,
+        height: usize,
+        mut f: impl FnMut(usize, usize) -> T,
+    ) -> Array2D<T> {
+        assert_ne!(width, 0);
+        assert_ne!(height, 0);
+
+        let len = width.checked_mul(height).unwrap();
+        Array2D {
+            width,
+            height,
+            storage: (0..len).map(|i| f(i % width, i / width)).collect(),
         }
     }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Flat index of `(x, y)`, if it's on the grid. Compares in `usize` so
+    /// no coordinate or dimension, however extreme, can wrap around.
+    fn index(&self, x: isize, y: isize) -> Option<usize> {
+        let x = usize::try_from(x).ok().filter(|&x| x < self.width)?;
+        let y = usize::try_from(y).ok().filter(|&y| y < self.height)?;
+        // Can't overflow: it's below width * height, which `new` checked.
+        Some(y * self.width + x)
+    }
+
+    pub fn get(&self, x: isize, y: isize) -> Option<&T> {
+        self.storage.get(self.index(x, y)?)
+    }
     pub fn get_mut(&mut self, x: isize, y: isize) -> Option<&mut T> {
-        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
-            None
-        } else {
-            self.storage
-                .get_mut((x + (self.width as isize * y)) as usize)
+        let i = self.index(x, y)?;
+        self.storage.get_mut(i)
+    }
+
+    /// The cell at `(x, y)` on a torus: coordinates wrap around the edges,
+    /// so `(-1, -1)` is the bottom-right corner.
+    pub fn get_wrapping(&self, x: isize, y: isize) -> &T {
+        &self.storage[self.wrapped_index(x, y)]
+    }
+    pub fn get_wrapping_mut(&mut self, x: isize, y: isize) -> &mut T {
+        let i = self.wrapped_index(x, y);
+        &mut self.storage[i]
+    }
+    fn wrapped_index(&self, x: isize, y: isize) -> usize {
+        // Widen so even isize::MIN and a huge dimension can't overflow.
+        let wrap = |v: isize, len: usize| (v as i128).rem_euclid(len as i128) as usize;
+        wrap(y, self.height) * self.width + wrap(x, self.width)
+    }
+
+    /// Set every cell of the `width` x `height` rectangle at `(x0, y0)` to
+    /// `val`, ignoring whatever part of it falls outside the grid.
+    pub fn fill_rect(&mut self, x0: isize, y0: isize, width: usize, height: usize, val: T)
+    where
+        T: Clone,
+    {
+        let clip = |start: isize, len: usize, limit: usize| {
+            let end = start.saturating_add_unsigned(len).clamp(0, limit as isize) as usize;
+            (start.clamp(0, limit as isize) as usize, end)
+        };
+        let (x_start, x_end) = clip(x0, width, self.width);
+        let (y_start, y_end) = clip(y0, height, self.height);
+        if x_start >= x_end {
+            return;
+        }
+        for row in self.rows_mut().take(y_end).skip(y_start) {
+            row[x_start..x_end].fill(val.clone());
+        }
+    }
+
+    /// Interpolate between the four cells around `(x, y)`. Points past the
+    /// edge read the nearest edge cell; whole coordinates give exactly that
+    /// cell.
+    pub fn get_sampled(&self, x: f32, y: f32) -> T
+    where
+        T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+    {
+        let x = x.clamp(0.0, (self.width - 1) as f32);
+        let y = y.clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let at = |x: isize, y: isize| {
+            self[(
+                x.min(self.width as isize - 1),
+                y.min(self.height as isize - 1),
+            )]
+        };
+
+        let top = at(x0, y0) * (1.0 - tx) + at(x0 + 1, y0) * tx;
+        let bottom = at(x0, y0 + 1) * (1.0 - tx) + at(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    /// Each row, top to bottom, as a `width`-long slice.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.storage.chunks_exact(self.width)
+    }
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        self.storage.chunks_exact_mut(self.width)
+    }
+
+    /// Every cell with its `(x, y)`, in row-major order.
+    pub fn iter_coords(&self) -> impl Iterator<Item = (usize, usize, &T)> {
+        self.rows()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, cell)| (x, y, cell)))
+    }
+
+    pub fn iter_coords_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut T)> {
+        self.rows_mut().enumerate().flat_map(|(y, row)| {
+            row.iter_mut()
+                .enumerate()
+                .map(move |(x, cell)| (x, y, cell))
+        })
+    }
+
+    /// Every cell with its `(x, y)`, in parallel. Indexed, so it zips with the
+    /// other grids' `par_iter`s.
+    pub fn par_iter_coords(&self) -> impl IndexedParallelIterator<Item = (usize, usize, &T)>
+    where
+        T: Sync,
+    {
+        let width = self.width;
+        self.storage
+            .par_iter()
+            .enumerate()
+            .map(move |(i, cell)| (i % width, i / width, cell))
+    }
+
+    /// A same-sized grid of `f` applied to every cell.
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Array2D<U> {
+        Array2D {
+            width: self.width,
+            height: self.height,
+            storage: self.storage.iter().map(f).collect(),
+        }
+    }
+
+    /// [`map`](Self::map), in parallel.
+    pub fn par_map<U: Send>(&self, f: impl Fn(&T) -> U + Sync + Send) -> Array2D<U>
+    where
+        T: Sync,
+    {
+        Array2D {
+            width: self.width,
+            height: self.height,
+            storage: self.storage.par_iter().map(f).collect(),
+        }
+    }
+
+    /// Call `f(x, y, cell)` for every cell, in parallel.
+    pub fn par_for_each_mut_coords(&mut self, f: impl Fn(usize, usize, &mut T) + Sync)
+    where
+        T: Send,
+    {
+        let width = self.width;
+        self.storage
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, cell)| f(i % width, i / width, cell));
+    }
+}
+impl Array2D<f32> {
+    /// Add a Gaussian bump of the given peak `amplitude` centered at `(cx, cy)`.
+    pub fn stamp_gaussian(&mut self, cx: f32, cy: f32, sigma: f32, amplitude: f32) {
+        let denom = 2.0 * sigma * sigma;
+        self.par_for_each_mut_coords(|x, y, cell| {
+            let dx = x as f32 - cx;
+            let dy = y as f32 - cy;
+            *cell += amplitude * (-(dx * dx + dy * dy) / denom).exp();
+        });
+    }
+
+    /// The mean over the part of `rect` inside the grid, or zero if none of
+    /// it is.
+    pub fn mean_over(&self, rect: CellRect) -> f32 {
+        let cells = rect.width.min(self.width.saturating_sub(rect.x))
+            * rect.height.min(self.height.saturating_sub(rect.y));
+        if cells == 0 {
+            return 0.0;
+        }
+        self.sum_over(rect) / cells as f32
+    }
+
+    /// The total over the part of `rect` inside the grid.
+    pub fn sum_over(&self, rect: CellRect) -> f32 {
+        let x_end = (rect.x + rect.width).min(self.width);
+        let y_end = (rect.y + rect.height).min(self.height);
+        if rect.x >= x_end || rect.y >= y_end {
+            return 0.0;
+        }
+
+        (rect.y..y_end)
+            .map(|y| {
+                self.storage[y * self.width + rect.x..y * self.width + x_end]
+                    .iter()
+                    .sum::<f32>()
+            })
+            .sum()
+    }
+
+    /// Replace each cell with the mean of its 3x3 neighborhood, clamped at
+    /// the edges.
+    pub fn box_blur(&mut self) {
+        let src = self.clone();
+        self.par_for_each_mut_coords(|x, y, cell| {
+            let (x, y) = (x as isize, y as isize);
+            let mut sum = 0.0;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let nx = (x + dx).clamp(0, src.width as isize - 1);
+                    let ny = (y + dy).clamp(0, src.height as isize - 1);
+                    sum += src.get(nx, ny).unwrap();
+                }
+            }
+            *cell = sum / 9.0;
+        });
+    }
+}
+impl<T> std::ops::Index<(isize, isize)> for Array2D<T> {
+    type Output = T;
+    fn index(&self, (x, y): (isize, isize)) -> &T {
+        match self.get(x, y) {
+            Some(cell) => cell,
+            None => panic!(
+                "coordinate ({x}, {y}) out of bounds for {}x{} grid",
+                self.width, self.height
+            ),
+        }
+    }
+}
+impl<T> std::ops::IndexMut<(isize, isize)> for Array2D<T> {
+    fn index_mut(&mut self, (x, y): (isize, isize)) -> &mut T {
+        let (width, height) = (self.width, self.height);
+        match self.get_mut(x, y) {
+            Some(cell) => cell,
+            None => panic!("coordinate ({x}, {y}) out of bounds for {width}x{height} grid"),
         }
     }
 }
@@ -48,3 +449,1434 @@ impl<T> std::ops::DerefMut for Array2D<T> {
         &mut self.storage
     }
 }
+
+/// A grid with a one-cell ghost border around it, so every neighbor of a
+/// cell is a plain offset away instead of a trip through the edge mode. The
+/// border holds whatever [`Haloed::refresh`] was told lies past the edge.
+#[derive(Clone)]
+pub struct Haloed<T> {
+    padded: Array2D<T>,
+}
+impl<T: Copy> Haloed<T> {
+    /// A `width` x `height` grid, plus its border, filled with `val`.
+    pub fn new(width: usize, height: usize, val: T) -> Haloed<T> {
+        Haloed {
+            padded: Array2D::new(width + 2, height + 2, val),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.padded.width - 2
+    }
+    pub fn height(&self) -> usize {
+        self.padded.height - 2
+    }
+
+    /// Copy `grid` into the middle, and set each border cell to
+    /// `outside(x, y)` for its coordinates on `grid` (so -1, or the width or
+    /// height, on at least one axis).
+    pub fn refresh(&mut self, grid: &Array2D<T>, outside: impl Fn(isize, isize) -> T) {
+        assert_eq!(
+            (grid.width, grid.height),
+            (self.width(), self.height()),
+            "grid doesn't match the halo"
+        );
+        for (padded, row) in self.padded.rows_mut().skip(1).zip(grid.rows()) {
+            padded[1..=row.len()].copy_from_slice(row);
+        }
+
+        let (w, h) = (grid.width as isize, grid.height as isize);
+        for x in -1..=w {
+            self.padded[(x + 1, 0)] = outside(x, -1);
+            self.padded[(x + 1, h + 1)] = outside(x, h);
+        }
+        for y in 0..h {
+            self.padded[(0, y + 1)] = outside(-1, y);
+            self.padded[(w + 1, y + 1)] = outside(w, y);
+        }
+    }
+
+    /// The cell at `(x, y)` on the grid, which may be one cell into the
+    /// border, without any edge handling. Further out than that is a bug:
+    /// debug builds panic, release builds read some other cell or panic.
+    pub fn get_unchecked_interior(&self, x: isize, y: isize) -> &T {
+        debug_assert!(
+            (-1..=self.width() as isize).contains(&x) && (-1..=self.height() as isize).contains(&y),
+            "({x}, {y}) is past the halo of a {}x{} grid",
+            self.width(),
+            self.height()
+        );
+        let i = (y + 1) as usize * self.padded.width + (x + 1) as usize;
+        &self.padded.storage[i]
+    }
+}
+
+/// What a cell is made of.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Material {
+    #[default]
+    Fluid,
+    /// A rigid wall: nothing flows into it, and waves bounce off it the
+    /// right way up.
+    Solid,
+    /// Held at [`WaveField::emitter_pressure`] every step.
+    Emitter,
+    /// A flexible boundary: passes some pressure through and reflects the
+    /// rest, more so the higher its tension.
+    Membrane(f32),
+}
+impl Material {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Material::Fluid => "Fluid",
+            Material::Solid => "Solid",
+            Material::Emitter => "Emitter",
+            Material::Membrane(_) => "Membrane",
+        }
+    }
+}
+
+/// The largest `grad_alpha` the stencil is stable at. Past it the finest
+/// (checkerboard) ripples grow every tick until the field overflows.
+pub const MAX_STABLE_ALPHA: f32 = 0.5;
+
+/// Overrides of the global parameters for one cell; `None` falls back to
+/// [`WaveParams`].
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct LocalParams {
+    pub grad_alpha: Option<f32>,
+    pub grad_damping: Option<f32>,
+    /// Scales `grad_alpha`, so waves travel `sqrt(stiffness)` times as fast
+    /// here as they otherwise would.
+    pub stiffness: Option<f32>,
+}
+impl LocalParams {
+    /// This cell's `(grad_alpha, grad_damping)`. Alpha is capped at
+    /// [`MAX_STABLE_ALPHA`], so no combination of settings blows up.
+    pub fn resolve(&self, params: &WaveParams) -> (f32, f32) {
+        let alpha = self.grad_alpha.unwrap_or(params.grad_alpha) * self.stiffness.unwrap_or(1.0);
+        (
+            alpha.min(MAX_STABLE_ALPHA),
+            self.grad_damping.unwrap_or(params.grad_damping),
+        )
+    }
+}
+
+/// The settings that govern how waves move: the physics half of the app's
+/// `SimParams`, which holds one of these alongside everything to do with
+/// audio and display.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WaveParams {
+    pub grad_alpha: f32,
+    /// Fraction of its velocity a cell keeps each tick: 1 is undamped, lower
+    /// values lose energy faster.
+    pub grad_damping: f32,
+    /// What waves do when they reach the edge of the grid.
+    pub edge_mode: EdgeMode,
+    /// Carry velocity along with itself (nonlinear); off is the plain linear
+    /// wave equation.
+    pub advection: bool,
+}
+impl WaveParams {
+    /// Whether these settings run as given; otherwise alpha gets capped.
+    pub fn is_stable(&self) -> bool {
+        self.grad_alpha <= MAX_STABLE_ALPHA
+    }
+}
+impl Default for WaveParams {
+    fn default() -> Self {
+        WaveParams {
+            grad_alpha: 0.1,
+            grad_damping: 0.9999,
+            edge_mode: EdgeMode::default(),
+            advection: false,
+        }
+    }
+}
+
+/// A ramp of extra damping to lay over part of a field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DampingRamp {
+    /// See [`WaveField::damp_rect`].
+    Rect {
+        rect: CellRect,
+        horizontal: bool,
+        start: f32,
+        end: f32,
+    },
+    /// See [`WaveField::damp_radial`].
+    Radial {
+        center: (f32, f32),
+        radius: f32,
+        center_value: f32,
+    },
+}
+
+/// Every grid a step depends on, for saving a field and restoring it
+/// exactly.
+#[derive(Clone)]
+pub struct FieldState {
+    /// Now, and one step ago.
+    pub pressures: [Array2D<f32>; 2],
+    pub velocities: [Array2D<Vec2>; 2],
+    pub materials: Array2D<Material>,
+    pub local_params: Array2D<LocalParams>,
+    pub damping: Array2D<f32>,
+}
+
+/// Pressure and velocity over a grid of materials, and the stencil that
+/// moves them forward in time.
+pub struct WaveField {
+    pub pressures: Array2D<f32>,
+    pressures_back: Array2D<f32>,
+    pub velocities: Array2D<Vec2>,
+    velocities_back: Array2D<Vec2>,
+    materials: Array2D<Material>,
+    /// Painted overrides of the global parameters.
+    pub local_params: Array2D<LocalParams>,
+    /// Extra damping, independent of material: every tick each cell's
+    /// pressure and velocity are multiplied by its value here. 1 (the
+    /// default) adds none.
+    pub damping: Array2D<f32>,
+    /// Read neighbors from bordered copies of the back buffers, refreshed
+    /// every step, rather than through the edge mode cell by cell. The
+    /// results are the same either way; this is just faster.
+    pub halo: bool,
+    pressure_halo: Haloed<f32>,
+    velocity_halo: Haloed<Vec2>,
+    pub params: WaveParams,
+    /// What emitter cells are held at on the next step.
+    pub emitter_pressure: f32,
+    /// Coordinates of every non-fluid cell. Rebuilt at the start of the next
+    /// step whenever `materials_dirty` is set, which `materials_mut` does.
+    special_cells: Vec<(usize, usize)>,
+    materials_dirty: bool,
+    /// Set once any cell has gone infinite or NaN.
+    diverged: bool,
+    /// Cells the last step found infinite or NaN and zeroed.
+    sanitized: usize,
+}
+impl WaveField {
+    /// A still field of fluid.
+    pub fn new(width: usize, height: usize, params: WaveParams) -> WaveField {
+        WaveField {
+            pressures: Array2D::new(width, height, 0.0),
+            pressures_back: Array2D::new(width, height, 0.0),
+            velocities: Array2D::new(width, height, Vec2::ZERO),
+            velocities_back: Array2D::new(width, height, Vec2::ZERO),
+            materials: Array2D::new(width, height, Material::Fluid),
+            local_params: Array2D::new(width, height, LocalParams::default()),
+            damping: Array2D::new(width, height, 1.0),
+            halo: true,
+            pressure_halo: Haloed::new(width, height, 0.0),
+            velocity_halo: Haloed::new(width, height, Vec2::ZERO),
+            params,
+            emitter_pressure: 0.0,
+            special_cells: Vec::new(),
+            materials_dirty: true,
+            diverged: false,
+            sanitized: 0,
+        }
+    }
+
+    /// Width and height of the grid, in cells.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.pressures.width(), self.pressures.height())
+    }
+
+    pub fn materials(&self) -> &Array2D<Material> {
+        &self.materials
+    }
+    /// The materials, for editing; they're rescanned before the next step.
+    pub fn materials_mut(&mut self) -> &mut Array2D<Material> {
+        self.materials_dirty = true;
+        &mut self.materials
+    }
+
+    /// Replace the pressure field, as it is now and as it was a step ago, so
+    /// it starts out still.
+    pub fn set_pressures(&mut self, pressures: Array2D<f32>) {
+        assert_eq!(
+            (pressures.width(), pressures.height()),
+            self.dimensions(),
+            "new pressures don't match the grid"
+        );
+        self.pressures_back = pressures.clone();
+        self.pressures = pressures;
+    }
+
+    /// A copy of everything the next step depends on.
+    pub fn state(&self) -> FieldState {
+        FieldState {
+            pressures: [self.pressures.clone(), self.pressures_back.clone()],
+            velocities: [self.velocities.clone(), self.velocities_back.clone()],
+            materials: self.materials.clone(),
+            local_params: self.local_params.clone(),
+            damping: self.damping.clone(),
+        }
+    }
+
+    /// Pick up from a saved `state`, which has to be the same size as the
+    /// field.
+    pub fn restore(&mut self, state: FieldState) -> Result<(), String> {
+        let dims = self.dimensions();
+        let sizes = [
+            (state.pressures[0].width(), state.pressures[0].height()),
+            (state.pressures[1].width(), state.pressures[1].height()),
+            (state.velocities[0].width(), state.velocities[0].height()),
+            (state.velocities[1].width(), state.velocities[1].height()),
+            (state.materials.width(), state.materials.height()),
+            (state.local_params.width(), state.local_params.height()),
+            (state.damping.width(), state.damping.height()),
+        ];
+        if let Some(&(width, height)) = sizes.iter().find(|&&size| size != dims) {
+            return Err(format!(
+                "saved {width}x{height} grid doesn't fit the {}x{} field",
+                dims.0, dims.1
+            ));
+        }
+
+        let [pressures, pressures_back] = state.pressures;
+        let [velocities, velocities_back] = state.velocities;
+        self.pressures = pressures;
+        self.pressures_back = pressures_back;
+        self.velocities = velocities;
+        self.velocities_back = velocities_back;
+        self.materials = state.materials;
+        self.local_params = state.local_params;
+        self.damping = state.damping;
+        self.materials_dirty = true;
+        self.diverged = false;
+        self.sanitized = 0;
+        Ok(())
+    }
+
+    /// Every grid is indexed with the same coordinates, so they had better
+    /// all be the same size.
+    pub fn assert_consistent(&self) {
+        let dims = self.dimensions();
+        let grids = [
+            (
+                "pressures_back",
+                self.pressures_back.width(),
+                self.pressures_back.height(),
+            ),
+            (
+                "velocities",
+                self.velocities.width(),
+                self.velocities.height(),
+            ),
+            (
+                "velocities_back",
+                self.velocities_back.width(),
+                self.velocities_back.height(),
+            ),
+            ("materials", self.materials.width(), self.materials.height()),
+            (
+                "local_params",
+                self.local_params.width(),
+                self.local_params.height(),
+            ),
+            ("damping", self.damping.width(), self.damping.height()),
+            (
+                "pressure_halo",
+                self.pressure_halo.width(),
+                self.pressure_halo.height(),
+            ),
+            (
+                "velocity_halo",
+                self.velocity_halo.width(),
+                self.velocity_halo.height(),
+            ),
+        ];
+        for (name, width, height) in grids {
+            assert_eq!(
+                (width, height),
+                dims,
+                "{name} doesn't match the pressure grid"
+            );
+        }
+    }
+
+    /// Whether the field has blown up since it was made.
+    pub fn diverged(&self) -> bool {
+        self.diverged
+    }
+    /// How many cells the last step had to zero because they'd gone
+    /// infinite or NaN.
+    pub fn sanitized(&self) -> usize {
+        self.sanitized
+    }
+
+    /// Ramp the damping across `rect` from `start` at its left edge (or top,
+    /// unless `horizontal`) to `end` at the opposite one. Cells only ever
+    /// get more damped, so overlapping ramps don't undo each other.
+    pub fn damp_rect(&mut self, rect: CellRect, horizontal: bool, start: f32, end: f32) {
+        let span = if horizontal { rect.width } else { rect.height };
+        let span = span.saturating_sub(1).max(1) as f32;
+        for (x, y, cell) in self.damping.iter_coords_mut() {
+            if rect.contains(x, y) {
+                let along = if horizontal { x - rect.x } else { y - rect.y };
+                let t = along as f32 / span;
+                *cell = cell.min(start + (end - start) * t);
+            }
+        }
+    }
+
+    /// Damp cells around `center` down to `center_value`, easing back to no
+    /// extra damping `radius` cells out. Like [`WaveField::damp_rect`], it
+    /// only ever adds damping.
+    pub fn damp_radial(&mut self, center: (f32, f32), radius: f32, center_value: f32) {
+        for (x, y, cell) in self.damping.iter_coords_mut() {
+            let distance = Vec2::new(x as f32 - center.0, y as f32 - center.1).length();
+            if distance < radius {
+                let t = distance / radius;
+                *cell = cell.min(center_value + (1.0 - center_value) * t);
+            }
+        }
+    }
+
+    /// Lay `ramp` over the damping.
+    pub fn damp(&mut self, ramp: DampingRamp) {
+        match ramp {
+            DampingRamp::Rect {
+                rect,
+                horizontal,
+                start,
+                end,
+            } => self.damp_rect(rect, horizontal, start, end),
+            DampingRamp::Radial {
+                center,
+                radius,
+                center_value,
+            } => self.damp_radial(center, radius, center_value),
+        }
+    }
+
+    /// Total pressure over `rect`, across both buffers. Each step writes over
+    /// the buffer from two steps back, so either one alone swings from tick
+    /// to tick even while the sum holds still.
+    pub fn charge_over(&self, rect: CellRect) -> f32 {
+        self.pressures.sum_over(rect) + self.pressures_back.sum_over(rect)
+    }
+
+    /// Kinetic plus potential energy over every cell waves move through
+    /// (fluid and membranes), summed in `f64` so a big grid doesn't lose the
+    /// small changes. Velocity counts divided by the cell's `grad_alpha`,
+    /// which is what keeps the total constant while nothing is damped.
+    pub fn total_energy(&self) -> f64 {
+        let params = self.params;
+        self.pressures
+            .par_iter()
+            .zip(self.velocities.par_iter())
+            .zip(self.materials.par_iter().zip(self.local_params.par_iter()))
+            .filter(|(_, (material, _))| !matches!(material, Material::Solid | Material::Emitter))
+            .map(|((&p, v), (_, local))| {
+                let (alpha, _) = local.resolve(&params);
+                let kinetic = if alpha > 0.0 {
+                    v.length_squared() as f64 / alpha as f64
+                } else {
+                    0.0
+                };
+                0.5 * (p as f64 * p as f64 + kinetic)
+            })
+            .sum()
+    }
+
+    /// Advance the field by one tick.
+    pub fn step(&mut self) {
+        std::mem::swap(&mut self.pressures, &mut self.pressures_back);
+        std::mem::swap(&mut self.velocities, &mut self.velocities_back);
+        // The front is still two steps old here, which is what the radiating
+        // edge extrapolates from.
+        let stale_edges = match self.params.edge_mode {
+            EdgeMode::Radiating => sample_edges(&self.pressures, &self.velocities),
+            _ => Vec::new(),
+        };
+
+        if self.materials_dirty {
+            self.special_cells = self
+                .materials
+                .iter_coords()
+                .filter(|&(_, _, &mat)| mat != Material::Fluid)
+                .map(|(x, y, _)| (x, y))
+                .collect();
+            self.materials_dirty = false;
+        }
+
+        let params = self.params;
+        let edge = params.edge_mode;
+        let front = (&mut self.pressures, &mut self.velocities);
+        let back = (&self.pressures_back, &self.velocities_back);
+        let cells = (&self.local_params, &self.damping);
+        if self.halo {
+            let (pressures, velocities) = back;
+            self.pressure_halo
+                .refresh(pressures, |x, y| edge.pressure_at(pressures, x, y));
+            self.velocity_halo
+                .refresh(velocities, |x, y| edge.velocity_at(velocities, x, y));
+            let (pressure_halo, velocity_halo) = (&self.pressure_halo, &self.velocity_halo);
+            stencil(
+                front,
+                back,
+                cells,
+                &params,
+                |x, y| *pressure_halo.get_unchecked_interior(x, y),
+                |x, y| *velocity_halo.get_unchecked_interior(x, y),
+            );
+        } else {
+            let (pressures, velocities) = back;
+            stencil(
+                front,
+                back,
+                cells,
+                &params,
+                |x, y| edge.pressure_at(pressures, x, y),
+                |x, y| edge.velocity_at(velocities, x, y),
+            );
+        }
+
+        // Everything above treated every cell as fluid; patch up the few
+        // that aren't.
+        for &(x, y) in &self.special_cells {
+            let i = (x as isize, y as isize);
+            match self.materials[i] {
+                Material::Fluid => {}
+                Material::Membrane(tension) => {
+                    // Only part of the flow makes it through; the rest
+                    // bounces back. The stencil took away all of it, so give
+                    // back the part that doesn't.
+                    let coupling = 1.0 / (1.0 + tension);
+                    let velocity_at = |x, y| edge.velocity_at(&self.velocities_back, x, y);
+                    let inflow = net_inflow(velocity_at, i.0, i.1);
+                    self.pressures[i] += (1.0 - coupling) * inflow * self.damping[i];
+                    self.velocities[i] *= coupling;
+                }
+                Material::Emitter => {
+                    self.pressures[i] = self.emitter_pressure;
+                }
+                Material::Solid => {
+                    self.pressures[i] = 0.0;
+                    self.velocities[i] = Vec2::ZERO;
+                    // The stencil read this cell as still water. Fluid next to
+                    // it should have seen a rigid wall instead, which mirrors
+                    // its own pressure and flow back at it, so nothing leaks
+                    // through and a sealed box keeps what's inside it.
+                    for dir in [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y] {
+                        let n = (i.0 - dir.x as isize, i.1 - dir.y as isize);
+                        if self.materials.get(n.0, n.1) != Some(&Material::Fluid) {
+                            continue;
+                        }
+                        let (grad_alpha, grad_damping) = self.local_params[n].resolve(&params);
+                        let damping = self.damping[n];
+                        let gap = self.pressures_back[n] - self.pressures_back[i];
+                        let flow = (self.velocities_back[n] + self.velocities_back[i]).dot(dir);
+                        self.velocities[n] += dir * gap * grad_alpha * grad_damping * damping;
+                        self.pressures[n] -= flow * damping;
+                    }
+                }
+            }
+        }
+
+        match params.edge_mode {
+            EdgeMode::Zero | EdgeMode::Reflect | EdgeMode::Periodic => {}
+            EdgeMode::Radiating => {
+                // v += alpha * grad p and p -= div v make a wave equation with
+                // speed sqrt(alpha) cells per tick.
+                let speed = params.grad_alpha.max(0.0).sqrt();
+                radiate_edges(
+                    &mut self.pressures,
+                    &mut self.velocities,
+                    &stale_edges,
+                    speed,
+                );
+            }
+            EdgeMode::Absorb { thickness } => {
+                absorb_edges(&mut self.pressures, thickness);
+                absorb_edges(&mut self.velocities, thickness);
+            }
+        }
+
+        // Anything that's blown up, here or in the passes after the stencil,
+        // is started over from rest rather than spreading to its neighbors
+        // next step.
+        self.sanitized = self
+            .pressures
+            .par_iter_mut()
+            .zip(self.velocities.par_iter_mut())
+            .map(|(front, front_v)| {
+                if front.is_finite() && front_v.is_finite() {
+                    0
+                } else {
+                    *front = 0.0;
+                    *front_v = Vec2::ZERO;
+                    1
+                }
+            })
+            .sum();
+        self.diverged |= self.sanitized > 0;
+    }
+}
+
+/// One pass of the stencil over every cell as if it were fluid, from `back`
+/// into `front`. `pressure_at` and `velocity_at` read `back`, including just
+/// past its edges.
+fn stencil(
+    (pressures, velocities): (&mut Array2D<f32>, &mut Array2D<Vec2>),
+    (pressures_back, velocities_back): (&Array2D<f32>, &Array2D<Vec2>),
+    (local_params, damping): (&Array2D<LocalParams>, &Array2D<f32>),
+    params: &WaveParams,
+    pressure_at: impl Fn(isize, isize) -> f32 + Sync,
+    velocity_at: impl Fn(isize, isize) -> Vec2 + Sync,
+) {
+    pressures
+        .par_iter_mut()
+        .zip(pressures_back.par_iter_coords())
+        .zip(
+            velocities
+                .par_iter_mut()
+                .zip(velocities_back.par_iter().cloned()),
+        )
+        .for_each(|((front, (x, y, _)), (front_v, back_v))| {
+            let (x, y) = (x as isize, y as isize);
+
+            let left = pressure_at(x - 1, y);
+            let right = pressure_at(x + 1, y);
+            let up = pressure_at(x, y - 1);
+            let down = pressure_at(x, y + 1);
+
+            let hgrad = right - left;
+            let vgrad = down - up;
+
+            let (grad_alpha, grad_damping) = local_params[(x, y)].resolve(params);
+
+            let grad = Vec2::new(hgrad, vgrad);
+            *front_v += grad * grad_alpha;
+            if params.advection {
+                // Semi-Lagrangian: the velocity arriving here is whatever
+                // was one step upstream, so add the difference.
+                let upstream =
+                    velocities_back.get_sampled(x as f32 - back_v.x, y as f32 - back_v.y);
+                *front_v += upstream - back_v;
+            }
+            *front_v *= grad_damping;
+
+            *front -= net_inflow(&velocity_at, x, y);
+
+            let damping = damping[(x, y)];
+            *front *= damping;
+            *front_v *= damping;
+        });
+}
+
+/// The velocities around `(x, y)` summed the way its pressure takes them
+/// away: from the left and above, less to the right and below.
+fn net_inflow(velocity_at: impl Fn(isize, isize) -> Vec2, x: isize, y: isize) -> f32 {
+    velocity_at(x - 1, y).x - velocity_at(x + 1, y).x + velocity_at(x, y - 1).y
+        - velocity_at(x, y + 1).y
+}
+
+/// Something that drives a field from outside, given the tick number.
+pub type Injector<'a> = &'a mut dyn FnMut(u32, &mut WaveField);
+
+/// Where a headless run ended up.
+pub struct FinalState {
+    pub field: WaveField,
+    /// Steps taken.
+    pub ticks: u32,
+}
+impl FinalState {
+    /// The largest absolute pressure anywhere on the grid.
+    pub fn peak_pressure(&self) -> f32 {
+        self.field
+            .pressures
+            .iter()
+            .fold(0.0, |peak, p| peak.max(p.abs()))
+    }
+}
+
+/// Step `field` `ticks` times with nothing else going on: no audio, no
+/// window. Before each step `inject`, if given, gets the tick number and the
+/// field to drive however it likes, so the same arguments always give the
+/// same result.
+pub fn run_headless(mut field: WaveField, ticks: u32, mut inject: Option<Injector>) -> FinalState {
+    for tick in 0..ticks {
+        if let Some(inject) = &mut inject {
+            inject(tick, &mut field);
+        }
+        field.step();
+    }
+    FinalState { field, ticks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn par_for_each_mut_coords_visits_each_cell_once() {
+        let mut grid = Array2D::new(7, 3, (0, 0, 0));
+        grid.par_for_each_mut_coords(|x, y, cell| *cell = (cell.0 + 1, x, y));
+        for (x, y, &(visits, seen_x, seen_y)) in grid.iter_coords() {
+            assert_eq!(visits, 1);
+            assert_eq!((seen_x, seen_y), (x, y));
+        }
+    }
+
+    /// Energy in rows `rows`, counted the way [`WaveField::total_energy`]
+    /// counts it.
+    fn energy_in_rows(field: &WaveField, rows: std::ops::Range<usize>) -> f64 {
+        let alpha = field.params.grad_alpha as f64;
+        field
+            .pressures
+            .iter_coords()
+            .zip(field.velocities.iter())
+            .filter(|((_, y, _), _)| rows.contains(y))
+            .map(|((_, _, &p), v)| 0.5 * ((p * p) as f64 + v.length_squared() as f64 / alpha))
+            .sum()
+    }
+
+    /// A pulse sent down a narrow periodic strip at a membrane across it:
+    /// the energy past the membrane, and the energy above it beyond what the
+    /// pulse's upward half carries, once the pulse has had time to cross.
+    fn membrane_split(tension: Option<f32>) -> (f64, f64) {
+        const HEIGHT: usize = 400;
+        const MEMBRANE: usize = 200;
+        let params = WaveParams {
+            edge_mode: EdgeMode::Periodic,
+            grad_damping: 1.0,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(4, HEIGHT, params);
+        field.set_pressures(Array2D::from_fn(4, HEIGHT, |_, y| {
+            (-((y as f32 - 150.0) / 4.0).powi(2)).exp()
+        }));
+        if let Some(tension) = tension {
+            let membrane = Material::Membrane(tension);
+            field
+                .materials_mut()
+                .fill_rect(0, MEMBRANE as isize, 4, 1, membrane);
+        }
+        for _ in 0..300 {
+            field.step();
+        }
+        (
+            energy_in_rows(&field, MEMBRANE + 1..HEIGHT),
+            energy_in_rows(&field, 0..MEMBRANE),
+        )
+    }
+
+    #[test]
+    fn membrane_splits_a_wave_by_tension() {
+        let (open, above_open) = membrane_split(None);
+        let (mut last_through, mut last_back) = (open, 0.0);
+        for tension in [0.5, 2.0, 8.0] {
+            let (through, above) = membrane_split(Some(tension));
+            let back = above - above_open;
+            assert!(through > 0.0 && through < last_through, "tension {tension}");
+            assert!(back > last_back, "tension {tension}");
+            assert!(
+                through + back < open * 1.01,
+                "tension {tension} made energy"
+            );
+            (last_through, last_back) = (through, back);
+        }
+    }
+
+    #[test]
+    fn region_average_of_uniform_and_gradient_fields() {
+        let rect = CellRect {
+            x: 2,
+            y: 3,
+            width: 4,
+            height: 5,
+        };
+        let uniform = Array2D::new(10, 10, 0.25);
+        assert_eq!(uniform.mean_over(rect), 0.25);
+
+        // Rises by one per column, so the mean is the middle column's value.
+        let gradient = Array2D::from_fn(10, 10, |x, _| x as f32);
+        assert_eq!(gradient.mean_over(rect), 3.5);
+        // Only the part on the grid counts.
+        let overhanging = CellRect {
+            x: 8,
+            y: 0,
+            width: 5,
+            height: 2,
+        };
+        assert_eq!(gradient.mean_over(overhanging), 8.5);
+        let off_grid = CellRect {
+            x: 20,
+            y: 0,
+            width: 5,
+            height: 2,
+        };
+        assert_eq!(gradient.mean_over(off_grid), 0.0);
+    }
+
+    /// Every non-fluid cell, by scanning the whole grid.
+    fn scan_special_cells(field: &WaveField) -> Vec<(usize, usize)> {
+        let materials = field.materials();
+        let mut cells = Vec::new();
+        for y in 0..materials.height() {
+            for x in 0..materials.width() {
+                if materials[(x as isize, y as isize)] != Material::Fluid {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn special_cells_follow_material_edits() {
+        let mut field = WaveField::new(12, 9, WaveParams::default());
+        field.materials_mut().fill_rect(2, 3, 3, 2, Material::Solid);
+        field.materials_mut()[(7, 1)] = Material::Emitter;
+        field.materials_mut()[(11, 8)] = Material::Membrane(1.0);
+        field.step();
+        assert_eq!(field.special_cells, scan_special_cells(&field));
+        assert_eq!(field.special_cells.len(), 8);
+
+        field.materials_mut().fill_rect(3, 3, 1, 2, Material::Fluid);
+        field.materials_mut()[(0, 0)] = Material::Solid;
+        field.step();
+        assert_eq!(field.special_cells, scan_special_cells(&field));
+        assert_eq!(field.special_cells.len(), 7);
+    }
+
+    /// Where a blob of rightward velocity centered on x = 20 has got to
+    /// after 20 steps, by its speed-weighted mean x.
+    fn blob_position(advection: bool) -> f32 {
+        let params = WaveParams {
+            grad_alpha: 0.0,
+            grad_damping: 1.0,
+            advection,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(64, 40, params);
+        let blob = Array2D::from_fn(64, 40, |x, y| {
+            let d2 = (x as f32 - 20.0).powi(2) + (y as f32 - 20.0).powi(2);
+            Vec2::new(0.5 * (-d2 / 8.0).exp(), 0.0)
+        });
+        let mut state = field.state();
+        state.velocities = [blob.clone(), blob];
+        field.restore(state).unwrap();
+
+        for _ in 0..20 {
+            field.step();
+        }
+        let (weighted, total) = field
+            .velocities
+            .iter_coords()
+            .fold((0.0, 0.0), |(weighted, total), (x, _, v)| {
+                (weighted + x as f32 * v.length(), total + v.length())
+            });
+        weighted / total
+    }
+
+    #[test]
+    fn advection_carries_velocity_along_itself() {
+        assert!((blob_position(false) - 20.0).abs() < 0.1);
+        assert!(blob_position(true) > 20.5);
+    }
+
+    /// The energy still in the middle of the grid once a pulse sent up and
+    /// down from its center would have hit the top and bottom edges head on
+    /// and come some way back.
+    fn reflected_energy(edge_mode: EdgeMode) -> f64 {
+        let params = WaveParams {
+            edge_mode,
+            grad_damping: 1.0,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(64, 160, params);
+        field.set_pressures(Array2D::from_fn(64, 160, |x, y| {
+            let across = (x as f32 - 32.0) / 12.0;
+            let along = (y as f32 - 80.0) / 4.0;
+            (-across * across - along * along).exp()
+        }));
+        for _ in 0..450 {
+            field.step();
+        }
+        energy_in_rows(&field, 20..140)
+    }
+
+    #[test]
+    fn radiating_edges_reflect_less_than_walls() {
+        let radiating = reflected_energy(EdgeMode::Radiating);
+        let reflect = reflected_energy(EdgeMode::Reflect);
+        assert!(radiating < reflect * 0.01, "{radiating} vs {reflect}");
+    }
+
+    #[test]
+    fn get_is_some_exactly_in_bounds_and_at_the_right_cell() {
+        let (width, height) = (7, 5);
+        let mut grid = Array2D::from_fn(width, height, |x, y| (x, y));
+        let extremes = [
+            isize::MIN,
+            isize::MIN + 1,
+            -(width as isize) - 1,
+            -1,
+            0,
+            1,
+            height as isize - 1,
+            height as isize,
+            width as isize - 1,
+            width as isize,
+            isize::MAX - 1,
+            isize::MAX,
+        ];
+        let mut coords: Vec<(isize, isize)> = extremes
+            .iter()
+            .flat_map(|&x| extremes.iter().map(move |&y| (x, y)))
+            .collect();
+        // Plus a spread of arbitrary ones, small and huge, from an xorshift.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..2000 {
+            let mut next = || {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as isize
+            };
+            let (x, y) = (next(), next());
+            coords.push((x, y));
+            coords.push((x % 10, y % 10));
+        }
+
+        for (x, y) in coords {
+            let in_bounds = (0..width as isize).contains(&x) && (0..height as isize).contains(&y);
+            let cell = grid.get(x, y);
+            assert_eq!(cell.is_some(), in_bounds, "({x}, {y})");
+            if let Some(cell) = cell {
+                let flat = y as usize * width + x as usize;
+                assert_eq!(*cell, (x as usize, y as usize));
+                assert!(std::ptr::eq(cell, &grid.as_ref()[flat]));
+            }
+            assert_eq!(grid.get_mut(x, y).is_some(), in_bounds, "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn indexing_reaches_every_corner() {
+        let mut grid = Array2D::from_fn(4, 3, |x, y| 10 * y + x);
+        assert_eq!(grid[(0, 0)], 0);
+        assert_eq!(grid[(3, 0)], 3);
+        assert_eq!(grid[(0, 2)], 20);
+        assert_eq!(grid[(3, 2)], 23);
+        grid[(3, 2)] = 99;
+        assert_eq!(grid.get(3, 2), Some(&99));
+        assert_eq!(grid.last(), Some(&99));
+    }
+
+    #[test]
+    #[should_panic(expected = "coordinate (-1, 0) out of bounds for 4x3 grid")]
+    fn indexing_a_negative_coordinate_panics() {
+        let grid = Array2D::new(4, 3, 0.0);
+        let _ = grid[(-1, 0)];
+    }
+
+    #[test]
+    #[should_panic(expected = "coordinate (2, 3) out of bounds for 4x3 grid")]
+    fn indexing_past_the_bottom_panics() {
+        let mut grid = Array2D::new(4, 3, 0.0);
+        grid[(2, 3)] = 1.0;
+    }
+
+    /// The energy left 40 steps after a pulse at `(x, 16)`, on a grid whose
+    /// left half is painted with heavy damping.
+    fn energy_after_pulse_at(x: usize) -> f64 {
+        let params = WaveParams {
+            edge_mode: EdgeMode::Periodic,
+            grad_damping: 1.0,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(64, 32, params);
+        let foam = LocalParams {
+            grad_damping: Some(0.9),
+            ..LocalParams::default()
+        };
+        field.local_params.fill_rect(0, 0, 32, 32, foam);
+        field.set_pressures(Array2D::from_fn(64, 32, |px, py| {
+            let d2 = (px as f32 - x as f32).powi(2) + (py as f32 - 16.0).powi(2);
+            (-d2 / 8.0).exp()
+        }));
+        for _ in 0..40 {
+            field.step();
+        }
+        field.total_energy()
+    }
+
+    #[test]
+    fn painted_damping_decays_faster_than_the_rest() {
+        let foam = energy_after_pulse_at(16);
+        let open = energy_after_pulse_at(48);
+        assert!(foam < open * 0.5, "{foam} vs {open}");
+    }
+
+    #[test]
+    fn wrapping_reads_come_round_the_other_side() {
+        let mut grid = Array2D::from_fn(5, 3, |x, y| 10 * y + x);
+        assert_eq!(grid.get_wrapping(-1, -1), grid.get(4, 2).unwrap());
+        assert_eq!(*grid.get_wrapping(5, 3), 0);
+        assert_eq!(*grid.get_wrapping(-5 * 1000 - 2, 3 * 1000 + 1), 13);
+        assert_eq!(*grid.get_wrapping(isize::MIN, isize::MAX), grid[(2, 1)]);
+        *grid.get_wrapping_mut(-6, 4) = 99;
+        assert_eq!(grid[(4, 1)], 99);
+    }
+
+    #[test]
+    fn from_fn_fills_in_row_major_order() {
+        let mut calls = Vec::new();
+        let grid = Array2D::from_fn(3, 2, |x, y| {
+            calls.push((x, y));
+            x * 100 + y
+        });
+        assert_eq!(calls, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        for (x, y, &cell) in grid.iter_coords() {
+            assert_eq!(cell, x * 100 + y);
+        }
+    }
+
+    #[test]
+    fn map_is_elementwise_and_keeps_the_shape() {
+        let grid = Array2D::from_fn(4, 3, |x, y| Vec2::new(x as f32, y as f32));
+        let lengths = grid.map(|v| v.length_squared());
+        let par_lengths = grid.par_map(|v| v.length_squared());
+        for mapped in [&lengths, &par_lengths] {
+            assert_eq!((mapped.width(), mapped.height()), (4, 3));
+            let expected: Vec<f32> = grid.iter().map(|v| v.length_squared()).collect();
+            assert_eq!(**mapped, *expected);
+        }
+        assert_eq!(lengths[(3, 2)], 13.0);
+    }
+
+    #[test]
+    fn coordinates_match_each_cells_position() {
+        let mut grid = Array2D::from_fn(5, 4, |x, y| (x, y));
+        assert_eq!(grid.iter_coords().count(), 20);
+        assert!(grid.iter_coords().all(|(x, y, &cell)| cell == (x, y)));
+        let par: Vec<_> = grid
+            .par_iter_coords()
+            .map(|(x, y, &cell)| (x, y, cell))
+            .collect();
+        let serial: Vec<_> = grid
+            .iter_coords()
+            .map(|(x, y, &cell)| (x, y, cell))
+            .collect();
+        assert_eq!(par, serial);
+        for (x, y, cell) in grid.iter_coords_mut() {
+            *cell = (y, x);
+        }
+        assert_eq!(grid[(4, 1)], (1, 4));
+    }
+
+    #[test]
+    fn sampling_interpolates_and_clamps() {
+        let grid = Array2D::from_fn(3, 3, |x, y| (x + 10 * y) as f32);
+        assert_eq!(grid.get_sampled(1.0, 2.0), 21.0);
+        assert_eq!(grid.get_sampled(0.5, 0.0), 0.5);
+        assert_eq!(grid.get_sampled(1.0, 1.5), 16.0);
+        assert_eq!(grid.get_sampled(0.5, 0.5), 5.5);
+        assert_eq!(grid.get_sampled(-4.0, 1.0), 10.0);
+        assert_eq!(grid.get_sampled(9.0, 9.0), 22.0);
+        assert_eq!(grid.get_sampled(2.0, -0.5), 2.0);
+    }
+
+    #[test]
+    fn rows_are_width_long_and_cover_the_storage() {
+        let mut grid = Array2D::from_fn(4, 3, |x, y| 4 * y + x);
+        assert_eq!(grid.rows().count(), 3);
+        assert!(grid.rows().all(|row| row.len() == 4));
+        let flattened: Vec<usize> = grid.rows().flatten().copied().collect();
+        assert_eq!(flattened, *grid);
+        for (y, row) in grid.rows_mut().enumerate() {
+            row.fill(y);
+        }
+        assert_eq!(grid[(3, 2)], 2);
+        assert_eq!(grid.rows_mut().count(), 3);
+    }
+
+    #[test]
+    fn absorbing_border_leaves_less_than_walls() {
+        let absorb = reflected_energy(EdgeMode::Absorb { thickness: 16 });
+        let reflect = reflected_energy(EdgeMode::Reflect);
+        assert!(absorb < reflect * 0.01, "{absorb} vs {reflect}");
+    }
+
+    /// An 8x200 strip after `steps` steps from a pulse across row `pulse_y`.
+    fn strip_after(edge_mode: EdgeMode, pulse_y: f32, steps: usize) -> WaveField {
+        let params = WaveParams {
+            edge_mode,
+            grad_damping: 1.0,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(8, 200, params);
+        field.set_pressures(Array2D::from_fn(8, 200, |_, y| {
+            (-((y as f32 - pulse_y) / 4.0).powi(2)).exp()
+        }));
+        for _ in 0..steps {
+            field.step();
+        }
+        field
+    }
+
+    fn row_extremes(field: &WaveField, rows: std::ops::Range<usize>) -> (f32, f32) {
+        field
+            .pressures
+            .iter_coords()
+            .filter(|(_, y, _)| rows.contains(y))
+            .fold((0.0, 0.0), |(lo, hi), (_, _, &p)| (p.min(lo), p.max(hi)))
+    }
+
+    #[test]
+    fn periodic_waves_come_back_in_the_far_side() {
+        // The upward half of the pulse runs off the top at about row -43.
+        let periodic = strip_after(EdgeMode::Periodic, 20.0, 200);
+        let (_, peak) = row_extremes(&periodic, 140..175);
+        assert!(peak > 0.2, "{peak}");
+        assert!(energy_in_rows(&periodic, 140..175) > 0.3 * energy_in_rows(&periodic, 0..200));
+
+        let zero = strip_after(EdgeMode::Zero, 20.0, 200);
+        assert!(energy_in_rows(&zero, 140..175) < 1e-3);
+    }
+
+    #[test]
+    fn walls_send_pulses_back_the_same_sign() {
+        // The upward half has bounced off the top and come back to about
+        // row 49; the downward half is still on its way to the bottom.
+        let (_, peak) = row_extremes(&strip_after(EdgeMode::Reflect, 30.0, 250), 35..65);
+        assert!(peak > 0.2, "{peak}");
+        // An open edge turns it over instead.
+        let (trough, _) = row_extremes(&strip_after(EdgeMode::Zero, 30.0, 250), 35..65);
+        assert!(trough < -0.2, "{trough}");
+    }
+
+    #[test]
+    fn emitter_spreads_outward_evenly() {
+        let mut field = WaveField::new(41, 41, WaveParams::default());
+        field.materials_mut()[(20, 20)] = Material::Emitter;
+        field.emitter_pressure = 1.0;
+
+        // How far out the disturbance has got along each axis. (Only every
+        // other cell along an axis moves: the stencil reads two apart.)
+        let reach = |field: &WaveField| {
+            let disturbed = |x: isize, y: isize| field.pressures[(x, y)].abs() > 0.02;
+            let along = |dx: isize, dy: isize| {
+                (1..=20)
+                    .filter(|&r| disturbed(20 + dx * r, 20 + dy * r))
+                    .max()
+                    .unwrap_or(0)
+            };
+            [along(1, 0), along(-1, 0), along(0, 1), along(0, -1)]
+        };
+        let mut last = 0;
+        for _ in 0..4 {
+            for _ in 0..10 {
+                field.step();
+            }
+            let [right, left, down, up] = reach(&field);
+            assert!(
+                right == left && left == down && down == up,
+                "{:?}",
+                reach(&field)
+            );
+            assert!(right > last, "stopped spreading at {right}");
+            last = right;
+        }
+        assert!(last < 20);
+    }
+
+    /// Knocks the field at two ticks, the same way every time.
+    fn knock(tick: u32, field: &mut WaveField) {
+        if tick.is_multiple_of(30) {
+            field
+                .pressures
+                .stamp_gaussian(20.0 + tick as f32 / 10.0, 16.0, 2.0, 1.0);
+        }
+    }
+
+    fn pressure_bits(field: &WaveField) -> Vec<u32> {
+        field.pressures.iter().map(|p| p.to_bits()).collect()
+    }
+
+    #[test]
+    fn headless_runs_match_each_other_and_stepping_by_hand() {
+        let fresh = || {
+            let mut field = WaveField::new(48, 32, WaveParams::default());
+            field
+                .materials_mut()
+                .fill_rect(30, 4, 2, 10, Material::Solid);
+            field
+        };
+        let first = run_headless(fresh(), 60, Some(&mut knock));
+        let second = run_headless(fresh(), 60, Some(&mut knock));
+        assert_eq!(first.ticks, 60);
+        assert!(first.peak_pressure() > 0.0);
+        assert_eq!(pressure_bits(&first.field), pressure_bits(&second.field));
+
+        let mut by_hand = fresh();
+        for tick in 0..60 {
+            knock(tick, &mut by_hand);
+            by_hand.step();
+        }
+        assert_eq!(pressure_bits(&first.field), pressure_bits(&by_hand));
+        let velocity_bits = |field: &WaveField| -> Vec<[u32; 2]> {
+            field
+                .velocities
+                .iter()
+                .map(|v| v.to_array().map(f32::to_bits))
+                .collect()
+        };
+        assert_eq!(velocity_bits(&first.field), velocity_bits(&by_hand));
+    }
+
+    #[test]
+    fn grad_damping_is_the_fraction_of_velocity_kept() {
+        let flow = Vec2::new(0.5, -0.25);
+        let speed_after_a_step = |grad_damping| {
+            let params = WaveParams {
+                grad_damping,
+                edge_mode: EdgeMode::Periodic,
+                ..WaveParams::default()
+            };
+            let mut field = WaveField::new(16, 16, params);
+            // A uniform flow on a periodic grid has no gradient and no net
+            // inflow anywhere, so only the damping touches it.
+            field.velocities = Array2D::new(16, 16, flow);
+            field.velocities_back = Array2D::new(16, 16, flow);
+            field.step();
+            assert!(field
+                .velocities
+                .iter()
+                .all(|&v| v == field.velocities[(3, 7)]));
+            field.velocities[(3, 7)].length() / flow.length()
+        };
+        assert_eq!(speed_after_a_step(1.0), 1.0);
+        assert!((speed_after_a_step(0.9) - 0.9).abs() < 1e-6);
+        assert!(speed_after_a_step(0.5) < speed_after_a_step(0.9));
+    }
+
+    #[test]
+    fn unstable_alpha_is_capped_rather_than_blowing_up() {
+        let run = |grad_alpha| {
+            let params = WaveParams {
+                grad_alpha,
+                ..WaveParams::default()
+            };
+            let mut field = WaveField::new(48, 32, params);
+            field.pressures.stamp_gaussian(24.0, 16.0, 2.0, 1.0);
+            run_headless(field, 500, None)
+        };
+        let wild = WaveParams {
+            grad_alpha: 50.0,
+            ..WaveParams::default()
+        };
+        assert!(!wild.is_stable());
+
+        let capped = run(wild.grad_alpha);
+        assert!(capped.field.pressures.iter().all(|p| p.is_finite()));
+        assert!(!capped.field.diverged());
+        assert_eq!(
+            pressure_bits(&capped.field),
+            pressure_bits(&run(MAX_STABLE_ALPHA).field)
+        );
+    }
+
+    #[test]
+    fn infinite_cells_are_zeroed_and_the_field_recovers() {
+        let params = WaveParams {
+            edge_mode: EdgeMode::Radiating,
+            ..WaveParams::default()
+        };
+        let mut field = WaveField::new(24, 16, params);
+        field.materials_mut()[(12, 3)] = Material::Membrane(2.0);
+        // One in the open, one on a membrane and one on the edge, in both
+        // buffers, so the stencil, the patch-up and the edge pass all see it.
+        for cell in [(6, 8), (12, 3), (0, 5)] {
+            field.pressures[cell] = f32::INFINITY;
+            field.pressures_back[cell] = f32::INFINITY;
+            field.velocities[cell] = Vec2::new(f32::INFINITY, 0.0);
+        }
+        let all_finite = |field: &WaveField| {
+            field.pressures.iter().all(|p| p.is_finite())
+                && field.velocities.iter().all(|v| v.is_finite())
+        };
+
+        field.step();
+        assert!(all_finite(&field));
+        assert!(field.sanitized() > 0);
+        assert!(field.diverged());
+        for _ in 0..4 {
+            field.step();
+            assert!(all_finite(&field));
+        }
+        assert_eq!(field.sanitized(), 0);
+        assert!(field.diverged());
+    }
+
+    #[test]
+    fn damping_ramps_soak_up_a_passing_pulse() {
+        let energy_after = |ramp: Option<DampingRamp>| {
+            let params = WaveParams {
+                edge_mode: EdgeMode::Periodic,
+                grad_damping: 1.0,
+                ..WaveParams::default()
+            };
+            let mut field = WaveField::new(64, 32, params);
+            if let Some(ramp) = ramp {
+                field.damp(ramp);
+            }
+            field.pressures.stamp_gaussian(16.0, 16.0, 2.0, 1.0);
+            for _ in 0..80 {
+                field.step();
+            }
+            field.total_energy()
+        };
+        let open = energy_after(None);
+        // A ramp past where the pulse starts, getting stronger away from it.
+        let rect = CellRect {
+            x: 24,
+            y: 0,
+            width: 16,
+            height: 32,
+        };
+        let soaked = energy_after(Some(DampingRamp::Rect {
+            rect,
+            horizontal: true,
+            start: 1.0,
+            end: 0.9,
+        }));
+        let spot = energy_after(Some(DampingRamp::Radial {
+            center: (16.0, 16.0),
+            radius: 6.0,
+            center_value: 0.8,
+        }));
+        assert!(soaked < open * 0.8, "{soaked} vs {open}");
+        assert!(spot < open * 0.8, "{spot} vs {open}");
+
+        // Overlapping ramps keep the stronger damping in each cell.
+        let mut field = WaveField::new(8, 1, WaveParams::default());
+        field.damp_rect(
+            CellRect {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 1,
+            },
+            true,
+            1.0,
+            0.3,
+        );
+        field.damp_rect(
+            CellRect {
+                x: 0,
+                y: 0,
+                width: 8,
+                height: 1,
+            },
+            true,
+            0.3,
+            1.0,
+        );
+        assert_eq!(field.damping[(0, 0)], 0.3);
+        assert_eq!(field.damping[(7, 0)], 0.3);
+        assert!((field.damping[(3, 0)] - 0.6).abs() < 1e-6);
+    }
+    #[test]
+    fn undamped_energy_holds_and_damped_energy_only_falls() {
+        // Each step writes over the buffer from two steps back, so the energy
+        // that holds still is the sum over both buffers: this step's and the
+        // last one's.
+        let run = |grad_damping: f32| {
+            let params = WaveParams {
+                edge_mode: EdgeMode::Zero,
+                grad_damping,
+                ..WaveParams::default()
+            };
+            let mut field = WaveField::new(48, 48, params);
+            field.pressures.stamp_gaussian(24.0, 24.0, 3.0, 1.0);
+            let mut last = field.total_energy();
+            (0..600)
+                .map(|_| {
+                    field.step();
+                    let energy = field.total_energy();
+                    std::mem::replace(&mut last, energy) + energy
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let undamped = run(1.0);
+        let mean = undamped.iter().sum::<f64>() / undamped.len() as f64;
+        for (tick, &energy) in undamped.iter().enumerate() {
+            assert!(
+                (energy - mean).abs() < mean * 0.1,
+                "tick {tick}: {energy} vs {mean}"
+            );
+        }
+        let (early, late) = undamped.split_at(300);
+        let late_mean = late.iter().sum::<f64>() / 300.0;
+        let early_mean = early.iter().sum::<f64>() / 300.0;
+        assert!((late_mean - early_mean).abs() < early_mean * 0.02);
+
+        // Tick to tick it still wobbles a little as energy moves between
+        // pressure and velocity, so compare stretches of ticks.
+        let damped = run(0.99);
+        let stretches = damped
+            .chunks(20)
+            .map(|c| c.iter().sum::<f64>())
+            .collect::<Vec<_>>();
+        for (i, pair) in stretches.windows(2).enumerate() {
+            assert!(
+                pair[1] < pair[0],
+                "rose after stretch {i}: {} -> {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        assert!(damped[damped.len() - 1] < damped[0] * 0.1);
+    }
+    #[test]
+    fn halo_and_edge_mode_reads_step_to_the_same_field() {
+        let edges = [
+            EdgeMode::Zero,
+            EdgeMode::Radiating,
+            EdgeMode::Absorb { thickness: 4 },
+            EdgeMode::Reflect,
+            EdgeMode::Periodic,
+        ];
+        for edge_mode in edges {
+            let run = |halo: bool| {
+                let params = WaveParams {
+                    edge_mode,
+                    advection: true,
+                    ..WaveParams::default()
+                };
+                let mut field = WaveField::new(40, 24, params);
+                field.halo = halo;
+                field
+                    .materials_mut()
+                    .fill_rect(20, 2, 2, 8, Material::Solid);
+                field
+                    .materials_mut()
+                    .fill_rect(10, 14, 6, 1, Material::Membrane(2.0));
+                field.materials_mut()[(30, 18)] = Material::Emitter;
+                let slow = LocalParams {
+                    grad_alpha: Some(0.05),
+                    ..LocalParams::default()
+                };
+                field.local_params.fill_rect(0, 0, 8, 24, slow);
+                // Start right up against the corner so every edge gets hit.
+                field.pressures.stamp_gaussian(2.0, 3.0, 2.0, 1.0);
+                for tick in 0..120 {
+                    field.emitter_pressure = (tick as f32 * 0.3).sin();
+                    field.step();
+                }
+                let velocities: Vec<_> = field.velocities.iter().map(|v| v.to_array()).collect();
+                (pressure_bits(&field), velocities)
+            };
+            assert!(run(true) == run(false), "{edge_mode:?}");
+        }
+    }
+}
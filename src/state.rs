@@ -0,0 +1,584 @@
+//! Saving the whole simulation to a file and picking it back up later.
+//!
+//! The format is little-endian binary: a magic number and version, the grid
+//! size, the world's clocks, the parameters as config text, every grid cell
+//! by cell, then the parameters the config doesn't cover: the injection
+//! path, the regions and the emitters.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use glam::Vec2;
+use kontawa::simulation::{Array2D, CellRect, FieldState, LocalParams, Material};
+
+use crate::config::Config;
+use crate::emitter::{Emitter, Modulation, Waveform};
+use crate::World;
+
+const MAGIC: &[u8; 4] = b"KTWS";
+/// Version 2 added the damping grid, and version 3 what the config doesn't
+/// cover. Older files still load, with no damping and the rest left as it
+/// was.
+const VERSION: u32 = 3;
+
+/// Why a save or load didn't work.
+#[derive(Debug)]
+pub enum StateError {
+    /// Reading, writing or making a directory for `path`.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file isn't a saved simulation at all.
+    NotASave,
+    UnsupportedVersion(u32),
+    /// The file is for a grid of a different size than this one.
+    SizeMismatch {
+        saved: (usize, usize),
+        grid: (usize, usize),
+    },
+    /// The file ends before everything's been read.
+    Truncated,
+    /// There's more in the file after everything's been read.
+    TrailingData,
+    UnknownMaterial(u8),
+    UnknownWaveform(u8),
+    UnknownModulation(u8),
+    /// The saved parameters don't parse.
+    Params(String),
+}
+impl StateError {
+    fn io(path: &Path) -> impl FnOnce(std::io::Error) -> StateError + '_ {
+        move |source| StateError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            StateError::NotASave => write!(f, "not a saved simulation"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {version}")
+            }
+            StateError::SizeMismatch {
+                saved: (width, height),
+                grid: (w, h),
+            } => write!(
+                f,
+                "saved for a {width}x{height} grid, but this one is {w}x{h}"
+            ),
+            StateError::Truncated => write!(f, "file ends early"),
+            StateError::TrailingData => write!(f, "trailing data"),
+            StateError::UnknownMaterial(tag) => write!(f, "unknown material {tag}"),
+            StateError::UnknownWaveform(tag) => write!(f, "unknown waveform {tag}"),
+            StateError::UnknownModulation(tag) => write!(f, "unknown modulation {tag}"),
+            StateError::Params(e) => write!(f, "saved parameters: {e}"),
+        }
+    }
+}
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl World {
+    /// Write everything needed to carry on from this tick to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        let (width, height) = self.dimensions();
+        let params = self.params.lock().unwrap().clone();
+        let config = Config {
+            params: params.clone(),
+            ..Config::default()
+        }
+        .to_config_string();
+
+        let mut out = Writer::default();
+        out.bytes(MAGIC);
+        out.u32(VERSION);
+        out.u32(width as u32);
+        out.u32(height as u32);
+        out.u32(self.ticks);
+        out.f32(self.time);
+        out.bool(self.bar_drawn);
+        out.option(self.membrane, Writer::f32);
+        out.option(self.dense, |out, (rect, stiffness)| {
+            out.rect(rect);
+            out.f32(stiffness);
+        });
+        out.u32(config.len() as u32);
+        out.bytes(config.as_bytes());
+
+        let state = self.field.state();
+        for grid in &state.pressures {
+            grid.rows().flatten().for_each(|&p| out.f32(p));
+        }
+        for grid in &state.velocities {
+            grid.rows().flatten().for_each(|&v| out.vec2(v));
+        }
+        for &material in state.materials.rows().flatten() {
+            let (tag, tension) = match material {
+                Material::Fluid => (0, 0.0),
+                Material::Solid => (1, 0.0),
+                Material::Emitter => (2, 0.0),
+                Material::Membrane(tension) => (3, tension),
+            };
+            out.u8(tag);
+            out.f32(tension);
+        }
+        for local in state.local_params.rows().flatten() {
+            for value in [local.grad_alpha, local.grad_damping, local.stiffness] {
+                out.option(value, Writer::f32);
+            }
+        }
+        state.damping.rows().flatten().for_each(|&d| out.f32(d));
+
+        out.u32(params.injection_path.len() as u32);
+        for &(x, y) in &params.injection_path {
+            out.i32(x as i32);
+            out.i32(y as i32);
+        }
+        for region in [
+            params.injection_region,
+            params.listening_region,
+            params.charge_region,
+            params.dense_region,
+        ] {
+            out.option(region, Writer::rect);
+        }
+        out.u32(params.emitters.len() as u32);
+        for emitter in &params.emitters {
+            let tone = emitter.tone;
+            out.rect(emitter.region);
+            out.f32(tone.freq);
+            out.f32(tone.amp);
+            out.u8(tone.waveform as u8);
+            out.u8(tone.modulation as u8);
+            out.f32(tone.mod_freq);
+            out.f32(tone.mod_depth);
+            out.f32(emitter.phase);
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(StateError::io(dir))?;
+        }
+        std::fs::write(path, out.0).map_err(StateError::io(path))
+    }
+
+    /// Replace the simulation with one saved by [`World::save`]. The file
+    /// has to be for a grid the same size as this one.
+    pub fn load(&mut self, path: &Path) -> Result<(), StateError> {
+        let bytes = std::fs::read(path).map_err(StateError::io(path))?;
+        self.load_bytes(&bytes)
+    }
+
+    fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut input = Reader(bytes);
+        if input.bytes(4)? != MAGIC {
+            return Err(StateError::NotASave);
+        }
+        let version = input.u32()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let (width, height) = (input.u32()? as usize, input.u32()? as usize);
+        if (width, height) != self.dimensions() {
+            return Err(StateError::SizeMismatch {
+                saved: (width, height),
+                grid: self.dimensions(),
+            });
+        }
+
+        let ticks = input.u32()?;
+        let time = input.f32()?;
+        let bar_drawn = input.bool()?;
+        let membrane = input.option(Reader::f32)?;
+        let dense = input.option(|input| Ok((input.rect()?, input.f32()?)))?;
+        let config_len = input.u32()? as usize;
+        let config_text = std::str::from_utf8(input.bytes(config_len)?)
+            .map_err(|_| StateError::Params("not text".to_string()))?;
+        let mut config = Config {
+            params: self.params.lock().unwrap().clone(),
+            ..Config::default()
+        };
+        config.apply_str(config_text).map_err(StateError::Params)?;
+
+        let mut pressures =
+            || -> Result<Array2D<f32>, StateError> { grid(width, height, || input.f32()) };
+        let pressures = [pressures()?, pressures()?];
+        let mut velocities =
+            || -> Result<Array2D<Vec2>, StateError> { grid(width, height, || input.vec2()) };
+        let velocities = [velocities()?, velocities()?];
+        let materials = grid(width, height, || {
+            let tag = input.u8()?;
+            let tension = input.f32()?;
+            match tag {
+                0 => Ok(Material::Fluid),
+                1 => Ok(Material::Solid),
+                2 => Ok(Material::Emitter),
+                3 => Ok(Material::Membrane(tension)),
+                _ => Err(StateError::UnknownMaterial(tag)),
+            }
+        })?;
+        let local_params = grid(width, height, || {
+            Ok(LocalParams {
+                grad_alpha: input.option(Reader::f32)?,
+                grad_damping: input.option(Reader::f32)?,
+                stiffness: input.option(Reader::f32)?,
+            })
+        })?;
+        let damping = match version {
+            1 => Array2D::new(width, height, 1.0),
+            _ => grid(width, height, || input.f32())?,
+        };
+        if version >= 3 {
+            let params = &mut config.params;
+            params.injection_path = (0..input.u32()?)
+                .map(|_| Ok((input.i32()? as isize, input.i32()? as isize)))
+                .collect::<Result<_, StateError>>()?;
+            for region in [
+                &mut params.injection_region,
+                &mut params.listening_region,
+                &mut params.charge_region,
+                &mut params.dense_region,
+            ] {
+                *region = input.option(Reader::rect)?;
+            }
+            params.emitters = (0..input.u32()?)
+                .map(|_| {
+                    let mut emitter = Emitter::new(input.rect()?, input.f32()?, input.f32()?);
+                    let tag = input.u8()?;
+                    emitter.tone.waveform = *Waveform::ALL
+                        .get(tag as usize)
+                        .ok_or(StateError::UnknownWaveform(tag))?;
+                    let tag = input.u8()?;
+                    emitter.tone.modulation = *Modulation::ALL
+                        .get(tag as usize)
+                        .ok_or(StateError::UnknownModulation(tag))?;
+                    emitter.tone.mod_freq = input.f32()?;
+                    emitter.tone.mod_depth = input.f32()?;
+                    emitter.phase = input.f32()?;
+                    Ok(emitter)
+                })
+                .collect::<Result<_, StateError>>()?;
+        }
+        if !input.0.is_empty() {
+            return Err(StateError::TrailingData);
+        }
+
+        self.field
+            .restore(FieldState {
+                pressures,
+                velocities,
+                materials,
+                local_params,
+                damping,
+            })
+            .expect("every grid was read at the size checked above");
+        *self.params.lock().unwrap() = config.params;
+        self.ticks = ticks;
+        self.time = time;
+        self.bar_drawn = bar_drawn;
+        self.membrane = membrane;
+        self.under_membrane.clear();
+        self.dense = dense;
+        self.clear_trail();
+        Ok(())
+    }
+}
+
+/// A grid filled from `cell`, called in row-major order, stopping at the
+/// first error.
+fn grid<T: Clone + Default>(
+    width: usize,
+    height: usize,
+    mut cell: impl FnMut() -> Result<T, StateError>,
+) -> Result<Array2D<T>, StateError> {
+    let mut error = None;
+    let grid = Array2D::from_fn(width, height, |_, _| {
+        if error.is_some() {
+            return T::default();
+        }
+        cell().unwrap_or_else(|e| {
+            error = Some(e);
+            T::default()
+        })
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(grid),
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+    fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn i32(&mut self, v: i32) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn f32(&mut self, v: f32) {
+        self.bytes(&v.to_le_bytes());
+    }
+    fn rect(&mut self, rect: CellRect) {
+        for v in [rect.x, rect.y, rect.width, rect.height] {
+            self.u32(v as u32);
+        }
+    }
+    fn vec2(&mut self, v: Vec2) {
+        self.f32(v.x);
+        self.f32(v.y);
+    }
+    /// A presence flag, then the value if there is one.
+    fn option<T>(&mut self, v: Option<T>, write: impl FnOnce(&mut Writer, T)) {
+        self.bool(v.is_some());
+        if let Some(v) = v {
+            write(self, v);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32, StateError> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn rect(&mut self) -> Result<CellRect, StateError> {
+        let mut rect = [0; 4];
+        for v in &mut rect {
+            *v = self.u32()? as usize;
+        }
+        let [x, y, width, height] = rect;
+        Ok(CellRect {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+    fn vec2(&mut self) -> Result<Vec2, StateError> {
+        Ok(Vec2::new(self.f32()?, self.f32()?))
+    }
+    fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Reader<'a>) -> Result<T, StateError>,
+    ) -> Result<Option<T>, StateError> {
+        if self.bool()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimParams;
+    use std::sync::{Arc, Mutex};
+
+    fn world(width: usize, height: usize) -> World {
+        World::new(Arc::new(Mutex::new(SimParams::default())), width, height)
+    }
+
+    fn float_bits(state: &FieldState) -> Vec<u32> {
+        let pressures = state.pressures.iter().flat_map(|grid| grid.iter().copied());
+        let velocities = state.velocities.iter().flat_map(|grid| grid.iter());
+        pressures
+            .chain(velocities.flat_map(|v| [v.x, v.y]))
+            .chain(state.damping.iter().copied())
+            .map(f32::to_bits)
+            .collect()
+    }
+
+    #[test]
+    fn saving_then_loading_reproduces_every_grid() {
+        let mut saved = world(24, 16);
+        saved.params.lock().unwrap().wave.grad_alpha = 0.2;
+        saved.seed_wave();
+        saved.field.materials_mut()[(3, 4)] = Material::Membrane(2.5);
+        saved.field.materials_mut()[(5, 5)] = Material::Emitter;
+        saved.set_material_rect(10, 2, 3, 4, Material::Solid);
+        saved.field.local_params[(7, 7)].grad_alpha = Some(0.3);
+        saved.field.local_params[(8, 7)].stiffness = Some(2.0);
+        saved.field.damping[(9, 9)] = 0.5;
+        for _ in 0..10 {
+            saved.update();
+        }
+        let path = std::env::temp_dir()
+            .join(format!("kontawa-state-test-{}", std::process::id()))
+            .join("round.state");
+        saved.save(&path).unwrap();
+
+        let mut loaded = world(24, 16);
+        loaded.load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let (before, after) = (saved.field.state(), loaded.field.state());
+        assert_eq!(float_bits(&before), float_bits(&after));
+        assert!(before.materials.iter().eq(after.materials.iter()));
+        assert!(before.local_params.iter().eq(after.local_params.iter()));
+        assert_eq!((loaded.ticks, loaded.time), (saved.ticks, saved.time));
+        assert_eq!(loaded.params.lock().unwrap().wave.grad_alpha, 0.2);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        assert!(matches!(
+            loaded.load_bytes(&out),
+            Err(StateError::Truncated)
+        ));
+        assert!(matches!(
+            loaded.load_bytes(b"PNG!...."),
+            Err(StateError::NotASave)
+        ));
+        let missing = Path::new("/nonexistent/kontawa.state");
+        assert!(matches!(loaded.load(missing), Err(StateError::Io { .. })));
+    }
+
+    #[test]
+    fn a_save_only_loads_into_a_grid_its_size() {
+        let path = std::env::temp_dir()
+            .join(format!("kontawa-state-size-test-{}", std::process::id()))
+            .join("small.state");
+        world(8, 6).save(&path).unwrap();
+        let loaded = world(8, 7).load(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        match loaded {
+            Err(StateError::SizeMismatch { saved, grid }) => {
+                assert_eq!((saved, grid), ((8, 6), (8, 7)));
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn version_one_files_load_with_no_extra_damping() {
+        let mut saved = world(12, 10);
+        saved.seed_wave();
+        saved.field.damping[(4, 4)] = 0.5;
+        for _ in 0..5 {
+            saved.update();
+        }
+        let path = std::env::temp_dir()
+            .join(format!("kontawa-state-v1-test-{}", std::process::id()))
+            .join("v2.state");
+        saved.save(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        // Version 1 was the same less the damping grid, and what comes after
+        // it: here an empty path, four absent regions and no emitters.
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        bytes.truncate(bytes.len() - 12 * 10 * 4 - (4 + 4 + 4));
+
+        let mut loaded = world(12, 10);
+        loaded.field.damping[(1, 1)] = 0.25;
+        loaded.load_bytes(&bytes).unwrap();
+        let (before, after) = (saved.field.state(), loaded.field.state());
+        assert!(after.damping.iter().all(|&d| d == 1.0));
+        assert!(before.pressures[0].iter().eq(after.pressures[0].iter()));
+        assert!(before.materials.iter().eq(after.materials.iter()));
+
+        bytes[4..8].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(
+            loaded.load_bytes(&bytes),
+            Err(StateError::UnsupportedVersion(4))
+        ));
+    }
+    #[test]
+    fn saving_keeps_the_path_regions_and_emitters() {
+        let rect = |x, y, width, height| CellRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let saved = world(32, 24);
+        {
+            let mut params = saved.params.lock().unwrap();
+            params.injection_path = vec![(1, 2), (3, -1), (30, 22)];
+            params.listening_region = Some(rect(4, 5, 6, 7));
+            params.charge_region = Some(rect(0, 0, 32, 24));
+            params.dense_region = Some(rect(10, 12, 3, 2));
+            let mut emitter = Emitter::new(rect(2, 3, 4, 5), 440.0, 0.5);
+            emitter.tone.waveform = Waveform::Saw;
+            emitter.tone.modulation = Modulation::Fm;
+            emitter.tone.mod_freq = 3.0;
+            emitter.tone.mod_depth = 0.25;
+            emitter.phase = 1.5;
+            params.emitters = vec![emitter, Emitter::new(rect(20, 20, 1, 1), 110.0, 1.0)];
+        }
+        let path = std::env::temp_dir()
+            .join(format!("kontawa-state-extras-test-{}", std::process::id()))
+            .join("extras.state");
+        saved.save(&path).unwrap();
+
+        let mut loaded = world(32, 24);
+        loaded.params.lock().unwrap().injection_region = Some(rect(1, 1, 1, 1));
+        loaded.load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let (before, after) = (saved.params.lock().unwrap(), loaded.params.lock().unwrap());
+        let config = |params: &SimParams| {
+            Config {
+                params: params.clone(),
+                ..Config::default()
+            }
+            .to_config_string()
+        };
+        assert_eq!(config(&before), config(&after));
+        assert_eq!(after.injection_path, before.injection_path);
+        assert_eq!(after.injection_region, None);
+        assert_eq!(after.listening_region, before.listening_region);
+        assert_eq!(after.charge_region, before.charge_region);
+        assert_eq!(after.dense_region, before.dense_region);
+        let emitters = |params: &SimParams| -> Vec<_> {
+            params
+                .emitters
+                .iter()
+                .map(|e| {
+                    let tone = e.tone;
+                    let shape = (tone.freq, tone.amp, tone.mod_freq, tone.mod_depth, e.phase);
+                    (e.region, tone.waveform, tone.modulation, shape)
+                })
+                .collect()
+        };
+        assert_eq!(emitters(&after), emitters(&before));
+    }
+}
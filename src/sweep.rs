@@ -0,0 +1,193 @@
+//! Headless parameter sweeps: run a fixed scene for every combination of
+//! parameters and save what it looks like.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::export::save_png;
+use kontawa::simulation::WaveParams;
+
+use crate::{SimParams, World};
+
+/// `steps` evenly spaced values from `min` to `max`, inclusive.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct SweepRange {
+    pub min: f32,
+    pub max: f32,
+    pub steps: usize,
+}
+impl SweepRange {
+    /// A range that only ever yields `value`.
+    pub fn single(value: f32) -> SweepRange {
+        SweepRange {
+            min: value,
+            max: value,
+            steps: 1,
+        }
+    }
+
+    /// Parse `MIN:MAX:STEPS`.
+    pub fn parse(s: &str) -> Result<SweepRange, String> {
+        let bad = || format!("expected MIN:MAX:STEPS, got '{s}'");
+        let mut parts = s.split(':');
+        let (Some(min), Some(max), Some(steps), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(bad());
+        };
+        let range = SweepRange {
+            min: min.parse().map_err(|_| bad())?,
+            max: max.parse().map_err(|_| bad())?,
+            steps: steps.parse().map_err(|_| bad())?,
+        };
+        if range.steps == 0 {
+            return Err(format!("'{s}' has no steps"));
+        }
+        Ok(range)
+    }
+
+    pub fn values(&self) -> Vec<f32> {
+        if self.steps == 1 {
+            return vec![self.min];
+        }
+        (0..self.steps)
+            .map(|i| self.min + (self.max - self.min) * i as f32 / (self.steps - 1) as f32)
+            .collect()
+    }
+}
+
+pub(crate) struct Sweep {
+    pub grad_alpha: SweepRange,
+    pub grad_damping: SweepRange,
+    /// Ticks to run each combination for.
+    pub ticks: u32,
+    pub out_dir: PathBuf,
+    /// Also tile every result into a single image.
+    pub contact_sheet: bool,
+    /// Grid size, in cells.
+    pub grid: (u32, u32),
+}
+
+impl Sweep {
+    /// Every `(grad_alpha, grad_damping)` pair, alpha-major.
+    pub fn combinations(&self) -> Vec<(f32, f32)> {
+        let dampings = self.grad_damping.values();
+        self.grad_alpha
+            .values()
+            .into_iter()
+            .flat_map(|a| dampings.iter().map(move |&d| (a, d)))
+            .collect()
+    }
+
+    /// Simulate each combination from the same starting scene, handing the
+    /// rendered frame to `save`. Stops at the first error `save` returns.
+    pub fn run_with<E>(
+        &self,
+        base: &SimParams,
+        mut save: impl FnMut(f32, f32, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for (grad_alpha, grad_damping) in self.combinations() {
+            let params = SimParams {
+                wave: WaveParams {
+                    grad_alpha,
+                    grad_damping,
+                    ..base.wave
+                },
+                ..base.clone()
+            };
+            let (width, height) = self.grid;
+            let params = Arc::new(Mutex::new(params));
+            let mut world = World::new(params, width as usize, height as usize);
+            world.seed_wave();
+            let frame = world.run_and_render(self.ticks);
+            save(grad_alpha, grad_damping, &frame)?;
+        }
+        Ok(())
+    }
+
+    /// Run the sweep, writing a PNG per combination into `out_dir`.
+    pub fn run(&self, base: &SimParams) -> Result<(), String> {
+        let cols = self.grad_damping.steps;
+        let rows = self.grad_alpha.steps;
+        let (w, h) = (self.grid.0 as usize, self.grid.1 as usize);
+        let mut sheet = if self.contact_sheet {
+            vec![0; w * cols * h * rows * 4]
+        } else {
+            Vec::new()
+        };
+
+        let mut n = 0;
+        self.run_with::<String>(base, |grad_alpha, grad_damping, frame| {
+            let name = format!("alpha{grad_alpha:.4}_damping{grad_damping:.4}.png");
+            let path = self.out_dir.join(name);
+            println!("{}", path.display());
+            save_png(&path, self.grid.0, self.grid.1, frame)?;
+
+            if self.contact_sheet {
+                let (col, row) = (n % cols, n / cols);
+                for (y, line) in frame.chunks_exact(w * 4).enumerate() {
+                    let start = ((row * h + y) * w * cols + col * w) * 4;
+                    sheet[start..start + w * 4].copy_from_slice(line);
+                }
+            }
+            n += 1;
+            Ok(())
+        })?;
+
+        if self.contact_sheet {
+            let path = self.out_dir.join("contact_sheet.png");
+            println!("{}", path.display());
+            save_png(&path, (w * cols) as u32, (h * rows) as u32, &sheet)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_renders_every_combination_once() {
+        let sweep = Sweep {
+            grad_alpha: SweepRange::parse("0.1:0.3:3").unwrap(),
+            grad_damping: SweepRange::parse("0.9:1:2").unwrap(),
+            ticks: 2,
+            out_dir: PathBuf::new(),
+            contact_sheet: false,
+            grid: (8, 6),
+        };
+        assert_eq!(sweep.combinations().len(), 6);
+
+        let mut rendered = Vec::new();
+        let done: Result<(), ()> =
+            sweep.run_with(&SimParams::default(), |alpha, damping, frame| {
+                assert_eq!(frame.len(), 8 * 6 * 4);
+                rendered.push((alpha, damping));
+                Ok(())
+            });
+        assert_eq!(done, Ok(()));
+        assert_eq!(rendered, sweep.combinations());
+        assert_eq!(rendered[1], (0.1, 1.0));
+    }
+    #[test]
+    fn a_failed_frame_stops_the_sweep_before_the_contact_sheet() {
+        let dir = std::env::temp_dir().join(format!("kontawa-sweep-{}", std::process::id()));
+        let sweep = Sweep {
+            grad_alpha: SweepRange::parse("0.1:0.3:3").unwrap(),
+            grad_damping: SweepRange::parse("0.9:1:2").unwrap(),
+            ticks: 2,
+            out_dir: dir.clone(),
+            contact_sheet: true,
+            grid: (8, 6),
+        };
+        // A directory where the second frame goes, so saving it fails.
+        std::fs::create_dir_all(dir.join("alpha0.1000_damping1.0000.png")).unwrap();
+
+        assert!(sweep.run(&SimParams::default()).is_err());
+        assert!(dir.join("alpha0.1000_damping0.9000.png").exists());
+        assert!(!dir.join("alpha0.2000_damping0.9000.png").exists());
+        assert!(!dir.join("contact_sheet.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Pacing the simulation against wall-clock time.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where the current time comes from, so pacing can be driven by something
+/// other than the wall clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real, monotonic clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share one time, so a test
+/// can keep a clone to advance while the code under test reads another.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Turns elapsed real time into a whole number of simulation ticks, carrying
+/// the fractional remainder over to the next frame.
+pub struct StepAccumulator {
+    ticks_per_second: f32,
+    pending: f32,
+    /// Never run more than this many ticks at once, so a long stall doesn't
+    /// snowball into ever-longer frames.
+    max_steps: u32,
+}
+
+/// Slowest and fastest playback speeds.
+pub const SPEED_RANGE: std::ops::RangeInclusive<f32> = 0.25..=4.0;
+
+impl StepAccumulator {
+    pub fn new(ticks_per_second: f32, max_steps: u32) -> StepAccumulator {
+        StepAccumulator {
+            ticks_per_second,
+            pending: 0.0,
+            max_steps,
+        }
+    }
+
+    /// Change the tick rate and cap from now on, keeping any fraction of a
+    /// tick already owed.
+    pub fn set_rate(&mut self, ticks_per_second: f32, max_steps: u32) {
+        self.ticks_per_second = ticks_per_second;
+        self.max_steps = max_steps;
+    }
+
+    /// Account for `elapsed` real time played back at `speed` times normal,
+    /// returning how many ticks to run now.
+    pub fn advance(&mut self, elapsed: Duration, speed: f32) -> u32 {
+        let speed = speed.clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end());
+        self.pending += elapsed.as_secs_f32() * self.ticks_per_second * speed;
+
+        let steps = self.pending.floor();
+        self.pending -= steps;
+
+        if steps > self.max_steps as f32 {
+            self.pending = 0.0;
+            self.max_steps
+        } else {
+            steps as u32
+        }
+    }
+}
+
+/// Pausing, and stepping one tick at a time while paused.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StepGate {
+    paused: bool,
+    /// Single steps asked for since the last frame.
+    requested: u32,
+}
+impl StepGate {
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.requested = 0;
+    }
+
+    /// Ask for one more tick while paused. Does nothing while running.
+    pub fn request_step(&mut self) {
+        if self.paused {
+            self.requested += 1;
+        }
+    }
+
+    /// How many ticks to run this frame, given the `running` ticks it would
+    /// get if it weren't paused.
+    pub fn steps(&mut self, running: u32) -> u32 {
+        if self.paused {
+            std::mem::take(&mut self.requested)
+        } else {
+            running
+        }
+    }
+}
+
+/// Lets an action through at most once per interval. Whatever piles up in
+/// between is the caller's to coalesce (usually by only keeping the latest).
+pub struct RateLimiter {
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter { last: None }
+    }
+
+    /// Whether to act at `now`, given at most `max_rate` actions per second.
+    /// A rate of zero or less means no limit.
+    pub fn ready(&mut self, now: Instant, max_rate: f32) -> bool {
+        let ready = match self.last {
+            Some(last) if max_rate > 0.0 => {
+                now.saturating_duration_since(last).as_secs_f32() >= max_rate.recip()
+            }
+            _ => true,
+        };
+        if ready {
+            self.last = Some(now);
+        }
+        ready
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_speed_runs_half_the_ticks() {
+        let ticks_at = |speed| {
+            let mut acc = StepAccumulator::new(180.0, 100);
+            (0..120)
+                .map(|_| acc.advance(Duration::from_secs_f32(1.0 / 60.0), speed))
+                .sum::<u32>()
+        };
+        let (full, half) = (ticks_at(1.0), ticks_at(0.5));
+        assert!((full as i32 - 360).abs() <= 1);
+        assert!((half as i32 - 180).abs() <= 1);
+    }
+
+    #[test]
+    fn rate_limiter_forwards_only_the_latest_per_interval() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        let mut pending = Vec::new();
+        let mut forwarded = Vec::new();
+        // A publish every millisecond for a tenth of a second, at most 20
+        // forwarded per second: one every 50 ms.
+        for ms in 0..100 {
+            pending.push(ms);
+            let now = start + Duration::from_millis(ms);
+            if limiter.ready(now, 20.0) {
+                forwarded.extend(pending.last().copied());
+                pending.clear();
+            }
+        }
+        assert_eq!(forwarded, [0, 50]);
+        assert_eq!(pending.len(), 49);
+
+        let mut unlimited = RateLimiter::new();
+        assert!(unlimited.ready(start, 0.0) && unlimited.ready(start, 0.0));
+    }
+
+    #[test]
+    fn accumulator_runs_the_ticks_the_elapsed_time_is_worth() {
+        let mut acc = StepAccumulator::new(60.0, 100);
+        assert_eq!(acc.advance(Duration::from_millis(500), 1.0), 30);
+        // A quarter of a tick at a time, so every fourth frame gets one.
+        let frames: Vec<u32> = (0..8)
+            .map(|_| acc.advance(Duration::from_secs_f64(1.0 / 240.0), 1.0))
+            .collect();
+        assert_eq!(frames.iter().sum::<u32>(), 2);
+        assert!(frames.iter().all(|&steps| steps <= 1));
+        // A long stall runs the cap and forgets the rest.
+        assert_eq!(acc.advance(Duration::from_secs(10), 1.0), 100);
+        assert_eq!(acc.advance(Duration::ZERO, 1.0), 0);
+        acc.set_rate(120.0, 100);
+        assert_eq!(acc.advance(Duration::from_millis(250), 1.0), 30);
+    }
+
+    #[test]
+    fn paused_gate_runs_exactly_the_steps_asked_for() {
+        let mut gate = StepGate::default();
+        gate.request_step();
+        assert_eq!(gate.steps(3), 3, "requests while running are ignored");
+
+        gate.toggle_pause();
+        assert!(gate.paused());
+        assert_eq!(gate.steps(3), 0);
+        for _ in 0..5 {
+            gate.request_step();
+        }
+        assert_eq!(gate.steps(3), 5);
+        assert_eq!(gate.steps(3), 0, "each request is used up once");
+
+        gate.request_step();
+        gate.toggle_pause();
+        gate.toggle_pause();
+        assert_eq!(gate.steps(3), 0, "unpausing drops leftover requests");
+    }
+}
@@ -0,0 +1,77 @@
+//! A scrolling history of the injected spectrum.
+
+use std::collections::VecDeque;
+
+use crate::render;
+
+/// The last `capacity` spectra, oldest first.
+pub struct Waterfall {
+    rows: VecDeque<Vec<f32>>,
+    capacity: usize,
+}
+
+impl Waterfall {
+    pub fn new(capacity: usize) -> Waterfall {
+        Waterfall {
+            rows: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add the newest spectrum, dropping the oldest if full.
+    pub fn push(&mut self, levels: Vec<f32>) {
+        if self.rows.len() == self.capacity {
+            self.rows.pop_front();
+        }
+        self.rows.push_back(levels);
+    }
+
+    /// Width (frequency bins) and height (time) of the picture.
+    pub fn dimensions(&self) -> (usize, usize) {
+        let width = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        (width, self.rows.len())
+    }
+
+    /// Render as RGBA, one column per bin and one row per spectrum, newest at
+    /// the bottom, in the same colors as pressure in the field, with
+    /// `non_finite` for NaN and infinities.
+    pub fn to_rgba(&self, non_finite: [u8; 3]) -> Vec<u8> {
+        let (width, _) = self.dimensions();
+        let mut out = Vec::with_capacity(width * self.rows.len() * 4);
+        for row in &self.rows {
+            for x in 0..width {
+                let level = row.get(x).copied().unwrap_or(0.0);
+                out.extend_from_slice(&render::pressure_rgba(level, 0, non_finite));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picture_is_bins_by_history_with_the_peak_in_place() {
+        let mut waterfall = Waterfall::new(5);
+        for t in 0..7 {
+            let mut levels = vec![0.1; 8];
+            if t == 4 {
+                levels[3] = 1.0;
+            }
+            waterfall.push(levels);
+        }
+        assert_eq!(waterfall.dimensions(), (8, 5));
+
+        let rgba = waterfall.to_rgba(render::NON_FINITE_RGB);
+        assert_eq!(rgba.len(), 8 * 5 * 4);
+        let brightest = rgba
+            .chunks_exact(4)
+            .enumerate()
+            .max_by_key(|(_, pixel)| pixel[0])
+            .map(|(i, _)| (i % 8, i / 8));
+        // The two oldest spectra have scrolled off the top.
+        assert_eq!(brightest, Some((3, 2)));
+    }
+}
@@ -0,0 +1,119 @@
+//! Reading `.wav` files, for driving the simulation from a recording
+//! instead of a live input.
+
+use std::path::Path;
+
+/// A decoded recording, downmixed to mono.
+pub(crate) struct Wav {
+    pub sample_rate: u32,
+    /// Samples in `[-1, 1]`.
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn load(path: &Path) -> Result<Wav, String> {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+        Wav::parse(&bytes).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Decode a RIFF WAVE file holding 8, 16, 24 or 32-bit integer PCM, or
+    /// 32-bit float.
+    pub fn parse(bytes: &[u8]) -> Result<Wav, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a WAV file".to_string());
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let id = &rest[0..4];
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let body = rest.get(8..8 + len).ok_or("truncated chunk")?;
+            match id {
+                b"fmt " => format = Some(Format::parse(body)?),
+                b"data" => data = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to an even length.
+            rest = rest.get(8 + len + len % 2..).unwrap_or(&[]);
+        }
+        let format = format.ok_or("no fmt chunk")?;
+        let data = data.ok_or("no data chunk")?;
+
+        let width = format.bits as usize / 8;
+        let frame = width * format.channels as usize;
+        let samples = data
+            .chunks_exact(frame)
+            .map(|frame| {
+                let sum: f32 = frame.chunks_exact(width).map(|s| format.decode(s)).sum();
+                sum / format.channels as f32
+            })
+            .collect();
+        Ok(Wav {
+            sample_rate: format.sample_rate,
+            samples,
+        })
+    }
+}
+
+struct Format {
+    float: bool,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+impl Format {
+    const PCM: u16 = 1;
+    const FLOAT: u16 = 3;
+    const EXTENSIBLE: u16 = 0xfffe;
+
+    fn parse(body: &[u8]) -> Result<Format, String> {
+        if body.len() < 16 {
+            return Err("fmt chunk too short".to_string());
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+        let mut tag = u16_at(0);
+        if tag == Format::EXTENSIBLE && body.len() >= 26 {
+            // The real format is the start of the subformat GUID.
+            tag = u16_at(24);
+        }
+        let format = Format {
+            float: tag == Format::FLOAT,
+            channels: u16_at(2),
+            sample_rate: u32::from_le_bytes(body[4..8].try_into().unwrap()),
+            bits: u16_at(14),
+        };
+
+        let supported = match tag {
+            Format::PCM => matches!(format.bits, 8 | 16 | 24 | 32),
+            Format::FLOAT => format.bits == 32,
+            _ => false,
+        };
+        if !supported {
+            return Err(format!(
+                "unsupported format {tag} with {} bits",
+                format.bits
+            ));
+        }
+        if format.channels == 0 {
+            return Err("no channels".to_string());
+        }
+        Ok(format)
+    }
+
+    /// One little-endian sample as an `f32` in `[-1, 1]`.
+    fn decode(&self, sample: &[u8]) -> f32 {
+        match (self.float, sample) {
+            (true, &[a, b, c, d]) => f32::from_le_bytes([a, b, c, d]),
+            // 8-bit PCM alone is unsigned.
+            (false, &[a]) => (a as f32 - 128.0) / 128.0,
+            (false, &[a, b]) => i16::from_le_bytes([a, b]) as f32 / 32768.0,
+            (false, &[a, b, c]) => (i32::from_le_bytes([0, a, b, c]) >> 8) as f32 / 8388608.0,
+            (false, &[a, b, c, d]) => i32::from_le_bytes([a, b, c, d]) as f32 / 2147483648.0,
+            _ => 0.0,
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Remembering where the window was between runs.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Outer position and inner size of the window, in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowGeometry {
+    /// Where the geometry is kept: `$XDG_CONFIG_HOME/kontawa/window.conf`,
+    /// falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_dir.join("kontawa").join("window.conf"))
+    }
+
+    pub fn to_config_string(self) -> String {
+        let mut out = String::from("# kontawa window geometry\n");
+        for (key, value) in [
+            ("x", self.x.to_string()),
+            ("y", self.y.to_string()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+        ] {
+            writeln!(out, "{key} = {value}").unwrap();
+        }
+        out
+    }
+
+    /// Parse what `to_config_string` wrote. Every key must be present.
+    pub fn parse(text: &str) -> Result<WindowGeometry, String> {
+        let (mut x, mut y, mut width, mut height) = (None, None, None, None);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'key = value', got '{line}'"))?;
+            let (key, value) = (key.trim(), value.trim());
+            let bad = || format!("bad value for {key}: '{value}'");
+            match key {
+                "x" => x = Some(value.parse().map_err(|_| bad())?),
+                "y" => y = Some(value.parse().map_err(|_| bad())?),
+                "width" => width = Some(value.parse().map_err(|_| bad())?),
+                "height" => height = Some(value.parse().map_err(|_| bad())?),
+                _ => return Err(format!("unknown key '{key}'")),
+            }
+        }
+        match (x, y, width, height) {
+            (Some(x), Some(y), Some(width), Some(height)) => Ok(WindowGeometry {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err("missing keys".to_string()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<WindowGeometry, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+        WindowGeometry::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("couldn't create {}: {e}", dir.display()))?;
+        }
+        std::fs::write(path, self.to_config_string())
+            .map_err(|e| format!("couldn't write {}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_round_trips_through_the_file() {
+        let geometry = WindowGeometry {
+            x: -40,
+            y: 120,
+            width: 1024,
+            height: 768,
+        };
+        let path = std::env::temp_dir()
+            .join(format!("kontawa-window-test-{}", std::process::id()))
+            .join("window.conf");
+        geometry.save(&path).unwrap();
+        let loaded = WindowGeometry::load(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(loaded, Ok(geometry));
+
+        assert!(WindowGeometry::parse("x = 1\ny = 2\nwidth = 3").is_err());
+        assert!(WindowGeometry::parse("x = 1\ny = 2\nwidth = 3\nheight = -4").is_err());
+    }
+}